@@ -5,12 +5,14 @@ use emule_proto as remule;
 use fmt_extra::Hs;
 use humantime::parse_duration;
 use remule::udp_proto::BootstrapRespContact;
+use remule::KadId;
 use sqlx::Executor;
 use std::io::Read;
 use std::io::{self, IsTerminal};
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
@@ -70,12 +72,14 @@ const STORE_V1: &str = "remule/collect/1";
 const STORE_V2: &str = "remule/collect/2";
 const STORE_V3: &str = "remule/collect/3";
 const STORE_V4: &str = "remule/collect/4";
+const STORE_V5: &str = "remule/collect/5";
+const STORE_V6: &str = "remule/collect/6";
 
-const CURRENT_STORE_VERSION: &str = STORE_V4;
+const CURRENT_STORE_VERSION: &str = STORE_V6;
 
 #[derive(Debug, Clone, Copy)]
 struct Peer {
-    id: u128,
+    id: KadId,
     ip: IpAddr,
     udp_port: u16,
 }
@@ -110,6 +114,10 @@ struct Contact {
     kad_udp_key_key: Option<u32>,
     // FIXME: figure out what verified means in detail
     verified: Option<u8>,
+
+    /// present only in hellos that include `TAG_KADMISCOPTIONS`
+    udp_firewalled: Option<bool>,
+    tcp_firewalled: Option<bool>,
 }
 
 #[derive(Debug)]
@@ -149,6 +157,8 @@ impl From<remule::nodes::Contact> for Contact {
             kad_udp_key_key: v.kad_udp_key.map(|x| x.0),
             kad_udp_key_ip: v.kad_udp_key.map(|x| x.1),
             verified: v.verified,
+            udp_firewalled: None,
+            tcp_firewalled: None,
         }
     }
 }
@@ -169,6 +179,8 @@ impl<'a> From<BootstrapRespContact<'a>> for Contact {
             kad_udp_key_ip: None,
             kad_udp_key_key: None,
             verified: None,
+            udp_firewalled: None,
+            tcp_firewalled: None,
         }
     }
 }
@@ -195,8 +207,7 @@ impl UnixMillis for std::time::SystemTime {
 
     fn from_unix_millis(ts: i64) -> Self {
         let d = std::time::Duration::from_millis(ts.try_into().unwrap());
-        let ts = std::time::UNIX_EPOCH + d;
-        ts
+        std::time::UNIX_EPOCH + d
     }
 }
 
@@ -205,7 +216,7 @@ impl Store {
         let db = sqlx::sqlite::SqlitePoolOptions::new()
             .max_connections(1)
             .connect_with(
-                sqlx::sqlite::SqliteConnectOptions::from_str(&db_uri)
+                sqlx::sqlite::SqliteConnectOptions::from_str(db_uri)
                     .map_err(|source| Error::DbPoolOpen {
                         source,
                         uri: db_uri.to_owned(),
@@ -359,6 +370,59 @@ impl Store {
 
                             v = new_version.to_owned();
                         }
+                        STORE_V4 => {
+                            let new_version = STORE_V5;
+                            executed_update = true;
+                            c.execute(
+                                "
+                                ALTER TABLE report_contact
+                                ADD COLUMN udp_firewalled INTEGER;
+
+                                ALTER TABLE report_contact
+                                ADD COLUMN tcp_firewalled INTEGER;
+                                ",
+                            )
+                            .await
+                            .map_err(|source| Error::DbUpgrade {
+                                new_version,
+                                old_version: v.clone(),
+                                source,
+                            })?;
+
+                            v = new_version.to_owned();
+                        }
+                        STORE_V5 => {
+                            let new_version = STORE_V6;
+                            executed_update = true;
+                            // kad ids were stored as the decimal value of their little endian
+                            // wire bytes, switch to the hex emule displays
+                            let peers: Vec<(i64, String)> =
+                                sqlx::query_as("SELECT id, kad_id FROM peer")
+                                    .fetch_all(&mut *c)
+                                    .await
+                                    .map_err(|source| Error::DbUpgrade {
+                                        new_version,
+                                        old_version: v.clone(),
+                                        source,
+                                    })?;
+                            for (id, kad_id) in peers {
+                                let kad_id = kad_id.parse::<u128>().with_context(|| {
+                                    format!("peer {} has invalid kad_id {:?}", id, kad_id)
+                                })?;
+                                sqlx::query("UPDATE peer SET kad_id = $1 WHERE id = $2")
+                                    .bind(KadId::from_wire(kad_id.to_le_bytes()).to_string())
+                                    .bind(id)
+                                    .execute(&mut *c)
+                                    .await
+                                    .map_err(|source| Error::DbUpgrade {
+                                        new_version,
+                                        old_version: v.clone(),
+                                        source,
+                                    })?;
+                            }
+
+                            v = new_version.to_owned();
+                        }
                         _ => {
                             return Err(Error::DbUnknownVersion { version: v, ts });
                         }
@@ -409,6 +473,9 @@ impl Store {
                         contact_version INTEGER,
                         verified INTEGER, 
 
+                        udp_firewalled INTEGER,
+                        tcp_firewalled INTEGER,
+
                         FOREIGN KEY(report_id) REFERENCES report(id),
                         FOREIGN KEY(reported_peer_id) REFERENCES peer(id)
                     );
@@ -510,8 +577,8 @@ impl Store {
         let (ct, peer) = self.insert_peer(&contact.peer).await?;
 
         let insert_res = sqlx::query(
-            "INSERT INTO report_contact (report_id, reported_peer_id, tcp_port, contact_version, verified)
-            SELECT $1, $2, $3, $4, $5
+            "INSERT INTO report_contact (report_id, reported_peer_id, tcp_port, contact_version, verified, udp_firewalled, tcp_firewalled)
+            SELECT $1, $2, $3, $4, $5, $6, $7
             ",
         )
         .bind(report.id)
@@ -519,6 +586,8 @@ impl Store {
         .bind(contact.tcp_port)
         .bind(contact.version)
         .bind(contact.verified)
+        .bind(contact.udp_firewalled)
+        .bind(contact.tcp_firewalled)
         .execute(&self.db)
         .await
         .map_err(|source| Error::DbInsertPeer { source })?;
@@ -557,6 +626,66 @@ impl Store {
         }
     }
 
+    /// The peer we know at `addr`, for replies that don't identify their sender
+    pub async fn find_peer_by_addr(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<Option<(PeerStoreId, KadId)>, Error> {
+        match sqlx::query_as::<_, (i64, String)>(
+            "SELECT id, kad_id FROM peer WHERE ip = $1 AND udp_port = $2 ORDER BY last_send_time DESC LIMIT 1",
+        )
+        .bind(addr.ip().to_string())
+        .bind(addr.port())
+        .fetch_one(&self.db)
+        .await
+        {
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(Error::DbFetchPeers { source: e }),
+            Ok((id, kad_id)) => Ok(Some((PeerStoreId { id }, kad_id.parse().unwrap()))),
+        }
+    }
+
+    /// Ipv4 peers that have answered us, with the tcp port and version they were last reported
+    /// with, most recently reported first
+    pub async fn answered_contacts(
+        &self,
+        limit: u32,
+    ) -> Result<Vec<remule::nodes::Contact>, Error> {
+        let rows = sqlx::query_as::<_, (String, String, u16, u16, u8, i64)>(
+            "SELECT p.kad_id, p.ip, p.udp_port, rc.tcp_port, rc.contact_version, MAX(r.recv_time)
+            FROM peer p
+            JOIN report_contact rc ON rc.reported_peer_id = p.id
+            JOIN report r ON r.id = rc.report_id
+            WHERE rc.tcp_port IS NOT NULL AND rc.contact_version IS NOT NULL
+                AND p.ip NOT LIKE '%:%'
+                AND EXISTS (SELECT 1 FROM report s WHERE s.source_peer = p.id)
+            GROUP BY p.id
+            ORDER BY MAX(r.recv_time) DESC
+            LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await
+        .map_err(|source| Error::DbFetchPeers { source })?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(kad_id, ip, udp_port, tcp_port, contact_version, _)| remule::nodes::Contact {
+                    id: kad_id.parse().unwrap(),
+                    ip: ip.parse().unwrap(),
+                    udp_port,
+                    tcp_port,
+                    contact_version: Some(contact_version),
+                    by_type: None,
+                    kad_udp_key: None,
+                    // they answered us from this address
+                    verified: Some(1),
+                },
+            )
+            .collect())
+    }
+
     async fn mark_peer_sent(&self, peer: PeerStoreId) -> Result<(), Error> {
         sqlx::query("UPDATE peer SET last_send_time = $1 WHERE id = $2")
             .bind(SystemTime::now().as_unix_millis())
@@ -570,7 +699,7 @@ impl Store {
 
 struct PeerStoreInfo {
     id: PeerStoreId,
-    _kad_id: u128,
+    _kad_id: KadId,
     addr: SocketAddr,
 }
 
@@ -578,18 +707,36 @@ struct PeerStoreInfo {
 struct KadShared {
     socket: net::UdpSocket,
     store: Store,
+
+    // used by peers to obfuscate packets sent to us
+    kad_id: KadId,
+    user_hash: [u8; 16],
+    // udp verify keys we hand out are derived from this
+    udp_key: u32,
+
+    // packets dropped for exceeding our `DecodeLimits`
+    rejected: AtomicU64,
 }
 
 impl KadShared {
     async fn from_addr<A: net::ToSocketAddrs>(addrs: A, store: Store) -> Result<Self, io::Error> {
         let socket = net::UdpSocket::bind(addrs).await?;
-        Ok(Self { socket, store })
+        Ok(Self {
+            socket,
+            store,
+            kad_id: KadId::random(&mut rand::rng()),
+            user_hash: rand::random(),
+            udp_key: rand::random(),
+            rejected: AtomicU64::new(0),
+        })
     }
 }
 
 #[derive(Debug, Clone)]
 struct Kad {
     send_wait: Duration,
+    /// also send kad v0 bootstrap requests, which only legacy clients answer
+    probe_legacy: bool,
 
     shared: Arc<KadShared>,
 }
@@ -599,9 +746,11 @@ impl Kad {
         addrs: A,
         store: Store,
         send_wait: Duration,
+        probe_legacy: bool,
     ) -> Result<Self, io::Error> {
         let kad = Self {
             send_wait,
+            probe_legacy,
             shared: Arc::new(KadShared::from_addr(addrs, store).await?),
         };
 
@@ -648,12 +797,118 @@ impl Kad {
                         continue;
                     }
                 }
+                if self.probe_legacy {
+                    self.send_bootstrap_req_v0(peer.addr).await?;
+                }
                 self.shared.store.mark_peer_sent(peer.id).await?;
                 timeout_bootstrap.tick().await;
             }
         }
     }
 
+    async fn send_bootstrap_req_v0(&self, addr: SocketAddr) -> Result<(), anyhow::Error> {
+        let mut out_buf = Vec::new();
+        remule::udp_proto::OperationBuf::BootstrapReqV0(remule::udp_proto::ContactBuf {
+            client_id: self.shared.kad_id,
+            ip_addr: std::net::Ipv4Addr::UNSPECIFIED,
+            udp_port: self.shared.socket.local_addr()?.port(),
+            tcp_port: 0,
+            version: 0,
+        })
+        .write_to(&mut out_buf)?;
+        self.shared.socket.send_to(&out_buf[..], addr).await?;
+        Ok(())
+    }
+
+    /// kad v0 bootstrap responses don't identify their sender, so we rely on having sent the
+    /// request to a known peer
+    async fn handle_bootstrap_res_v0(
+        &self,
+        recv_time: std::time::SystemTime,
+        rx_addr: SocketAddr,
+        res: remule::udp_proto::BootstrapResV0<'_>,
+        was_packed: bool,
+        packet_size: usize,
+    ) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let Some((peer_sid, kad_id)) = self.shared.store.find_peer_by_addr(rx_addr).await? else {
+            event!(
+                Level::INFO,
+                "{}: v0 bootstrap res from unknown peer",
+                rx_addr
+            );
+            return Ok(());
+        };
+
+        let report = self
+            .shared
+            .store
+            .insert_report(peer_sid, recv_time, Some(packet_size), Some(was_packed))
+            .await?;
+
+        let self_contact = Contact {
+            peer: Peer {
+                id: kad_id,
+                ip: rx_addr.ip(),
+                udp_port: rx_addr.port(),
+            },
+            tcp_port: None,
+            version: Some(0),
+            kad_udp_key_ip: None,
+            kad_udp_key_key: None,
+            verified: None,
+            udp_firewalled: None,
+            tcp_firewalled: None,
+        };
+        event!(Level::INFO, "{}: legacy node: {:?}", rx_addr, self_contact);
+        self.shared
+            .store
+            .insert_report_contact(report, &self_contact, ContactSource::ReportedByRemote)
+            .await?;
+
+        for bs_node in res.contacts()? {
+            // the last byte is a contact type, not a version
+            let n = Contact {
+                version: None,
+                ..bs_node.into()
+            };
+            self.shared
+                .store
+                .insert_report_contact(report, &n, ContactSource::ReportedByBootstrap)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_hello_v0(
+        &self,
+        recv_time: std::time::SystemTime,
+        rx_addr: SocketAddr,
+        hello: &remule::udp_proto::ResContact<'_>,
+        was_packed: bool,
+        packet_size: usize,
+    ) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let self_contact = Contact {
+            peer: Peer {
+                id: hello.client_id(),
+                ip: rx_addr.ip(),
+                udp_port: hello.udp_port(),
+            },
+            tcp_port: Some(hello.tcp_port()),
+            version: Some(0),
+            kad_udp_key_ip: None,
+            kad_udp_key_key: None,
+            verified: None,
+            udp_firewalled: None,
+            tcp_firewalled: None,
+        };
+
+        event!(Level::INFO, "{}: legacy node: {:?}", rx_addr, self_contact);
+
+        self.insert_self_report(recv_time, rx_addr, &self_contact, was_packed, packet_size)
+            .await
+    }
+
     async fn handle_bootstrap_resp(
         &self,
         _ts: std::time::Instant,
@@ -706,6 +961,8 @@ impl Kad {
             kad_udp_key_ip: None,
             kad_udp_key_key: None,
             verified: None,
+            udp_firewalled: None,
+            tcp_firewalled: None,
         };
         let self_report_is_new = self
             .shared
@@ -758,6 +1015,117 @@ impl Kad {
         Ok(())
     }
 
+    /// Record what a remote told us about itself
+    async fn insert_self_report(
+        &self,
+        recv_time: std::time::SystemTime,
+        rx_addr: SocketAddr,
+        self_contact: &Contact,
+        was_packed: bool,
+        packet_size: usize,
+    ) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let peer = Peer {
+            id: self_contact.peer.id,
+            ip: rx_addr.ip(),
+            udp_port: rx_addr.port(),
+        };
+
+        let (_, peer_sid) = self.shared.store.insert_peer(&peer).await?;
+        let report = self
+            .shared
+            .store
+            .insert_report(peer_sid, recv_time, Some(packet_size), Some(was_packed))
+            .await?;
+
+        self.shared
+            .store
+            .insert_report_contact(report, self_contact, ContactSource::ReportedByRemote)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn handle_hello(
+        &self,
+        recv_time: std::time::SystemTime,
+        rx_addr: SocketAddr,
+        hello: &remule::udp_proto::Hello<'_>,
+        was_packed: bool,
+        packet_size: usize,
+    ) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let misc_options = hello.misc_options();
+        let self_contact = Contact {
+            peer: Peer {
+                id: hello.client_id(),
+                ip: rx_addr.ip(),
+                udp_port: hello.source_udp_port().unwrap_or(rx_addr.port()),
+            },
+            tcp_port: Some(hello.tcp_port()),
+            version: Some(hello.version()),
+            kad_udp_key_ip: None,
+            kad_udp_key_key: None,
+            verified: None,
+            udp_firewalled: misc_options.map(|m| m.udp_firewalled),
+            tcp_firewalled: misc_options.map(|m| m.tcp_firewalled),
+        };
+
+        event!(Level::DEBUG, "{}: hello: {:?}", rx_addr, self_contact);
+
+        self.insert_self_report(recv_time, rx_addr, &self_contact, was_packed, packet_size)
+            .await
+    }
+
+    /// Only firewalled (LowID) nodes look for buddies
+    async fn handle_find_buddy_req(
+        &self,
+        recv_time: std::time::SystemTime,
+        rx_addr: SocketAddr,
+        req: &remule::udp_proto::FindBuddy<'_>,
+        was_packed: bool,
+        packet_size: usize,
+    ) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let self_contact = Contact {
+            peer: Peer {
+                id: req.requester_id(),
+                ip: rx_addr.ip(),
+                udp_port: rx_addr.port(),
+            },
+            tcp_port: Some(req.client_port()),
+            version: None,
+            kad_udp_key_ip: None,
+            kad_udp_key_key: None,
+            verified: None,
+            udp_firewalled: None,
+            tcp_firewalled: Some(true),
+        };
+
+        event!(Level::DEBUG, "{}: find buddy: {:?}", rx_addr, self_contact);
+
+        self.insert_self_report(recv_time, rx_addr, &self_contact, was_packed, packet_size)
+            .await
+    }
+
+    /// Count decode results that were rejected by `DecodeLimits`, passing them on unchanged
+    fn count_rejected<T>(
+        &self,
+        rx_addr: SocketAddr,
+        r: Result<T, remule::udp_proto::Error>,
+    ) -> Result<T, remule::udp_proto::Error> {
+        if let Err(e) = &r {
+            if e.is_limit_exceeded() {
+                let rejected = self.shared.rejected.fetch_add(1, Ordering::Relaxed) + 1;
+                event!(
+                    Level::WARN,
+                    "{}: rejected packet ({} so far): {}",
+                    rx_addr,
+                    rejected,
+                    e
+                );
+            }
+        }
+        r
+    }
+
     async fn handle_packet(
         &self,
         ts: std::time::Instant,
@@ -772,9 +1140,32 @@ impl Kad {
             Hs(rx_data)
         );
 
-        let packet = remule::udp_proto::Packet::from_slice(rx_data)?;
-        match packet.kind()? {
-            remule::udp_proto::Kind::Kad(kad_packet) => match kad_packet.operation() {
+        let mut packet = remule::udp_proto::Packet::from_slice(rx_data)?;
+        if let IpAddr::V4(source_ip) = rx_addr.ip() {
+            let keys = remule::udp_proto::Keys {
+                kad_id: self.shared.kad_id,
+                user_hash: &self.shared.user_hash,
+                source_ip,
+                source_key: Some(remule::obfuscate::udp_verify_key(
+                    self.shared.udp_key,
+                    source_ip,
+                )),
+            };
+            if let Some(deobfuscated) = self.count_rejected(rx_addr, packet.decrypt(&keys))? {
+                event!(
+                    Level::DEBUG,
+                    "{}: obfuscated packet: {:?}, valid receiver key: {}",
+                    rx_addr,
+                    deobfuscated,
+                    deobfuscated.is_receiver_key_valid(self.shared.udp_key, source_ip)
+                );
+            }
+        }
+
+        match self.count_rejected(rx_addr, packet.kind())? {
+            remule::udp_proto::Kind::Kad(kad_packet) => match self
+                .count_rejected(rx_addr, kad_packet.try_operation())?
+            {
                 Some(remule::udp_proto::Operation::BootstrapResp(bootstrap_resp)) => {
                     // XXX: consider how this async affects things.
                     self.handle_bootstrap_resp(
@@ -787,6 +1178,40 @@ impl Kad {
                     )
                     .await
                 }
+                Some(
+                    remule::udp_proto::Operation::HelloReq(hello)
+                    | remule::udp_proto::Operation::HelloRes(hello),
+                ) => {
+                    self.handle_hello(s_time, rx_addr, &hello, packet.is_packed(), rx_data.len())
+                        .await
+                }
+                Some(remule::udp_proto::Operation::BootstrapResV0(res)) => {
+                    self.handle_bootstrap_res_v0(
+                        s_time,
+                        rx_addr,
+                        res,
+                        packet.is_packed(),
+                        rx_data.len(),
+                    )
+                    .await
+                }
+                Some(
+                    remule::udp_proto::Operation::HelloReqV0(hello)
+                    | remule::udp_proto::Operation::HelloResV0(hello),
+                ) => {
+                    self.handle_hello_v0(s_time, rx_addr, &hello, packet.is_packed(), rx_data.len())
+                        .await
+                }
+                Some(remule::udp_proto::Operation::FindBuddyReqV1(req)) => {
+                    self.handle_find_buddy_req(
+                        s_time,
+                        rx_addr,
+                        &req,
+                        packet.is_packed(),
+                        rx_data.len(),
+                    )
+                    .await
+                }
                 kad_operation => {
                     event!(
                         Level::WARN,
@@ -797,6 +1222,21 @@ impl Kad {
                     Ok(())
                 }
             },
+            // we don't take part in file transfers, so these are only of passing interest
+            remule::udp_proto::Kind::Emule(emule_packet) => {
+                event!(
+                    Level::DEBUG,
+                    "{}: emule op: {:?}",
+                    rx_addr,
+                    emule_packet.operation()
+                );
+                Ok(())
+            }
+            remule::udp_proto::Kind::Server(server_packet) => {
+                let op = self.count_rejected(rx_addr, server_packet.try_operation())?;
+                event!(Level::DEBUG, "{}: server op: {:?}", rx_addr, op);
+                Ok(())
+            }
         }
     }
 
@@ -880,6 +1320,19 @@ enum Action {
     /// Take a nodes.dat and feed it's content into our database
     FeedNodesDat { nodes_dat_path: PathBuf },
 
+    /// Write the peers that most recently answered us to a nodes.dat
+    WriteNodesDat {
+        nodes_dat_path: PathBuf,
+
+        /// Write emule's bootstrap edition, used to distribute a larger set of contacts
+        #[arg(long = "bootstrap")]
+        bootstrap: bool,
+
+        /// Most contacts to write, 200 by default, or 500 with `--bootstrap`
+        #[arg(long = "count")]
+        count: Option<u32>,
+    },
+
     /// Use known peers in the database to collect more peers
     Collect {
         bind_addr: SocketAddr,
@@ -889,6 +1342,10 @@ enum Action {
             value_parser = parse_duration
         )]
         send_wait: Duration,
+
+        /// Also send kad v0 bootstrap requests to find legacy clients
+        #[arg(long = "probe-legacy")]
+        probe_legacy: bool,
     },
 }
 
@@ -925,7 +1382,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
             let mut f_nodes = std::fs::File::open(nodes_dat_path)?;
             let mut b = Vec::default();
             f_nodes.read_to_end(&mut b)?;
-            let nodes = remule::nodes::parse(&mut b)?.contacts.into_iter();
+            let nodes = remule::nodes::parse(&b)?.contacts.into_iter();
 
             // FIXME: generalize report sources so we can have a report that represents this
             // nodes.dat file import
@@ -938,11 +1395,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
 
             Ok(())
         }
+        Action::WriteNodesDat {
+            nodes_dat_path,
+            bootstrap,
+            count,
+        } => {
+            let count = count.unwrap_or(if bootstrap { 500 } else { 200 });
+            let nodes = remule::nodes::Nodes {
+                version: if bootstrap { 3 } else { 2 },
+                is_bootstrap: bootstrap,
+                contacts: store.answered_contacts(count).await?,
+            };
+
+            let mut b = Vec::new();
+            nodes.write_to(&mut b)?;
+            std::fs::write(nodes_dat_path, b)?;
+
+            event!(Level::INFO, "Wrote {} peers", nodes.contacts.len());
+
+            Ok(())
+        }
         Action::Collect {
             bind_addr,
             send_wait,
+            probe_legacy,
         } => {
-            let kad = Kad::from_addr(bind_addr, store, send_wait).await?;
+            let kad = Kad::from_addr(bind_addr, store, send_wait, probe_legacy).await?;
             kad.run().await;
             Ok(())
         }
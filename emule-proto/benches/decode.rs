@@ -0,0 +1,72 @@
+//! Decoding cost of the udp traffic of a crawl, with and without a reused `DecodeContext`
+//!
+//! `crawl.pcap` holds one bootstrap and the hellos, pings, node lookups and a keyword publish
+//! that follow it, using contacts from the emule 0.50a nodes.dat sample. As in real traffic, only
+//! the publish is large and compressible enough to be packed.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use emule_proto::pcap;
+use emule_proto::udp_proto::*;
+use std::hint::black_box;
+
+fn packets() -> Vec<&'static [u8]> {
+    let packets: Vec<_> = pcap::datagrams(include_bytes!("crawl.pcap"))
+        .unwrap()
+        .map(|d| d.unwrap().payload)
+        .collect();
+    assert!(packets
+        .iter()
+        .any(|p| Packet::from_slice(p).unwrap().is_packed()));
+    packets
+}
+
+/// Touch the decoded operation so both paths do the same work
+fn walk(kind: Kind<'_>) -> usize {
+    match kind {
+        Kind::Kad(k) => match k.operation() {
+            Some(Operation::BootstrapResp(r)) => r.contacts().map_or(0, |c| c.count()),
+            Some(Operation::Res(r)) => r.contacts().count(),
+            Some(Operation::PublishKeyReq(r)) => r.entries().count(),
+            Some(_) => 1,
+            None => 0,
+        },
+        _ => 0,
+    }
+}
+
+fn decode(c: &mut Criterion) {
+    let packets = packets();
+    let mut group = c.benchmark_group("decode");
+    group.bench_with_input(
+        BenchmarkId::new("packet_kind", "crawl"),
+        &packets,
+        |b, packets| {
+            b.iter(|| {
+                packets
+                    .iter()
+                    .map(|raw| walk(Packet::from_slice(black_box(raw)).unwrap().kind().unwrap()))
+                    .sum::<usize>()
+            })
+        },
+    );
+
+    let mut ctx = DecodeContext::new();
+    group.bench_with_input(
+        BenchmarkId::new("decode_context", "crawl"),
+        &packets,
+        |b, packets| {
+            b.iter(|| {
+                packets
+                    .iter()
+                    .map(|raw| {
+                        let p = Packet::from_slice(black_box(raw)).unwrap();
+                        walk(ctx.kind(&p).unwrap())
+                    })
+                    .sum::<usize>()
+            })
+        },
+    );
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
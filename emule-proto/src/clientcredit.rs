@@ -65,7 +65,7 @@ unsafe impl Plain for CreditData {}
 pub fn split_from<P: Plain>(buf: &[u8]) -> (&P, &[u8]) {
     let sz = std::mem::size_of::<P>();
     let (i, rem) = buf.split_at(sz);
-    (P::from_bytes(&i).unwrap(), rem)
+    (P::from_bytes(i).unwrap(), rem)
 }
 
 #[derive(Debug)]
@@ -97,8 +97,8 @@ impl ClientCredit {
 }
 
 pub fn parse(inp: &[u8]) -> Result<Vec<ClientCredit>, Box<dyn Error>> {
-    if inp.len() < 1 {
-        return Err("no version byte found")?;
+    if inp.is_empty() {
+        Err("no version byte found")?;
     }
 
     let version = inp[0];
@@ -117,7 +117,7 @@ pub fn parse(inp: &[u8]) -> Result<Vec<ClientCredit>, Box<dyn Error>> {
     let mut rem = &inp[1..];
 
     if rem.len() < 4 {
-        return Err(format!("missing count, need 4 bytes, have {}", rem.len()))?;
+        Err(format!("missing count, need 4 bytes, have {}", rem.len()))?;
     }
 
     let count = u32::from_le_bytes(rem[..4].try_into().unwrap()) as usize;
@@ -125,12 +125,12 @@ pub fn parse(inp: &[u8]) -> Result<Vec<ClientCredit>, Box<dyn Error>> {
 
     let n = count * entry_size;
     if rem.len() < n {
-        return Err(format!("not enough space, need {} bytes ({} entries {} bytes each), have {}",
+        Err(format!("not enough space, need {} bytes ({} entries {} bytes each), have {}",
             n, count, entry_size, rem.len()))?;
     }
 
     if rem.len() != n {
-        return Err(format!("spare bytes {}, ({} entries, {} bytes each, {} buf bytes, {} bytes needed",
+        Err(format!("spare bytes {}, ({} entries, {} bytes each, {} buf bytes, {} bytes needed",
             rem.len() - n, count, entry_size, rem.len(), n))?;
     }
 
@@ -0,0 +1,74 @@
+//! Determining our external kad udp port from `Pong` replies, like emule's `CPrefs::SetExternKadPort`
+//!
+//! When behind a NAT that rewrites ports, the port we bind is not the port others see. emule
+//! periodically sends a `Ping` to a random contact (every 15 seconds, while the lookup is
+//! running). Each `Pong` reports the udp port the `Ping` arrived from. Once 2 different hosts
+//! agree on a port, that port is used as our external port. If `EXTERNAL_PORT_ASK_IPS` hosts
+//! reply without agreeing, the lookup gives up.
+//!
+//! Like emule, only `Pong`s from hosts we sent a `Ping` to are used, so others can't pick our port
+//! by sending unsolicited `Pong`s.
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Number of distinct hosts asked before giving up
+pub const EXTERNAL_PORT_ASK_IPS: usize = 3;
+
+/// How often emule sends a `Ping` while the lookup is running
+pub const EXTERNAL_PORT_LOOKUP_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Default)]
+pub struct ExternPortLookup {
+    pinged_ips: Vec<IpAddr>,
+    from_ips: Vec<IpAddr>,
+    ports: Vec<u16>,
+    extern_port: Option<u16>,
+}
+
+impl ExternPortLookup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `true` while more `Pong`s are needed (and more `Ping`s should be sent)
+    pub fn is_searching(&self) -> bool {
+        self.extern_port.is_none() && self.from_ips.len() < EXTERNAL_PORT_ASK_IPS
+    }
+
+    /// Our external port, if the lookup found one
+    pub fn extern_port(&self) -> Option<u16> {
+        self.extern_port
+    }
+
+    /// Record that we sent a `Ping` to `to` for this lookup
+    pub fn add_ping(&mut self, to: IpAddr) {
+        if !self.pinged_ips.contains(&to) {
+            self.pinged_ips.push(to);
+        }
+    }
+
+    /// Record that `from` reported (in a `Pong`) that our packet came from `port`
+    ///
+    /// Only the first report from each host we pinged (see `add_ping`) is used. Returns the
+    /// external port once it is known.
+    pub fn add_pong(&mut self, from: IpAddr, port: u16) -> Option<u16> {
+        let solicited = self.pinged_ips.contains(&from);
+        if !self.is_searching() || !solicited || self.from_ips.contains(&from) {
+            return self.extern_port;
+        }
+
+        self.from_ips.push(from);
+        if self.ports.contains(&port) {
+            self.extern_port = Some(port);
+        } else {
+            self.ports.push(port);
+        }
+
+        self.extern_port
+    }
+
+    /// Forget all replies & start over, for example after our address changes
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,184 @@
+//! Kademlia node, keyword and file ids, emule's `CUInt128`
+//!
+//! emule keeps ids as 4 u32 words, most significant first, and sends each word little endian:
+//!
+//! ```norust
+//! struct KadId {
+//!     words: [le32; 4],
+//! }
+//! ```
+//!
+//! `KadId` orders, displays and parses ids the way emule does, so hex ids match the ones shown in
+//! emule's UI and distances compare the same way.
+use rand::{Rng, RngExt};
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::{BitXor, Not};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct KadId(u128);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseKadIdError {
+    #[error("expected 32 hex digits, have {len} characters")]
+    Length { len: usize },
+
+    #[error("invalid hex digit {c:?}")]
+    Digit { c: char },
+}
+
+/// bytes of a `KadId` on the wire and in files
+pub const KAD_ID_LEN: usize = 16;
+
+impl KadId {
+    pub const ZERO: KadId = KadId(0);
+
+    pub fn from_wire(raw: [u8; KAD_ID_LEN]) -> Self {
+        let mut v = 0u128;
+        for w in raw.chunks_exact(4) {
+            v = (v << 32) | u32::from_le_bytes(w.try_into().unwrap()) as u128;
+        }
+        KadId(v)
+    }
+
+    /// `raw` must have at least `KAD_ID_LEN` bytes, only the first `KAD_ID_LEN` are used
+    pub(crate) fn from_slice(raw: &[u8]) -> Self {
+        Self::from_wire(raw[..KAD_ID_LEN].try_into().unwrap())
+    }
+
+    pub fn to_wire(self) -> [u8; KAD_ID_LEN] {
+        let mut raw = [0u8; KAD_ID_LEN];
+        for (i, w) in raw.chunks_exact_mut(4).enumerate() {
+            w.copy_from_slice(&((self.0 >> (96 - i * 32)) as u32).to_le_bytes());
+        }
+        raw
+    }
+
+    /// Kademlia's xor metric. Smaller is closer.
+    pub fn distance(self, other: KadId) -> KadId {
+        self ^ other
+    }
+
+    /// Bit `i`, counting from the most significant. emule's `GetBitNumber()`
+    ///
+    /// Panics if `i >= 128`
+    pub fn bit(self, i: u32) -> bool {
+        assert!(i < 128, "bit {} out of range", i);
+        (self.0 >> (127 - i)) & 1 == 1
+    }
+
+    /// Number of leading bits `self` and `other` have in common, 128 if they are equal. This is
+    /// the depth at which both land in the same routing zone.
+    pub fn common_prefix_len(self, other: KadId) -> u32 {
+        (self ^ other).0.leading_zeros()
+    }
+
+    /// A random id with the leading `prefix_len` bits of `prefix`, which is a random id within
+    /// the range of ids sharing that prefix. emule's `CUInt128(const CUInt128&, UINT)`
+    ///
+    /// Panics if `prefix_len > 128`
+    pub fn random_with_prefix<R: Rng + ?Sized>(
+        prefix: KadId,
+        prefix_len: u32,
+        rng: &mut R,
+    ) -> Self {
+        assert!(
+            prefix_len <= 128,
+            "prefix length {} out of range",
+            prefix_len
+        );
+        let random = rng.random::<u128>();
+        let mask = u128::MAX.checked_shr(prefix_len).unwrap_or(0);
+        KadId((prefix.0 & !mask) | (random & mask))
+    }
+
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        KadId(rng.random())
+    }
+}
+
+impl From<u128> for KadId {
+    fn from(v: u128) -> Self {
+        KadId(v)
+    }
+}
+
+impl From<KadId> for u128 {
+    fn from(v: KadId) -> Self {
+        v.0
+    }
+}
+
+impl BitXor for KadId {
+    type Output = KadId;
+
+    fn bitxor(self, rhs: KadId) -> KadId {
+        KadId(self.0 ^ rhs.0)
+    }
+}
+
+impl Not for KadId {
+    type Output = KadId;
+
+    fn not(self) -> KadId {
+        KadId(!self.0)
+    }
+}
+
+/// Upper case hex, like emule's `ToHexString()`
+impl fmt::Display for KadId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:032X}", self.0)
+    }
+}
+
+impl fmt::Debug for KadId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "KadId({})", self)
+    }
+}
+
+impl fmt::LowerHex for KadId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, fmt)
+    }
+}
+
+impl fmt::UpperHex for KadId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, fmt)
+    }
+}
+
+/// 32 hex digits, in either case
+impl FromStr for KadId {
+    type Err = ParseKadIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 32 {
+            return Err(ParseKadIdError::Length { len: s.len() });
+        }
+
+        let mut v = 0u128;
+        for c in s.chars() {
+            let d = c.to_digit(16).ok_or(ParseKadIdError::Digit { c })?;
+            v = (v << 4) | d as u128;
+        }
+        Ok(KadId(v))
+    }
+}
+
+impl serde::Serialize for KadId {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for KadId {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
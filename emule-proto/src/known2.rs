@@ -38,14 +38,13 @@ impl fmt::Display for CaichTree {
 }
 
 /// the known2 file (known2_64.dat) contains "masterhashes"
-
 pub fn parse(inp: &[u8]) -> Result<Vec<CaichTree>, Box<dyn Error>> {
-    if inp.len() < 1 {
-        return Err("no magic marker")?;
+    if inp.is_empty() {
+        Err("no magic marker")?;
     }
 
     if inp[0] != KNOWN2_MET_VERSION {
-        return Err("unknown version")?;
+        Err("unknown version")?;
     }
 
     // every HASHSIZE bytes is a `CAICHHash` followed by a 32-bit count (which
@@ -59,13 +58,13 @@ pub fn parse(inp: &[u8]) -> Result<Vec<CaichTree>, Box<dyn Error>> {
     let tn = HASHSIZE + 4;
     loop {
         let mut c = CaichTree::default();
-        if rem.len() == 0 {
+        if rem.is_empty() {
             return Ok(r);
         }
 
         // XXX: try split?
         if rem.len() < tn {
-            return Err(format!("Spare bytes where tree entry expected: need {}, have {}",
+            Err(format!("Spare bytes where tree entry expected: need {}, have {}",
                 tn, rem.len()))?;
         }
 
@@ -76,7 +75,7 @@ pub fn parse(inp: &[u8]) -> Result<Vec<CaichTree>, Box<dyn Error>> {
 
         let n = HASHSIZE * ct as usize;
         if rem.len() < n {
-            return Err(format!("tree {} needs {} bytes, but have {}",
+            Err(format!("tree {} needs {} bytes, but have {}",
                 r.len(), n, rem.len()))?;
         }
 
@@ -1,7 +1,13 @@
 pub mod udp_proto;
+pub mod kad_id;
 pub mod known2;
 pub mod clientcredit;
 pub mod nodes;
+pub mod obfuscate;
+pub mod extern_port;
+pub mod pcap;
+
+pub use kad_id::KadId;
 
 // AC_BootstrapIPs.dat
 // AC_IPFilterUpdateURLs.dat
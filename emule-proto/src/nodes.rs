@@ -1,5 +1,7 @@
+use crate::KadId;
 use std::error::Error;
 use std::convert::TryInto;
+use std::io;
 use serde::{Serialize, Deserialize};
 
 // 2 kinds:
@@ -9,7 +11,7 @@ use serde::{Serialize, Deserialize};
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Contact {
     // bootstrap/version 0/1 fields
-    pub id: u128,
+    pub id: KadId,
     pub ip: std::net::Ipv4Addr,
     pub udp_port: u16,
     pub tcp_port: u16,
@@ -25,6 +27,15 @@ pub struct Contact {
     pub verified: Option<u8>,    
 }
 
+impl Contact {
+    /// The udp verify key this contact handed us, if any
+    pub fn udp_key(&self) -> Option<crate::obfuscate::KadUdpKey> {
+        self.kad_udp_key
+            .map(crate::obfuscate::KadUdpKey::from_raw)
+            .filter(|k| k.key != 0)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Nodes {
     pub version: u32,
@@ -32,6 +43,62 @@ pub struct Nodes {
     pub contacts: Vec<Contact>,
 }
 
+impl Nodes {
+    /// Write a nodes.dat in `self.version` (0 to 3), or the version 3 bootstrap edition if
+    /// `self.is_bootstrap`. Fields missing for that version are written as 0.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        if self.version > 3 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("unknown version {}", self.version)));
+        }
+        if self.is_bootstrap && self.version != 3 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("bootstrap nodes.dat must be version 3, not {}", self.version)));
+        }
+
+        let count = self.contacts.len() as u32;
+        if self.version == 0 {
+            // a 0 count marks the versioned header
+            if count == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    "version 0 needs at least 1 contact"));
+            }
+        } else {
+            w.write_all(&0u32.to_le_bytes())?;
+            w.write_all(&self.version.to_le_bytes())?;
+            if self.version == 3 {
+                let bootstrap_edition = if self.is_bootstrap { 1u32 } else { 0 };
+                w.write_all(&bootstrap_edition.to_le_bytes())?;
+            }
+        }
+        w.write_all(&count.to_le_bytes())?;
+
+        for c in &self.contacts {
+            w.write_all(&c.id.to_wire())?;
+            w.write_all(&u32::from(c.ip).to_le_bytes())?;
+            w.write_all(&c.udp_port.to_le_bytes())?;
+            w.write_all(&c.tcp_port.to_le_bytes())?;
+            if self.version >= 1 {
+                w.write_all(&[c.contact_version.unwrap_or(0)])?;
+            } else {
+                w.write_all(&[c.by_type.unwrap_or(0)])?;
+            }
+
+            if self.version >= 2 && !self.is_bootstrap {
+                let (key, ip) = c.kad_udp_key.unwrap_or((0, 0));
+                w.write_all(&key.to_le_bytes())?;
+                w.write_all(&ip.to_le_bytes())?;
+                w.write_all(&[c.verified.unwrap_or(0)])?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The bootstrap edition of version 3, written by emule when collecting nodes for distribution.
+// Entries only have the version 0/1 fields, and no `by_type`.
+//
 // NOTE: requires `inp` to already have the version 3 header removed
 pub fn parse_bootstrap(inp: &[u8]) -> Result<Vec<Contact>, Box<dyn Error>> {
     let mut rem = inp;
@@ -45,24 +112,24 @@ pub fn parse_bootstrap(inp: &[u8]) -> Result<Vec<Contact>, Box<dyn Error>> {
 
     let n = count * 25;
     if n != rem.len() {
-        Err(format!("not enough data, need {} bytes for {} entries of {} bytes each, have {}",
+        Err(format!("wrong size, need {} bytes for {} entries of {} bytes each, have {}",
             n, count, 25, rem.len()))?;
     }
 
     let mut r = Vec::with_capacity(count);
 
     for _ in 0..count {
-        let id = u128::from_le_bytes(rem[..8].try_into().unwrap());
-        rem = &rem[..8];
+        let id = KadId::from_slice(rem);
+        rem = &rem[16..];
         let ip = u32::from_le_bytes(rem[..4].try_into().unwrap());
         let ip = std::net::Ipv4Addr::from(ip);
-        rem = &rem[..4];
+        rem = &rem[4..];
         let udp_port = u16::from_le_bytes(rem[..2].try_into().unwrap());
-        rem = &rem[..2];
+        rem = &rem[2..];
         let tcp_port = u16::from_le_bytes(rem[..2].try_into().unwrap());
-        rem = &rem[..2];
+        rem = &rem[2..];
         let contact_version = Some(rem[0]);
-        rem = &rem[..1];
+        rem = &rem[1..];
 
         r.push(Contact {
             id,
@@ -100,18 +167,31 @@ pub fn parse(inp: &[u8]) -> Result<Nodes, Box<dyn Error>> {
         rem = &rem[4..];
 
         if version == 3 {
+            if rem.len() < 4 {
+                Err(format!("no bootstrap edition, have {} bytes", rem.len()))?;
+            }
+
             let bootstrap_edition = u32::from_le_bytes(rem[..4].try_into().unwrap());
             rem = &rem[4..];
 
             if bootstrap_edition == 1 {
-                // bootstrap node parsing?
                 // In practice, I haven't found any bootstrap variant nodes.dat around the
                 // internet. Generating them requires compiling emule with special options, and it
                 // seems likely no one bothers.
-                todo!();
+                return Ok(Nodes {
+                    version,
+                    is_bootstrap: true,
+                    contacts: parse_bootstrap(rem)?,
+                });
+            } else if bootstrap_edition != 0 {
+                Err(format!("unknown bootstrap edition {}", bootstrap_edition))?;
             }
         }
 
+        if rem.len() < 4 {
+            Err(format!("no count, have {} bytes", rem.len()))?;
+        }
+
         let count = u32::from_le_bytes(rem[..4].try_into().unwrap()) as usize;
         rem = &rem[4..];
         (version, count)
@@ -130,7 +210,7 @@ pub fn parse(inp: &[u8]) -> Result<Nodes, Box<dyn Error>> {
         }
 
         let (mut s, rs) = rem.split_at(n);
-        let id = u128::from_le_bytes(s[..16].try_into().unwrap());
+        let id = KadId::from_slice(s);
         s = &s[16..];
         let ip = u32::from_le_bytes(s[..4].try_into().unwrap());
         let ip = std::net::Ipv4Addr::from(ip);
@@ -164,7 +244,7 @@ pub fn parse(inp: &[u8]) -> Result<Nodes, Box<dyn Error>> {
             s = &s[1..];
         }
     
-        if s.len() != 0 {
+        if !s.is_empty() {
             Err(format!("spare bytes in entry {}: {} bytes, ", r.len(), s.len()))?;
         }
 
@@ -182,12 +262,12 @@ pub fn parse(inp: &[u8]) -> Result<Nodes, Box<dyn Error>> {
         })
     }
 
-    if rem.len() != 0 {
+    if !rem.is_empty() {
         Err(format!("spare bytes: {}", rem.len()))?;
     }
 
     Ok(Nodes {
-        version: version,
+        version,
         is_bootstrap: false,
         contacts: r,         
     })
@@ -0,0 +1,291 @@
+//! UDP packet obfuscation as performed by emule's `CEncryptedDatagramSocket`
+//!
+//! ```norust
+//! struct ObfuscatedPacket {
+//!     // semi random, but never a `UdpProto` value other than `Edonkey`. The low 2 bits hint at
+//!     // which key was used (see `KeyKind`), but old clients set them randomly.
+//!     marker: u8,
+//!     // mixed into the md5 used to generate the RC4 key
+//!     random_key_part: [u8;2],
+//!
+//!     // everything below is RC4 encrypted
+//!     magic: le32,
+//!     pad_len: u8,
+//!     padding: [u8;pad_len],
+//!     // only present for kad packets
+//!     receiver_verify_key: le32,
+//!     sender_verify_key: le32,
+//!     // a complete packet, starting with a `UdpProto` byte
+//!     payload: [u8],
+//! }
+//! ```
+use crate::kad_id::{KadId, KAD_ID_LEN};
+use crate::udp_proto::UdpProto;
+use md5::{Digest, Md5};
+use rand::{Rng, RngExt};
+use std::convert::TryInto;
+use std::io;
+use std::net::Ipv4Addr;
+
+/// bytes before the padding: marker, random key part, magic, pad_len
+pub const CRYPT_HEADER_WITHOUT_PADDING: usize = 8;
+
+/// mixed into the md5 for keys based on the user hash
+pub const MAGIC_UDP: u8 = 91;
+
+/// decrypted value of the `magic` field for client (kad & ed2k) packets
+pub const MAGIC_UDP_SYNC_CLIENT: u32 = 0x395F2EC1;
+
+/// Which of our keys the remote used to obfuscate a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeyKind {
+    /// kad packet keyed with the kad id of the receiving node
+    KadId,
+    /// ed2k packet keyed with the user hash of the receiving client & the ip of the sender
+    UserHash,
+    /// kad packet keyed with the udp verify key the receiver previously handed to the sender
+    VerifyKey,
+}
+
+impl KeyKind {
+    /// The key emule tries first, based on the marker byte (the first byte of the packet)
+    pub fn from_marker(marker: u8) -> Self {
+        match marker & 0x03 {
+            0 => KeyKind::KadId,
+            2 => KeyKind::VerifyKey,
+            _ => KeyKind::UserHash,
+        }
+    }
+
+    /// emule cycles through the keys in this order
+    pub(crate) fn next(self) -> Self {
+        match self {
+            KeyKind::KadId => KeyKind::UserHash,
+            KeyKind::UserHash => KeyKind::VerifyKey,
+            KeyKind::VerifyKey => KeyKind::KadId,
+        }
+    }
+
+    /// Only ed2k packets omit the verify keys
+    pub fn is_kad(self) -> bool {
+        !matches!(self, KeyKind::UserHash)
+    }
+}
+
+/// Keys used to obfuscate a kad packet we send
+///
+/// Like emule, the `receiver_verify_key` is used only if the kad id of the target is unknown.
+#[derive(Debug, Clone, Copy)]
+pub struct SendKeys {
+    /// kad id of the node we're sending to
+    pub target_kad_id: Option<KadId>,
+    /// udp verify key the target previously handed to us (see `KadUdpKey::key_for()`). 0 if
+    /// unknown.
+    pub receiver_verify_key: u32,
+    /// udp verify key we hand to the target (see `udp_verify_key()`), it will use this to
+    /// obfuscate replies to us.
+    pub sender_verify_key: u32,
+}
+
+impl SendKeys {
+    fn key(&self, random_key_part: [u8; 2]) -> io::Result<(KeyKind, Rc4)> {
+        match self.target_kad_id {
+            Some(kad_id) if kad_id != KadId::default() => {
+                Ok((KeyKind::KadId, kad_id_key(kad_id, random_key_part)))
+            }
+            _ if self.receiver_verify_key != 0 => Ok((
+                KeyKind::VerifyKey,
+                verify_key_key(self.receiver_verify_key, random_key_part),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "need either a target kad id or a receiver verify key to obfuscate",
+            )),
+        }
+    }
+
+    /// Obfuscate the complete packet `packet` (starting with a `UdpProto` byte) and write it to
+    /// `w`.
+    ///
+    /// A random amount of padding (up to `max_padding` bytes) is inserted. emule currently uses
+    /// no padding for UDP, but accepts it.
+    pub fn write_to<W: io::Write, R: Rng + ?Sized>(
+        &self,
+        w: &mut W,
+        packet: &[u8],
+        max_padding: u8,
+        rng: &mut R,
+    ) -> io::Result<()> {
+        let random_key_part: [u8; 2] = rng.random();
+        let (key_kind, mut rc4) = self.key(random_key_part)?;
+
+        // emule gives up after 128 tries and uses 0x01
+        let marker = (0..128)
+            .map(|_| {
+                let m: u8 = rng.random();
+                match key_kind {
+                    KeyKind::VerifyKey => (m & 0xFE) | 0x02,
+                    _ => m & 0xFC,
+                }
+            })
+            .find(|&m| !UdpProto::is_plain_marker(m))
+            .unwrap_or(0x01);
+
+        let pad_len = (rng.random::<u8>() as u16 % (max_padding as u16 + 1)) as u8;
+
+        let mut out =
+            Vec::with_capacity(CRYPT_HEADER_WITHOUT_PADDING + pad_len as usize + 8 + packet.len());
+        out.push(marker);
+        out.extend_from_slice(&random_key_part);
+        out.extend_from_slice(&MAGIC_UDP_SYNC_CLIENT.to_le_bytes());
+        out.push(pad_len);
+        out.extend((0..pad_len).map(|_| rng.random::<u8>()));
+        out.extend_from_slice(&self.receiver_verify_key.to_le_bytes());
+        out.extend_from_slice(&self.sender_verify_key.to_le_bytes());
+        out.extend_from_slice(packet);
+        rc4.apply(&mut out[3..]);
+
+        w.write_all(&out)
+    }
+}
+
+/// The udp verify key we hand to the node at `target_ip`, emule's `CPrefs::GetUDPVerifyKey()`
+///
+/// `private_key` is our own random kad udp key, which emule generates once and keeps in its
+/// preferences so handed out keys stay valid across restarts. The result is never 0, which means
+/// "no key".
+pub fn udp_verify_key(private_key: u32, target_ip: Ipv4Addr) -> u32 {
+    // emule hashes a u64 of `private_key << 32 | ip`, with the ip in network order
+    let mut d = [0u8; 8];
+    d[..4].copy_from_slice(&target_ip.octets());
+    d[4..].copy_from_slice(&private_key.to_le_bytes());
+    let h = Md5::digest(d);
+    let k = h
+        .chunks_exact(4)
+        .fold(0, |k, c| k ^ u32::from_le_bytes(c.try_into().unwrap()));
+    k % 0xFFFF_FFFE + 1
+}
+
+/// A udp verify key handed to us by a kad node, emule's `CKadUDPKey`
+///
+/// The node derived the key from the ip it saw us at, so it is only worth sending back while our
+/// public ip is unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KadUdpKey {
+    pub key: u32,
+    /// our public ip when the key was handed to us
+    pub ip: Ipv4Addr,
+}
+
+impl KadUdpKey {
+    /// From the `(key, ip)` pair emule stores (see `nodes::Contact::kad_udp_key`)
+    pub fn from_raw((key, ip): (u32, u32)) -> Self {
+        KadUdpKey {
+            key,
+            // in network order
+            ip: Ipv4Addr::from(ip.to_le_bytes()),
+        }
+    }
+
+    pub fn to_raw(&self) -> (u32, u32) {
+        (self.key, u32::from_le_bytes(self.ip.octets()))
+    }
+
+    pub fn is_valid_for(&self, public_ip: Ipv4Addr) -> bool {
+        self.key != 0 && self.ip == public_ip
+    }
+
+    /// The `receiver_verify_key` to use when sending to the node that handed us this key, or 0
+    /// if our public ip has changed since.
+    pub fn key_for(&self, public_ip: Ipv4Addr) -> u32 {
+        if self.is_valid_for(public_ip) {
+            self.key
+        } else {
+            0
+        }
+    }
+}
+
+/// RC4 stream cipher state. emule generates keys from md5 hashes and never discards the initial
+/// keystream for UDP.
+#[derive(Clone)]
+pub(crate) struct Rc4 {
+    s: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    pub(crate) fn new(key: &[u8]) -> Self {
+        let mut s = [0u8; 256];
+        for (i, v) in s.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(s[i]).wrapping_add(key[i % key.len()]);
+            s.swap(i, j as usize);
+        }
+
+        Rc4 { s, i: 0, j: 0 }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.i = self.i.wrapping_add(1);
+        self.j = self.j.wrapping_add(self.s[self.i as usize]);
+        self.s.swap(self.i as usize, self.j as usize);
+        let idx = self.s[self.i as usize].wrapping_add(self.s[self.j as usize]);
+        self.s[idx as usize]
+    }
+
+    /// encrypt or decrypt (they are the same operation) `buf` in place
+    pub(crate) fn apply(&mut self, buf: &mut [u8]) {
+        for b in buf.iter_mut() {
+            *b ^= self.next_byte();
+        }
+    }
+
+    /// discard `n` bytes of keystream
+    pub(crate) fn skip(&mut self, n: usize) {
+        for _ in 0..n {
+            self.next_byte();
+        }
+    }
+}
+
+fn md5_rc4(data: &[u8]) -> Rc4 {
+    Rc4::new(&Md5::digest(data))
+}
+
+/// `md5(kad_id || random_key_part)`
+pub(crate) fn kad_id_key(kad_id: KadId, random_key_part: [u8; 2]) -> Rc4 {
+    let mut d = Vec::with_capacity(KAD_ID_LEN + 2);
+    d.extend_from_slice(&kad_id.to_wire());
+    d.extend_from_slice(&random_key_part);
+    md5_rc4(&d)
+}
+
+/// `md5(user_hash || ip || MAGIC_UDP || random_key_part)`
+///
+/// `ip` is the public ip of the sender of the packet.
+pub(crate) fn user_hash_key(
+    user_hash: &[u8],
+    ip: std::net::Ipv4Addr,
+    random_key_part: [u8; 2],
+) -> Rc4 {
+    let mut d = Vec::with_capacity(user_hash.len() + 4 + 1 + 2);
+    d.extend_from_slice(user_hash);
+    d.extend_from_slice(&ip.octets());
+    d.push(MAGIC_UDP);
+    d.extend_from_slice(&random_key_part);
+    md5_rc4(&d)
+}
+
+/// `md5(verify_key || random_key_part)`
+pub(crate) fn verify_key_key(verify_key: u32, random_key_part: [u8; 2]) -> Rc4 {
+    let mut d = [0u8; 6];
+    d[..4].copy_from_slice(&verify_key.to_le_bytes());
+    d[4..].copy_from_slice(&random_key_part);
+    md5_rc4(&d)
+}
@@ -0,0 +1,462 @@
+//! Reading udp datagrams out of packet captures, as written by tcpdump/wireshark
+//!
+//! Both classic pcap (either byte order, micro or nanosecond timestamps) and pcapng are
+//! supported. Frames may be ethernet (optionally vlan tagged), raw ip, loopback or linux
+//! "cooked" captures. Anything that isn't a complete, unfragmented udp datagram over ipv4 or ipv6
+//! is skipped.
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use strum::IntoStaticStr;
+use thiserror::Error;
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum Error {
+    #[error("capture too short for a header: have {have}, need {need}")]
+    HeaderTooShort { have: usize, need: usize },
+
+    #[error("unrecognized capture magic {magic:#010x}")]
+    UnrecognizedMagic { magic: u32 },
+
+    #[error("record at offset {offset} too short: have {have}, need {need}")]
+    RecordTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("pcapng block at offset {offset} has invalid length {len}")]
+    BlockLengthInvalid { offset: usize, len: usize },
+
+    #[error("pcapng block at offset {offset} refers to unknown interface {interface}")]
+    UnknownInterface { offset: usize, interface: u32 },
+}
+
+impl Error {
+    /// Name of the variant, without its fields. Useful for grouping errors
+    pub fn variant_name(&self) -> &'static str {
+        self.into()
+    }
+}
+
+/// A udp datagram found in a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram<'a> {
+    /// since the unix epoch
+    pub timestamp: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: &'a [u8],
+}
+
+// link layer header types, see https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SHB: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_IDB: u32 = 0x00000001;
+const PCAPNG_SPB: u32 = 0x00000003;
+const PCAPNG_EPB: u32 = 0x00000006;
+const PCAPNG_OPT_IF_TSRESOL: u16 = 9;
+
+/// Iterate over the udp datagrams in a classic pcap or pcapng capture
+///
+/// Iteration stops after the first error.
+pub fn datagrams(capture: &[u8]) -> Result<Datagrams<'_>, Error> {
+    if capture.len() < 4 {
+        return Err(Error::HeaderTooShort {
+            have: capture.len(),
+            need: 4,
+        });
+    }
+
+    let magic = u32::from_le_bytes(capture[..4].try_into().unwrap());
+    let format = match magic {
+        PCAPNG_SHB => Format::PcapNg {
+            big_endian: false,
+            interfaces: Vec::new(),
+        },
+        _ => {
+            let (big_endian, nanos) = match (magic, magic.swap_bytes()) {
+                (PCAP_MAGIC_MICROS, _) => (false, false),
+                (PCAP_MAGIC_NANOS, _) => (false, true),
+                (_, PCAP_MAGIC_MICROS) => (true, false),
+                (_, PCAP_MAGIC_NANOS) => (true, true),
+                _ => return Err(Error::UnrecognizedMagic { magic }),
+            };
+            let need = 24;
+            if capture.len() < need {
+                return Err(Error::HeaderTooShort {
+                    have: capture.len(),
+                    need,
+                });
+            }
+
+            let order = ByteOrder { big_endian };
+            Format::Pcap {
+                order,
+                nanos,
+                link_type: order.u32(&capture[20..24]),
+            }
+        }
+    };
+
+    Ok(Datagrams {
+        raw: capture,
+        offset: match format {
+            Format::Pcap { .. } => 24,
+            Format::PcapNg { .. } => 0,
+        },
+        format,
+        failed: false,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ByteOrder {
+    big_endian: bool,
+}
+
+impl ByteOrder {
+    fn u16(self, b: &[u8]) -> u16 {
+        let b = b[..2].try_into().unwrap();
+        if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(self, b: &[u8]) -> u32 {
+        let b = b[..4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: u32,
+    /// timestamp units per second
+    ts_per_sec: u64,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        order: ByteOrder,
+        nanos: bool,
+        link_type: u32,
+    },
+    PcapNg {
+        big_endian: bool,
+        /// from the interface description blocks of the current section
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// A captured link layer frame, as `(timestamp, link_type, frame)`
+type Frame<'a> = (Duration, u32, &'a [u8]);
+
+/// Returned by `datagrams()`
+#[derive(Debug)]
+pub struct Datagrams<'a> {
+    raw: &'a [u8],
+    offset: usize,
+    format: Format,
+    failed: bool,
+}
+
+impl<'a> Datagrams<'a> {
+    fn too_short(&self, need: usize) -> Error {
+        Error::RecordTooShort {
+            offset: self.offset,
+            have: self.raw.len() - self.offset,
+            need,
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame<'a>>, Error> {
+        loop {
+            let rem = &self.raw[self.offset..];
+            if rem.is_empty() {
+                return Ok(None);
+            }
+
+            match &mut self.format {
+                Format::Pcap {
+                    order,
+                    nanos,
+                    link_type,
+                } => {
+                    let (order, nanos, link_type) = (*order, *nanos, *link_type);
+                    if rem.len() < 16 {
+                        return Err(self.too_short(16));
+                    }
+                    let secs = order.u32(&rem[0..4]) as u64;
+                    let frac = order.u32(&rem[4..8]);
+                    let incl_len = order.u32(&rem[8..12]) as usize;
+                    if rem.len() < 16 + incl_len {
+                        return Err(self.too_short(16 + incl_len));
+                    }
+
+                    let frame = &rem[16..(16 + incl_len)];
+                    self.offset += 16 + incl_len;
+                    let ts = if nanos {
+                        Duration::new(secs, frac)
+                    } else {
+                        Duration::from_secs(secs) + Duration::from_micros(frac.into())
+                    };
+                    return Ok(Some((ts, link_type, frame)));
+                }
+                Format::PcapNg {
+                    big_endian,
+                    interfaces,
+                } => {
+                    if rem.len() < 12 {
+                        return Err(self.too_short(12));
+                    }
+
+                    let block_type = ByteOrder {
+                        big_endian: *big_endian,
+                    }
+                    .u32(&rem[0..4]);
+                    if block_type == PCAPNG_SHB {
+                        // each section sets its own byte order
+                        let magic = u32::from_le_bytes(rem[8..12].try_into().unwrap());
+                        *big_endian = match (magic, magic.swap_bytes()) {
+                            (PCAPNG_BYTE_ORDER_MAGIC, _) => false,
+                            (_, PCAPNG_BYTE_ORDER_MAGIC) => true,
+                            _ => return Err(Error::UnrecognizedMagic { magic }),
+                        };
+                        interfaces.clear();
+                    }
+
+                    let order = ByteOrder {
+                        big_endian: *big_endian,
+                    };
+                    let len = order.u32(&rem[4..8]) as usize;
+                    if len < 12 || !len.is_multiple_of(4) {
+                        return Err(Error::BlockLengthInvalid {
+                            offset: self.offset,
+                            len,
+                        });
+                    }
+                    if rem.len() < len {
+                        return Err(self.too_short(len));
+                    }
+
+                    let offset = self.offset;
+                    let body = &rem[8..(len - 4)];
+                    self.offset += len;
+                    match block_type {
+                        PCAPNG_IDB => {
+                            if body.len() < 8 {
+                                return Err(Error::BlockLengthInvalid { offset, len });
+                            }
+                            interfaces.push(Interface {
+                                link_type: order.u16(&body[0..2]).into(),
+                                ts_per_sec: ts_per_sec(order, &body[8..]),
+                            });
+                        }
+                        PCAPNG_EPB => {
+                            if body.len() < 20 {
+                                return Err(Error::BlockLengthInvalid { offset, len });
+                            }
+                            let interface = order.u32(&body[0..4]);
+                            let Some(i) = interfaces.get(interface as usize) else {
+                                return Err(Error::UnknownInterface { offset, interface });
+                            };
+                            let ts = (order.u32(&body[4..8]) as u64) << 32
+                                | order.u32(&body[8..12]) as u64;
+                            let cap_len = order.u32(&body[12..16]) as usize;
+                            if body.len() < 20 + cap_len {
+                                return Err(Error::BlockLengthInvalid { offset, len });
+                            }
+
+                            let ts = Duration::from_secs(ts / i.ts_per_sec)
+                                + Duration::from_nanos(
+                                    ((ts % i.ts_per_sec) as u128 * 1_000_000_000
+                                        / i.ts_per_sec as u128)
+                                        as u64,
+                                );
+                            return Ok(Some((ts, i.link_type, &body[20..(20 + cap_len)])));
+                        }
+                        PCAPNG_SPB => {
+                            // no timestamp, and always from the first interface
+                            let Some(i) = interfaces.first() else {
+                                return Err(Error::UnknownInterface {
+                                    offset,
+                                    interface: 0,
+                                });
+                            };
+                            if body.len() < 4 {
+                                return Err(Error::BlockLengthInvalid { offset, len });
+                            }
+                            let orig_len = order.u32(&body[0..4]) as usize;
+                            let frame = &body[4..];
+                            return Ok(Some((
+                                Duration::ZERO,
+                                i.link_type,
+                                &frame[..orig_len.min(frame.len())],
+                            )));
+                        }
+                        // section headers (handled above), statistics, name resolution, etc.
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Datagrams<'a> {
+    type Item = Result<Datagram<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            match self.next_frame() {
+                Ok(Some((timestamp, link_type, frame))) => {
+                    if let Some((src, dst, payload)) = udp_of_frame(link_type, frame) {
+                        return Some(Ok(Datagram {
+                            timestamp,
+                            src,
+                            dst,
+                            payload,
+                        }));
+                    }
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// The `if_tsresol` option of an interface description block, as units per second
+fn ts_per_sec(order: ByteOrder, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = order.u16(&options[0..2]);
+        let len = order.u16(&options[2..4]) as usize;
+        let padded = (len + 3) & !3;
+        if code == 0 || options.len() < 4 + padded {
+            break;
+        }
+
+        if code == PCAPNG_OPT_IF_TSRESOL && len >= 1 {
+            let v = options[4];
+            let exp = (v & 0x7f) as u32;
+            let per_sec = if v & 0x80 == 0 {
+                10u64.checked_pow(exp)
+            } else {
+                1u64.checked_shl(exp)
+            };
+            // nothing finer than a nanosecond has ever been seen in practice
+            return per_sec.unwrap_or(1_000_000_000).max(1);
+        }
+
+        options = &options[(4 + padded)..];
+    }
+
+    1_000_000
+}
+
+/// Strip the link layer, ip and udp headers, returning `(src, dst, payload)`
+fn udp_of_frame(link_type: u32, frame: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (ethertype, ip) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().unwrap());
+            let mut ip = frame.get(14..)?;
+            // 802.1Q and 802.1ad tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                ethertype = u16::from_be_bytes(ip.get(2..4)?.try_into().unwrap());
+                ip = ip.get(4..)?;
+            }
+            (Some(ethertype), ip)
+        }
+        LINKTYPE_LINUX_SLL => (
+            Some(u16::from_be_bytes(frame.get(14..16)?.try_into().unwrap())),
+            frame.get(16..)?,
+        ),
+        LINKTYPE_LINUX_SLL2 => (
+            Some(u16::from_be_bytes(frame.get(0..2)?.try_into().unwrap())),
+            frame.get(20..)?,
+        ),
+        // address family in the capturing host's byte order, the ip version tells us enough
+        LINKTYPE_NULL => (None, frame.get(4..)?),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => (None, frame),
+        _ => return None,
+    };
+
+    match (ethertype, ip.first()? >> 4) {
+        (Some(0x0800) | None, 4) => udp_of_ipv4(ip),
+        (Some(0x86dd) | None, 6) => udp_of_ipv6(ip),
+        _ => None,
+    }
+}
+
+fn udp_of_ipv4(ip: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let header_len = ((ip.first()? & 0x0f) as usize) * 4;
+    let total_len = u16::from_be_bytes(ip.get(2..4)?.try_into().unwrap()) as usize;
+    let flags_frag = u16::from_be_bytes(ip.get(6..8)?.try_into().unwrap());
+    // more fragments, or a fragment offset
+    if flags_frag & 0x3fff != 0 || *ip.get(9)? != 17 || header_len < 20 {
+        return None;
+    }
+
+    let src: [u8; 4] = ip.get(12..16)?.try_into().unwrap();
+    let dst: [u8; 4] = ip.get(16..20)?.try_into().unwrap();
+    let udp = ip.get(header_len..total_len)?;
+    udp_payload(Ipv4Addr::from(src).into(), Ipv4Addr::from(dst).into(), udp)
+}
+
+fn udp_of_ipv6(ip: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let payload_len = u16::from_be_bytes(ip.get(4..6)?.try_into().unwrap()) as usize;
+    let src: [u8; 16] = ip.get(8..24)?.try_into().unwrap();
+    let dst: [u8; 16] = ip.get(24..40)?.try_into().unwrap();
+    let mut next = *ip.get(6)?;
+    let mut rem = ip.get(40..(40 + payload_len))?;
+    // hop-by-hop, routing and destination options headers. Fragments are skipped.
+    while matches!(next, 0 | 43 | 60) {
+        next = *rem.first()?;
+        let len = (*rem.get(1)? as usize + 1) * 8;
+        rem = rem.get(len..)?;
+    }
+    if next != 17 {
+        return None;
+    }
+
+    udp_payload(Ipv6Addr::from(src).into(), Ipv6Addr::from(dst).into(), rem)
+}
+
+fn udp_payload(src: IpAddr, dst: IpAddr, udp: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let src_port = u16::from_be_bytes(udp.get(0..2)?.try_into().unwrap());
+    let dst_port = u16::from_be_bytes(udp.get(2..4)?.try_into().unwrap());
+    let len = u16::from_be_bytes(udp.get(4..6)?.try_into().unwrap()) as usize;
+    // a capture truncated by its snaplen won't have the whole datagram
+    let payload = udp.get(8..len)?;
+
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        payload,
+    ))
+}
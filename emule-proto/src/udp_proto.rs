@@ -1,14 +1,25 @@
+use crate::kad_id::KadId;
+use crate::obfuscate::{
+    self, KadUdpKey, KeyKind, SendKeys, CRYPT_HEADER_WITHOUT_PADDING, MAGIC_UDP_SYNC_CLIENT,
+};
 use enum_primitive_derive::Primitive;
+use fmt_extra::Hs;
 use num_traits::FromPrimitive;
 use std::borrow::Cow;
 use std::convert::TryInto;
 use std::fmt;
 use std::io;
-use std::io::Read;
+use std::io::Write;
+use strum::IntoStaticStr;
 use thiserror::Error;
 use tracing::{event, Level};
 
-#[derive(Debug, Error)]
+#[cfg(feature = "serde")]
+mod ser;
+#[cfg(feature = "serde")]
+pub use ser::Dissection;
+
+#[derive(Debug, Error, IntoStaticStr)]
 pub enum Error {
     #[error("failed to decompress packed packet: {source}")]
     KadPackedDecompress { source: std::io::Error },
@@ -40,6 +51,13 @@ pub enum Error {
     #[error("res contact size mismatch: have {have}, need {need}")]
     ResContactSizeMismatch { have: usize, need: usize },
 
+    #[error("res contact at offset {offset} too short: have {have}, need {need}")]
+    ResContactTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
     #[error("res size mismatch: have {have}, need {need}")]
     ResSizeMismatch { have: usize, need: usize },
 
@@ -58,8 +76,248 @@ pub enum Error {
     #[error("kad packet needs at least 1 byte")]
     KadPacketTooShort,
 
-    #[error("bootstrap resp too short: have {have}, need {need}")]
-    BootstrapRespTooShort { have: usize, need: usize },
+    #[error("emule packet needs at least 1 byte")]
+    EmulePacketTooShort,
+
+    #[error("emule operation at offset {offset} too short: have {have}, need {need}")]
+    EmuleOpTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("server packet needs at least 1 byte")]
+    ServerPacketTooShort,
+
+    #[error("server operation at offset {offset} too short: have {have}, need {need}")]
+    ServerOpTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("part status at offset {offset} too short: have {have}, need {need}")]
+    PartStatusTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("bootstrap resp contact at offset {offset} too short: have {have}, need {need}")]
+    BootstrapRespContactTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("bootstrap resp at offset {offset} too short: have {have}, need {need}")]
+    BootstrapRespTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("hello at offset {offset} too short: have {have}, need {need}")]
+    HelloTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("hello has {spare} spare bytes after tags, at offset {offset}")]
+    HelloSpareBytes { offset: usize, spare: usize },
+
+    #[error("search request at offset {offset} too short: have {have}, need {need}")]
+    SearchReqTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("search expression at offset {offset} too short: have {have}, need {need}")]
+    SearchExprTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("search expression at offset {offset} nested deeper than {max}")]
+    SearchExprTooDeep { offset: usize, max: usize },
+
+    #[error("search expression op at offset {offset} invalid: {value:#x}")]
+    SearchExprInvalidOp { offset: usize, value: u8 },
+
+    #[error("search expression boolean op at offset {offset} invalid: {value:#x}")]
+    SearchExprInvalidBoolOp { offset: usize, value: u8 },
+
+    #[error("search expression numeric op at offset {offset} invalid: {value:#x}")]
+    SearchExprInvalidNumericOp { offset: usize, value: u8 },
+
+    #[error("search res at offset {offset} too short: have {have}, need {need}")]
+    SearchResTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("search res has {spare} spare bytes after results, at offset {offset}")]
+    SearchResSpareBytes { offset: usize, spare: usize },
+
+    #[error("search result at offset {offset} too short: have {have}, need {need}")]
+    SearchResultTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("publish request at offset {offset} too short: have {have}, need {need}")]
+    PublishReqTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("publish request has {spare} spare bytes, at offset {offset}")]
+    PublishReqSpareBytes { offset: usize, spare: usize },
+
+    #[error("publish res at offset {offset} too short: have {have}, need {need}")]
+    PublishResTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("firewall operation at offset {offset} too short: have {have}, need {need}")]
+    FirewallTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("find buddy at offset {offset} too short: have {have}, need {need}")]
+    FindBuddyTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("callback req at offset {offset} too short: have {have}, need {need}")]
+    CallbackReqTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("pong at offset {offset} too short: have {have}, need {need}")]
+    PongTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("obfuscated packet at offset {offset} too short: have {have}, need {need}")]
+    ObfuscatedTooShort {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error(
+        "obfuscated packet padding of {pad_len} bytes at offset {offset} does not fit in {have} bytes"
+    )]
+    ObfuscatedPaddingTooLarge {
+        offset: usize,
+        have: usize,
+        pad_len: usize,
+    },
+
+    #[error(
+        "obfuscated kad packet missing verify keys at offset {offset}: have {have}, need {need}"
+    )]
+    ObfuscatedVerifyKeysMissing {
+        offset: usize,
+        have: usize,
+        need: usize,
+    },
+
+    #[error("no key could remove obfuscation from packet")]
+    ObfuscatedNoKeyMatched,
+
+    #[error("packed packet decompresses to more than {max} bytes by offset {offset}")]
+    DecompressedTooLarge { offset: usize, max: usize },
+
+    #[error("tag list at offset {offset} has {count} tags, limit is {max}")]
+    TooManyTags {
+        offset: usize,
+        count: usize,
+        max: usize,
+    },
+
+    #[error("string at offset {offset} of {len} bytes exceeds limit of {max}")]
+    StringTooLong {
+        offset: usize,
+        len: usize,
+        max: usize,
+    },
+}
+
+impl Error {
+    /// Shift the `offset` of this error by `base` bytes
+    ///
+    /// Decoders report offsets from the start of the slice they were given, so offsets of errors
+    /// from an operation count from the start of its payload (after the opcode). Decoders of a
+    /// part of a larger buffer use this so offsets count from the start of that buffer.
+    pub fn offset_by(mut self, base: usize) -> Self {
+        match &mut self {
+            Error::ResContactTooShort { offset, .. }
+            | Error::EmuleOpTooShort { offset, .. }
+            | Error::ServerOpTooShort { offset, .. }
+            | Error::PartStatusTooShort { offset, .. }
+            | Error::BootstrapRespContactTooShort { offset, .. }
+            | Error::BootstrapRespTooShort { offset, .. }
+            | Error::HelloTooShort { offset, .. }
+            | Error::HelloSpareBytes { offset, .. }
+            | Error::SearchReqTooShort { offset, .. }
+            | Error::SearchExprTooShort { offset, .. }
+            | Error::SearchResTooShort { offset, .. }
+            | Error::SearchResSpareBytes { offset, .. }
+            | Error::SearchResultTooShort { offset, .. }
+            | Error::PublishReqTooShort { offset, .. }
+            | Error::PublishReqSpareBytes { offset, .. }
+            | Error::PublishResTooShort { offset, .. }
+            | Error::FirewallTooShort { offset, .. }
+            | Error::FindBuddyTooShort { offset, .. }
+            | Error::CallbackReqTooShort { offset, .. }
+            | Error::PongTooShort { offset, .. }
+            | Error::SearchExprTooDeep { offset, .. }
+            | Error::SearchExprInvalidOp { offset, .. }
+            | Error::SearchExprInvalidBoolOp { offset, .. }
+            | Error::SearchExprInvalidNumericOp { offset, .. }
+            | Error::ObfuscatedTooShort { offset, .. }
+            | Error::ObfuscatedPaddingTooLarge { offset, .. }
+            | Error::ObfuscatedVerifyKeysMissing { offset, .. }
+            | Error::DecompressedTooLarge { offset, .. }
+            | Error::TooManyTags { offset, .. }
+            | Error::StringTooLong { offset, .. } => *offset += base,
+            _ => {}
+        }
+        self
+    }
+
+    /// The input was rejected by a `DecodeLimits` bound rather than being malformed
+    pub fn is_limit_exceeded(&self) -> bool {
+        matches!(
+            self,
+            Error::DecompressedTooLarge { .. }
+                | Error::TooManyTags { .. }
+                | Error::StringTooLong { .. }
+        )
+    }
+
+    /// Name of the variant, without its fields. Useful for grouping errors
+    pub fn variant_name(&self) -> &'static str {
+        self.into()
+    }
 }
 
 /// The first byte of a emule/kad udp packet _may_ be one of these bytes, which establishes the
@@ -70,6 +328,8 @@ pub enum Error {
 #[repr(u8)]
 pub enum UdpProto {
     Emule = 0xC5,
+    /// ed2k server udp, `ServerOpCode` follows
+    Edonkey = 0xE3,
     /// uncompress [2..] and then process as `KademliaHeader` (op code is uncompressed)
     KademliaPacked = 0xE5,
     /// `KadOpCode` follows, `Operation` represents the contents
@@ -79,24 +339,157 @@ pub enum UdpProto {
     Packed = 0xD4,
 }
 
+impl UdpProto {
+    /// `true` if a packet starting with `b` is never obfuscated, as in emule's
+    /// `DecryptReceivedClient`. `Edonkey` isn't included: emule doesn't avoid it when picking the
+    /// marker of an obfuscated ed2k packet.
+    pub(crate) fn is_plain_marker(b: u8) -> bool {
+        matches!(
+            UdpProto::from_u8(b),
+            Some(
+                UdpProto::Emule
+                    | UdpProto::KademliaPacked
+                    | UdpProto::KademliaHeader
+                    | UdpProto::UdpReserved1
+                    | UdpProto::UdpReserved2
+                    | UdpProto::Packed
+            )
+        )
+    }
+}
+
 /// A complete UDP packet as recieved over the network
 pub struct Packet<'a> {
-    raw: &'a [u8],
+    raw: Cow<'a, [u8]>,
+    limits: DecodeLimits,
+}
+
+/// Bounds applied while decoding a `Packet`, so hostile input can't make us do unbounded work
+///
+/// Exceeding one fails decoding with an error for which `Error::is_limit_exceeded()` is true.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Largest payload a `UdpProto::KademliaPacked` packet may inflate to
+    pub max_decompressed_len: usize,
+    /// Most tags allowed in a single `TagList` or `TagList32`
+    pub max_tags: usize,
+    /// Longest tag name, string tag value or search expression string
+    pub max_string_len: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            // far more than emule ever sends in a single packet
+            max_decompressed_len: 64 * 1024,
+            max_tags: 256,
+            max_string_len: 4096,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// `offset` is where `s` starts
+    fn check_string(&self, s: &[u8], offset: usize) -> Result<(), Error> {
+        if s.len() > self.max_string_len {
+            return Err(Error::StringTooLong {
+                offset,
+                len: s.len(),
+                max: self.max_string_len,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// `raw` is the whole tag list, offsets count from its start
+    fn check_tags(&self, count: usize, tags: TagListIter<'_>, raw: &[u8]) -> Result<(), Error> {
+        if count > self.max_tags {
+            return Err(Error::TooManyTags {
+                offset: 0,
+                count,
+                max: self.max_tags,
+            });
+        }
+
+        for tag in tags {
+            let tag = tag?;
+            self.check_string(tag.name(), offset_in(tag.name(), raw))?;
+            if let TagValue::String_(v) = tag.value() {
+                self.check_string(v, offset_in(v, raw))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Offset of `part` from the start of `whole`, which it is a sub-slice of
+fn offset_in(part: &[u8], whole: &[u8]) -> usize {
+    (part.as_ptr() as usize).saturating_sub(whole.as_ptr() as usize)
 }
 
+/// Keys of the receiving node, used to remove obfuscation from a packet
 pub struct Keys<'a> {
-    pub kad_id: &'a [u8],
+    /// our kad id
+    pub kad_id: KadId,
+    /// our (ed2k) user hash
     pub user_hash: &'a [u8],
-    pub source_key: Option<&'a [u8]>,
+    /// public ip of the node that sent the packet, combined with `user_hash`
+    pub source_ip: std::net::Ipv4Addr,
+    /// udp verify key we handed out to the node that sent the packet (see
+    /// `obfuscate::udp_verify_key()`)
+    pub source_key: Option<u32>,
+}
+
+/// Result of removing obfuscation from a `Packet`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deobfuscated {
+    /// the key that the remote used to obfuscate the packet
+    pub key: KeyKind,
+    /// `(receiver_verify_key, sender_verify_key)`, only included in kad packets
+    pub verify_keys: Option<(u32, u32)>,
+}
+
+impl Deobfuscated {
+    /// Whether the remote obfuscated with the verify key we handed it, which proves it has seen
+    /// our replies at `source_ip`. emule only marks a contact's ip as verified after a hello with
+    /// a valid receiver key.
+    pub fn is_receiver_key_valid(&self, private_key: u32, source_ip: std::net::Ipv4Addr) -> bool {
+        let expected = obfuscate::udp_verify_key(private_key, source_ip);
+        matches!(self.verify_keys, Some((r, _)) if r == expected)
+    }
+
+    /// The key the remote handed us, to use when sending to it while our public ip is
+    /// `public_ip`
+    pub fn sender_key(&self, public_ip: std::net::Ipv4Addr) -> Option<KadUdpKey> {
+        match self.verify_keys {
+            Some((_, key)) if key != 0 => Some(KadUdpKey { key, ip: public_ip }),
+            _ => None,
+        }
+    }
 }
 
 impl<'a> Packet<'a> {
     pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
-        if raw.len() < 1 {
+        if raw.is_empty() {
             Err(Error::PacketTooShort)?;
         }
 
-        Ok(Packet { raw: raw.into() })
+        Ok(Packet {
+            raw: raw.into(),
+            limits: DecodeLimits::default(),
+        })
+    }
+
+    /// Decode with `limits` instead of `DecodeLimits::default()`
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
     }
 
     pub fn udp_proto(&self) -> Option<UdpProto> {
@@ -104,18 +497,20 @@ impl<'a> Packet<'a> {
     }
 
     pub fn is_packed(&self) -> bool {
-        if let Some(UdpProto::KademliaPacked) = self.udp_proto() {
-            true
-        } else {
-            false
-        }
+        matches!(self.udp_proto(), Some(UdpProto::KademliaPacked))
     }
 
     pub fn kind(&self) -> Result<Kind<'_>, Error> {
         match self.udp_proto() {
-            Some(UdpProto::KademliaHeader) => {
-                Ok(Kind::Kad(KadPacket::from_cow((&self.raw[1..]).into())?))
+            Some(UdpProto::KademliaHeader) => Ok(Kind::Kad(
+                KadPacket::from_cow((&self.raw[1..]).into())?.with_limits(self.limits),
+            )),
+            Some(UdpProto::Emule) => {
+                Ok(Kind::Emule(EmulePacket::from_cow((&self.raw[1..]).into())?))
             }
+            Some(UdpProto::Edonkey) => Ok(Kind::Server(
+                ServerPacket::from_cow((&self.raw[1..]).into())?.with_limits(self.limits),
+            )),
             Some(UdpProto::KademliaPacked) => {
                 // [0] is set to KademliaHeader
                 // [1] is set to self.raw[1]
@@ -124,47 +519,143 @@ impl<'a> Packet<'a> {
                     return Err(Error::PacketTooShort);
                 }
 
-                let mut z = flate2::bufread::ZlibDecoder::new(&self.raw[2..]);
+                let max = self.limits.max_decompressed_len;
                 // TODO: we should collect some stats to figure out if this sizing makes any sense
-                let mut out = Vec::with_capacity(self.raw.len() * 10 + 300);
+                let mut out =
+                    Vec::with_capacity((self.raw.len() * 10 + 300).min(max.saturating_add(1)));
                 out.push(self.raw[1]);
-                z.read_to_end(&mut out)
-                    .map_err(|source| Error::KadPackedDecompress { source })?;
+                inflate(
+                    &mut flate2::Decompress::new(true),
+                    &self.raw[2..],
+                    max,
+                    &mut out,
+                )
+                .map_err(|e| e.offset_by(2))?;
 
                 event!(Level::DEBUG, "PACKED {} -> {}", self.raw.len(), out.len());
 
-                Ok(Kind::Kad(KadPacket::from_cow(out.into())?))
+                Ok(Kind::Kad(
+                    KadPacket::from_cow(out.into())?.with_limits(self.limits),
+                ))
             }
             None => Err(Error::UnrecognizedUdpProto),
             Some(udp_proto) => Err(Error::UnhandledUdpProto { udp_proto }),
         }
     }
 
-    // modify the packet in place to remove the obfuscation
-    pub fn decrypt(&mut self, _keys: &Keys) {
-        match self.udp_proto() {
-            None => {
-                // might be an encrypted packet
+    /// Remove obfuscation from the packet, replacing its content with the decrypted packet.
+    ///
+    /// packets are obfuscated via a couple types of keys:
+    ///  - Kad packets using the KadId of the recieving node as the key
+    ///  - ed2k packets using a "user hash" as the basis for the key
+    ///  - kad packets using a per-source ip key sent by the source node
+    ///
+    /// all keys are generated with md5 & RC4 is used as encryption. Like emule, we start with the
+    /// key hinted at by the marker byte and then try the others.
+    ///
+    /// Returns `Ok(None)` if the packet was not obfuscated. Packets starting with
+    /// `UdpProto::Edonkey` may be either, and are only treated as plain if no key matches.
+    pub fn decrypt(&mut self, keys: &Keys) -> Result<Option<Deobfuscated>, Error> {
+        if UdpProto::is_plain_marker(self.raw[0]) {
+            // non-obfuscated packet
+            return Ok(None);
+        }
+        let maybe_plain = self.udp_proto() == Some(UdpProto::Edonkey);
+
+        // might be an encrypted packet
+        let raw = &self.raw[..];
+        if raw.len() <= CRYPT_HEADER_WITHOUT_PADDING {
+            if maybe_plain {
+                return Ok(None);
+            }
+            return Err(Error::ObfuscatedTooShort {
+                offset: 0,
+                have: raw.len(),
+                need: CRYPT_HEADER_WITHOUT_PADDING + 1,
+            });
+        }
+
+        let random_key_part = [raw[1], raw[2]];
+        let mut key = KeyKind::from_marker(raw[0]);
+        for _ in 0..3 {
+            let try_key = key;
+            key = key.next();
+
+            let mut rc4 = match try_key {
+                KeyKind::KadId => obfuscate::kad_id_key(keys.kad_id, random_key_part),
+                KeyKind::UserHash => {
+                    obfuscate::user_hash_key(keys.user_hash, keys.source_ip, random_key_part)
+                }
+                KeyKind::VerifyKey => match keys.source_key {
+                    Some(source_key) => obfuscate::verify_key_key(source_key, random_key_part),
+                    None => continue,
+                },
+            };
+
+            let mut magic: [u8; 4] = raw[3..7].try_into().unwrap();
+            rc4.apply(&mut magic);
+            if u32::from_le_bytes(magic) != MAGIC_UDP_SYNC_CLIENT {
+                continue;
             }
-            Some(_v) => {
-                // non-obfuscated packet
-                return;
+
+            let mut pad_len = [raw[7]];
+            rc4.apply(&mut pad_len);
+            let pad_len = pad_len[0] as usize;
+            let mut offs = CRYPT_HEADER_WITHOUT_PADDING + pad_len;
+            if raw.len() <= offs {
+                return Err(Error::ObfuscatedPaddingTooLarge {
+                    offset: 7,
+                    have: raw.len(),
+                    pad_len,
+                });
             }
+            rc4.skip(pad_len);
+
+            let verify_keys = if try_key.is_kad() {
+                if raw.len() <= offs + 8 {
+                    return Err(Error::ObfuscatedVerifyKeysMissing {
+                        offset: offs,
+                        have: raw.len(),
+                        need: offs + 8 + 1,
+                    });
+                }
+
+                let mut vk: [u8; 8] = raw[offs..(offs + 8)].try_into().unwrap();
+                rc4.apply(&mut vk);
+                offs += 8;
+                Some((
+                    u32::from_le_bytes(vk[..4].try_into().unwrap()),
+                    u32::from_le_bytes(vk[4..].try_into().unwrap()),
+                ))
+            } else {
+                None
+            };
+
+            let mut out = raw[offs..].to_vec();
+            rc4.apply(&mut out);
+            event!(
+                Level::DEBUG,
+                "OBFUSCATED {:?} {} -> {}",
+                try_key,
+                raw.len(),
+                out.len()
+            );
+            self.raw = out.into();
+
+            return Ok(Some(Deobfuscated {
+                key: try_key,
+                verify_keys,
+            }));
         }
 
-        todo!();
-        // packets are obfuscated via a couple types of keys:
-        //  - Kad packets using the KadId of the recieving node as the key
-        //  - ed2k packets using a "user hash" as the basis for the key
-        //  - kad packets using a per-source ip key sent by the source node
-        //
-        // all keys are generated with md5 & RC4 is used as encryption
-        //
         // TODO: consider if we can be sneaky and not require the keys
-        // TODO: consider allowing arbitrary numbers of potential keys to be provided
         // TODO: consider if the nature of the "check" (validating a few bytes) might result in
         // multiple keys being acceptable. Consider how our API should handle this and if it's
         // something we can be cheeky with.
+        if maybe_plain {
+            return Ok(None);
+        }
+        Err(Error::ObfuscatedNoKeyMatched)
     }
 }
 
@@ -176,34 +667,235 @@ impl<'a> fmt::Debug for Packet<'a> {
     }
 }
 
+/// Inflate the zlib stream `input` onto the end of `out`, failing if it produces more than `max`
+/// bytes. The offset of that error is how much of `input` had been consumed.
+fn inflate(
+    z: &mut flate2::Decompress,
+    input: &[u8],
+    max: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let start = out.len();
+    let mut in_pos = 0;
+    loop {
+        let produced = out.len() - start;
+        if produced > max {
+            return Err(Error::DecompressedTooLarge {
+                offset: in_pos,
+                max,
+            });
+        }
+        if out.len() == out.capacity() {
+            // one byte past the limit lets us notice it being exceeded
+            out.reserve_exact(
+                produced
+                    .max(input.len() * 4)
+                    .max(256)
+                    .min(max.saturating_add(1) - produced),
+            );
+        }
+
+        let (before_in, before_out) = (z.total_in(), z.total_out());
+        let status = z
+            .decompress_vec(&input[in_pos..], out, flate2::FlushDecompress::None)
+            .map_err(|e| Error::KadPackedDecompress { source: e.into() })?;
+        in_pos += (z.total_in() - before_in) as usize;
+
+        if status == flate2::Status::StreamEnd {
+            if out.len() - start > max {
+                return Err(Error::DecompressedTooLarge {
+                    offset: in_pos,
+                    max,
+                });
+            }
+            return Ok(());
+        }
+        if z.total_in() == before_in && z.total_out() == before_out {
+            return Err(Error::KadPackedDecompress {
+                source: io::ErrorKind::UnexpectedEof.into(),
+            });
+        }
+    }
+}
+
+/// Reusable state for decoding many packets, one after another
+///
+/// `Packet::kind()` allocates an output buffer and sets up a new inflater for every
+/// `UdpProto::KademliaPacked` packet. `DecodeContext::kind()` reuses both, returning a `Kind` that
+/// borrows the context until the next packet is decoded.
+#[derive(Debug)]
+pub struct DecodeContext {
+    inflater: flate2::Decompress,
+    buf: Vec<u8>,
+}
+
+impl Default for DecodeContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DecodeContext {
+    pub fn new() -> Self {
+        Self {
+            inflater: flate2::Decompress::new(true),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Same as `packet.kind()`, but packed packets are decompressed into the context's buffer
+    pub fn kind<'a>(&'a mut self, packet: &'a Packet<'_>) -> Result<Kind<'a>, Error> {
+        if !packet.is_packed() {
+            return packet.kind();
+        }
+        if packet.raw.len() < 2 {
+            return Err(Error::PacketTooShort);
+        }
+
+        self.inflater.reset(true);
+        self.buf.clear();
+        self.buf.push(packet.raw[1]);
+        inflate(
+            &mut self.inflater,
+            &packet.raw[2..],
+            packet.limits.max_decompressed_len,
+            &mut self.buf,
+        )
+        .map_err(|e| e.offset_by(2))?;
+
+        event!(
+            Level::DEBUG,
+            "PACKED {} -> {}",
+            packet.raw.len(),
+            self.buf.len()
+        );
+
+        Ok(Kind::Kad(
+            KadPacket::from_cow(self.buf[..].into())?.with_limits(packet.limits),
+        ))
+    }
+}
+
 #[derive(Debug)]
 pub enum Kind<'a> {
     Kad(KadPacket<'a>),
+    Emule(EmulePacket<'a>),
+    Server(ServerPacket<'a>),
 }
 
 pub struct KadPacket<'a> {
     raw: Cow<'a, [u8]>,
+    limits: DecodeLimits,
 }
 
 impl<'a> KadPacket<'a> {
     pub fn from_cow(raw: Cow<'a, [u8]>) -> Result<Self, Error> {
-        if raw.len() < 1 {
+        if raw.is_empty() {
             return Err(Error::KadPacketTooShort);
         }
 
-        Ok(Self { raw })
+        Ok(Self {
+            raw,
+            limits: DecodeLimits::default(),
+        })
+    }
+
+    /// Decode with `limits` instead of `DecodeLimits::default()`
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
     }
 
     pub fn opcode(&self) -> Option<KadOpCode> {
         KadOpCode::from_u8(self.raw[0])
     }
 
-    pub fn operation(&self) -> Option<Operation<'_>> {
-        match self.opcode() {
-            Some(KadOpCode::BootstrapResp) => Some(Operation::BootstrapResp(
-                BootstrapResp::from_slice(&self.raw[1..]).unwrap(),
-            )),
-            Some(KadOpCode::Req) => Some(Operation::Req(Req::from_slice(&self.raw[1..]).unwrap())),
+    /// Kad protocol revision of the opcode, see `KadOpCode::version`
+    pub fn version(&self) -> Option<KadVersion> {
+        self.opcode().map(|o| o.version())
+    }
+
+    /// Like `operation()`, but returns the parse error instead of logging it
+    ///
+    /// `Ok(None)` means the opcode is not one we handle.
+    pub fn try_operation(&self) -> Result<Option<Operation<'_>>, Error> {
+        let data = &self.raw[1..];
+        let op = match self.opcode() {
+            Some(KadOpCode::BootstrapResp) => {
+                BootstrapResp::from_slice(data).map(Operation::BootstrapResp)
+            }
+            Some(KadOpCode::Req) => Req::from_slice(data).map(Operation::Req),
+            Some(KadOpCode::Res) => Res::from_slice(data).map(Operation::Res),
+            Some(KadOpCode::HelloReq) => Hello::from_slice(data).map(Operation::HelloReq),
+            Some(KadOpCode::HelloRes) => Hello::from_slice(data).map(Operation::HelloRes),
+            Some(KadOpCode::HelloResAck) => {
+                HelloResAck::from_slice(data).map(Operation::HelloResAck)
+            }
+            Some(KadOpCode::SearchKeyReq) => {
+                SearchKeyReq::from_slice(data).map(Operation::SearchKeyReq)
+            }
+            Some(KadOpCode::SearchSourceReq) => {
+                SearchSourceReq::from_slice(data).map(Operation::SearchSourceReq)
+            }
+            Some(KadOpCode::SearchNotesReq) => {
+                SearchNotesReq::from_slice(data).map(Operation::SearchNotesReq)
+            }
+            Some(KadOpCode::SearchRes) => SearchRes::from_slice(data).map(Operation::SearchRes),
+            Some(KadOpCode::PublishKeyReq) => {
+                PublishKeyReq::from_slice(data).map(Operation::PublishKeyReq)
+            }
+            Some(KadOpCode::PublishSourceReq) => {
+                PublishReq::from_slice(data).map(Operation::PublishSourceReq)
+            }
+            Some(KadOpCode::PublishNotesReq) => {
+                PublishReq::from_slice(data).map(Operation::PublishNotesReq)
+            }
+            Some(KadOpCode::PublishRes) => PublishRes::from_slice(data).map(Operation::PublishRes),
+            Some(KadOpCode::PublishResAck) => Ok(Operation::PublishResAck),
+            Some(KadOpCode::FirewalledReqV1) => {
+                FirewalledReq::from_slice(data).map(Operation::FirewalledReqV1)
+            }
+            Some(KadOpCode::Firewalled2ReqV1) => {
+                Firewalled2Req::from_slice(data).map(Operation::Firewalled2ReqV1)
+            }
+            Some(KadOpCode::FirewalledResV1) => {
+                FirewalledRes::from_slice(data).map(Operation::FirewalledResV1)
+            }
+            Some(KadOpCode::FirewalledAckResV1) => Ok(Operation::FirewalledAckResV1),
+            Some(KadOpCode::FindBuddyReqV1) => {
+                FindBuddy::from_slice(data).map(Operation::FindBuddyReqV1)
+            }
+            Some(KadOpCode::FindBuddyResV1) => {
+                FindBuddy::from_slice(data).map(Operation::FindBuddyResV1)
+            }
+            Some(KadOpCode::CallbackReqV1) => {
+                CallbackReq::from_slice(data).map(Operation::CallbackReqV1)
+            }
+            Some(KadOpCode::FirewallUdp) => {
+                FirewallUdp::from_slice(data).map(Operation::FirewallUdp)
+            }
+            Some(KadOpCode::Ping) => Ok(Operation::Ping),
+            Some(KadOpCode::Pong) => Pong::from_slice(data).map(Operation::Pong),
+            Some(KadOpCode::BootstrapReqV0) => contact_v0(data).map(Operation::BootstrapReqV0),
+            Some(KadOpCode::BootstrapResV0) => {
+                BootstrapResV0::from_slice(data).map(Operation::BootstrapResV0)
+            }
+            Some(KadOpCode::HelloReqV0) => contact_v0(data).map(Operation::HelloReqV0),
+            Some(KadOpCode::HelloResV0) => contact_v0(data).map(Operation::HelloResV0),
+            Some(KadOpCode::ReqV0) => Req::from_slice(data).map(Operation::ReqV0),
+            Some(KadOpCode::ResV0) => Res::from_slice(data).map(Operation::ResV0),
+            Some(KadOpCode::SearchReqV1) => {
+                SearchReqV1::from_slice(data).map(Operation::SearchReqV1)
+            }
+            Some(KadOpCode::SearchResV1) => {
+                SearchResV1::from_slice(data).map(Operation::SearchResV1)
+            }
+            Some(KadOpCode::PublishReqV1) => {
+                PublishKeyReq::from_slice(data).map(Operation::PublishReqV1)
+            }
+            Some(KadOpCode::PublishResV1) => {
+                PublishRes::from_slice(data).map(Operation::PublishResV1)
+            }
             // someone sent us this while we were bootstrap scannning
             opcode => {
                 event!(
@@ -211,6 +903,19 @@ impl<'a> KadPacket<'a> {
                     "packet included unhandled opcode {:?}",
                     opcode
                 );
+                return Ok(None);
+            }
+        }?;
+
+        op.check_limits(&self.limits)?;
+        Ok(Some(op))
+    }
+
+    pub fn operation(&self) -> Option<Operation<'_>> {
+        match self.try_operation() {
+            Ok(op) => op,
+            Err(e) => {
+                event!(Level::ERROR, "failed to parse {:?}: {}", self.opcode(), e);
                 None
             }
         }
@@ -225,33 +930,210 @@ impl<'a> fmt::Debug for KadPacket<'a> {
     }
 }
 
-/// If `UdpProto::Kad` is the first byte, this is the second byte
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Primitive)]
-#[repr(u8)]
-pub enum KadOpCode {
-    BootstrapReqV0 = 0x00,
-    BootstrapReq = 0x01,
-    BootstrapResV0 = 0x08,
-    BootstrapResp = 0x09,
-    HelloReqV0 = 0x10,
-    HelloReq = 0x11,
-    HelloResV0 = 0x18,
-    HelloRes = 0x19,
-    ReqV0 = 0x20,
-    Req = 0x21,
-    HelloResAck = 0x22,
-    ResV0 = 0x28,
-    Res = 0x29,
-    SearchReqV1 = 0x30,
-    SearchNotesReqV1 = 0x32,
-    SearchKeyReq = 0x33,
-    SearchSourceReq = 0x34,
-    SearchNotesReq = 0x35,
+pub struct EmulePacket<'a> {
+    raw: Cow<'a, [u8]>,
+}
 
-    SearchResV1 = 0x38,
-    SearchNotesResV1 = 0x3A,
-    SearchRes = 0x3b,
-    PublishReqV1 = 0x40,
+impl<'a> EmulePacket<'a> {
+    pub fn from_cow(raw: Cow<'a, [u8]>) -> Result<Self, Error> {
+        if raw.is_empty() {
+            return Err(Error::EmulePacketTooShort);
+        }
+
+        Ok(Self { raw })
+    }
+
+    pub fn opcode(&self) -> Option<EmuleOpCode> {
+        EmuleOpCode::from_u8(self.raw[0])
+    }
+
+    /// Like `operation()`, but returns the parse error instead of logging it
+    ///
+    /// `Ok(None)` means the opcode is not one we handle.
+    pub fn try_operation(&self) -> Result<Option<EmuleOperation<'_>>, Error> {
+        let data = &self.raw[1..];
+        let op = match self.opcode() {
+            Some(EmuleOpCode::ReAskFilePing) => {
+                ReAskFilePing::from_slice(data).map(EmuleOperation::ReAskFilePing)
+            }
+            Some(EmuleOpCode::ReAskAck) => ReAskAck::from_slice(data).map(EmuleOperation::ReAskAck),
+            Some(EmuleOpCode::FileNotFound) => Ok(EmuleOperation::FileNotFound),
+            Some(EmuleOpCode::QueueFull) => Ok(EmuleOperation::QueueFull),
+            Some(EmuleOpCode::ReAskCallBackUdp) => {
+                ReAskCallBackUdp::from_slice(data).map(EmuleOperation::ReAskCallBackUdp)
+            }
+            Some(EmuleOpCode::DirectCallbackReq) => {
+                DirectCallbackReq::from_slice(data).map(EmuleOperation::DirectCallbackReq)
+            }
+            Some(EmuleOpCode::PortTest) => match data {
+                [value] => Ok(EmuleOperation::PortTest { value: *value }),
+                _ => Err(Error::EmuleOpTooShort {
+                    offset: 0,
+                    have: data.len(),
+                    need: 1,
+                }),
+            },
+            opcode => {
+                event!(
+                    Level::ERROR,
+                    "emule packet included unhandled opcode {:?}",
+                    opcode
+                );
+                return Ok(None);
+            }
+        };
+
+        op.map(Some)
+    }
+
+    pub fn operation(&self) -> Option<EmuleOperation<'_>> {
+        match self.try_operation() {
+            Ok(op) => op,
+            Err(e) => {
+                event!(Level::ERROR, "failed to parse {:?}: {}", self.opcode(), e);
+                None
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for EmulePacket<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("EmulePacket")
+            .field("operation", &self.operation())
+            .finish()
+    }
+}
+
+pub struct ServerPacket<'a> {
+    raw: Cow<'a, [u8]>,
+    limits: DecodeLimits,
+}
+
+impl<'a> ServerPacket<'a> {
+    pub fn from_cow(raw: Cow<'a, [u8]>) -> Result<Self, Error> {
+        if raw.is_empty() {
+            return Err(Error::ServerPacketTooShort);
+        }
+
+        Ok(Self {
+            raw,
+            limits: DecodeLimits::default(),
+        })
+    }
+
+    /// Decode with `limits` instead of `DecodeLimits::default()`
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn opcode(&self) -> Option<ServerOpCode> {
+        ServerOpCode::from_u8(self.raw[0])
+    }
+
+    /// Like `operation()`, but returns the parse error instead of logging it
+    ///
+    /// `Ok(None)` means the opcode is not one we handle.
+    pub fn try_operation(&self) -> Result<Option<ServerOperation<'_>>, Error> {
+        let data = &self.raw[1..];
+        let op =
+            match self.opcode() {
+                Some(ServerOpCode::GlobSearchReq) => SearchExpr::from_slice(data)
+                    .map(|(expr, _)| ServerOperation::GlobSearchReq(expr)),
+                Some(ServerOpCode::GlobSearchReq2) => SearchExpr::from_slice(data)
+                    .map(|(expr, _)| ServerOperation::GlobSearchReq2(expr)),
+                Some(ServerOpCode::GlobSearchRes) => {
+                    GlobSearchRes::from_slice(data).map(ServerOperation::GlobSearchRes)
+                }
+                Some(ServerOpCode::GlobGetSources) => {
+                    GetSources::from_slice(data).map(ServerOperation::GlobGetSources)
+                }
+                Some(ServerOpCode::GlobGetSources2) => {
+                    GetSources2::from_slice(data).map(ServerOperation::GlobGetSources2)
+                }
+                Some(ServerOpCode::GlobFoundSources) => {
+                    FoundSources::from_slice(data).map(ServerOperation::GlobFoundSources)
+                }
+                Some(ServerOpCode::GlobServStatReq) => match data.try_into() {
+                    Ok(c) => Ok(ServerOperation::GlobServStatReq {
+                        challenge: u32::from_le_bytes(c),
+                    }),
+                    Err(_) => Err(Error::ServerOpTooShort {
+                        offset: 0,
+                        have: data.len(),
+                        need: 4,
+                    }),
+                },
+                Some(ServerOpCode::GlobServStatRes) => {
+                    ServStatRes::from_slice(data).map(ServerOperation::GlobServStatRes)
+                }
+                Some(ServerOpCode::ServerDescReq) => Ok(ServerOperation::ServerDescReq {
+                    challenge: data.try_into().ok().map(u32::from_le_bytes),
+                }),
+                Some(ServerOpCode::ServerDescRes) => {
+                    ServerDescRes::from_slice(data).map(ServerOperation::ServerDescRes)
+                }
+                opcode => {
+                    event!(
+                        Level::ERROR,
+                        "server packet included unhandled opcode {:?}",
+                        opcode
+                    );
+                    return Ok(None);
+                }
+            }?;
+
+        op.check_limits(&self.limits, data)?;
+        Ok(Some(op))
+    }
+
+    pub fn operation(&self) -> Option<ServerOperation<'_>> {
+        match self.try_operation() {
+            Ok(op) => op,
+            Err(e) => {
+                event!(Level::ERROR, "failed to parse {:?}: {}", self.opcode(), e);
+                None
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for ServerPacket<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ServerPacket")
+            .field("operation", &self.operation())
+            .finish()
+    }
+}
+
+/// If `UdpProto::Kad` is the first byte, this is the second byte
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Primitive)]
+#[repr(u8)]
+pub enum KadOpCode {
+    BootstrapReqV0 = 0x00,
+    BootstrapReq = 0x01,
+    BootstrapResV0 = 0x08,
+    BootstrapResp = 0x09,
+    HelloReqV0 = 0x10,
+    HelloReq = 0x11,
+    HelloResV0 = 0x18,
+    HelloRes = 0x19,
+    ReqV0 = 0x20,
+    Req = 0x21,
+    HelloResAck = 0x22,
+    ResV0 = 0x28,
+    Res = 0x29,
+    SearchReqV1 = 0x30,
+    SearchNotesReqV1 = 0x32,
+    SearchKeyReq = 0x33,
+    SearchSourceReq = 0x34,
+    SearchNotesReq = 0x35,
+
+    SearchResV1 = 0x38,
+    SearchNotesResV1 = 0x3A,
+    SearchRes = 0x3b,
+    PublishReqV1 = 0x40,
     PublishNotesReqV0 = 0x42,
     PublishKeyReq = 0x43,
     PublishSourceReq = 0x44,
@@ -274,13 +1156,196 @@ pub enum KadOpCode {
     FirewallUdp = 0x62,
 }
 
+/// Revision of the kad protocol that introduced an opcode
+///
+/// Current clients (kad v2) still use the `V1` firewall & buddy opcodes, but not the `V0` ones
+/// or the `V1` search & publish ones.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum KadVersion {
+    V0,
+    V1,
+    V2,
+}
+
+impl KadOpCode {
+    pub fn version(&self) -> KadVersion {
+        use KadOpCode::*;
+        match self {
+            BootstrapReqV0 | BootstrapResV0 | HelloReqV0 | HelloResV0 | ReqV0 | ResV0
+            | PublishNotesReqV0 | PublishNotesResV0 => KadVersion::V0,
+            SearchReqV1 | SearchNotesReqV1 | SearchResV1 | SearchNotesResV1 | PublishReqV1
+            | PublishResV1 | FirewalledReqV1 | FindBuddyReqV1 | CallbackReqV1
+            | Firewalled2ReqV1 | FirewalledResV1 | FirewalledAckResV1 | FindBuddyResV1 => {
+                KadVersion::V1
+            }
+            _ => KadVersion::V2,
+        }
+    }
+
+    /// Only sent by clients that predate kad v2
+    pub fn is_legacy(&self) -> bool {
+        match self.version() {
+            KadVersion::V0 => true,
+            KadVersion::V1 => matches!(
+                self,
+                KadOpCode::SearchReqV1
+                    | KadOpCode::SearchNotesReqV1
+                    | KadOpCode::SearchResV1
+                    | KadOpCode::SearchNotesResV1
+                    | KadOpCode::PublishReqV1
+                    | KadOpCode::PublishResV1
+            ),
+            KadVersion::V2 => false,
+        }
+    }
+}
+
 /// If `UdpProto::Emule` is the first byte, this is the second byte
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Primitive)]
 #[repr(u8)]
 pub enum EmuleOpCode {
-    ReAskCallBackUdp = 0,
+    ReAskFilePing = 0x90,
+    ReAskAck = 0x91,
+    FileNotFound = 0x92,
+    QueueFull = 0x93,
+    ReAskCallBackUdp = 0x94,
+    DirectCallbackReq = 0x95,
+    PortTest = 0xFE,
+}
+
+/// Representation of an entire `EmuleOpCode` with the associated data, see `Operation`
+#[derive(Debug)]
+pub enum EmuleOperation<'a> {
+    ReAskFilePing(ReAskFilePing<'a>),
+    ReAskAck(ReAskAck<'a>),
+    /// has no content
+    FileNotFound,
+    /// has no content
+    QueueFull,
+    ReAskCallBackUdp(ReAskCallBackUdp<'a>),
+    DirectCallbackReq(DirectCallbackReq<'a>),
+    /// sent by the emule port test service
+    PortTest {
+        value: u8,
+    },
+}
+
+/// If `UdpProto::Edonkey` is the first byte, this is the second byte
+///
+/// These are the udp messages exchanged between clients and ed2k servers. Servers listen for them
+/// on their tcp port + `SERVER_UDP_PORT_OFFSET`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Primitive)]
+#[repr(u8)]
+pub enum ServerOpCode {
+    /// newer `GlobSearchReq` variant, not parsed
+    GlobSearchReq3 = 0x90,
+    /// `GlobSearchReq` which may include 64-bit numeric comparisons
+    GlobSearchReq2 = 0x92,
+    /// `GlobGetSources` including file sizes, for servers with `SRV_UDPFLG_EXT_GETSOURCES2`
+    GlobGetSources2 = 0x94,
+    GlobServStatReq = 0x96,
+    GlobServStatRes = 0x97,
+    GlobSearchReq = 0x98,
+    GlobSearchRes = 0x99,
+    GlobGetSources = 0x9A,
+    GlobFoundSources = 0x9B,
+    GlobCallbackReq = 0x9C,
+    InvalidLowId = 0x9E,
+    ServerListReq = 0xA0,
+    ServerListRes = 0xA1,
+    ServerDescReq = 0xA2,
+    ServerDescRes = 0xA3,
+    ServerListReq2 = 0xA4,
+}
+
+/// Representation of an entire `ServerOpCode` with the associated data, see `Operation`
+#[derive(Debug)]
+pub enum ServerOperation<'a> {
+    GlobSearchReq(SearchExpr<'a>),
+    GlobSearchReq2(SearchExpr<'a>),
+    GlobSearchRes(GlobSearchRes<'a>),
+    GlobGetSources(GetSources<'a>),
+    GlobGetSources2(GetSources2<'a>),
+    GlobFoundSources(FoundSources<'a>),
+    /// `challenge` is echoed in the `GlobServStatRes`, see `GLOBSERVSTAT_CHALLENGE`
+    GlobServStatReq {
+        challenge: u32,
+    },
+    GlobServStatRes(ServStatRes<'a>),
+    /// Old clients send no challenge, see `SERVER_DESC_CHALLENGE`
+    ServerDescReq {
+        challenge: Option<u32>,
+    },
+    ServerDescRes(ServerDescRes<'a>),
+}
+
+impl<'a> ServerOperation<'a> {
+    /// Check the tags and search strings carried by the operation against `limits`
+    ///
+    /// `raw` is the payload (after the opcode) the operation was decoded from, error offsets count
+    /// from its start.
+    pub fn check_limits(&self, limits: &DecodeLimits, raw: &[u8]) -> Result<(), Error> {
+        match self {
+            ServerOperation::GlobSearchReq(e) | ServerOperation::GlobSearchReq2(e) => {
+                e.check_limits(limits, raw)
+            }
+            ServerOperation::GlobSearchRes(r) => r
+                .results()
+                .try_for_each(|r| r.tags().check_limits_within(limits, raw)),
+            ServerOperation::ServerDescRes(r) => match r.tags() {
+                Some(tags) => tags.check_limits_within(limits, raw),
+                None => {
+                    let name = r.name().unwrap_or_default();
+                    limits.check_string(name, offset_in(name, raw))?;
+                    let description = r.description().unwrap_or_default();
+                    limits.check_string(description, offset_in(description, raw))
+                }
+            },
+            _ => Ok(()),
+        }
+    }
 }
 
+/// ed2k servers listen for udp on their tcp port + 4
+pub const SERVER_UDP_PORT_OFFSET: u16 = 4;
+
+/// The high 16 bits of a `GlobServStatReq` challenge, the low 16 bits are random
+pub const GLOBSERVSTAT_CHALLENGE: u32 = 0x55AA0000;
+
+/// The low 16 bits of a `ServerDescReq` challenge, the high 16 bits are random
+///
+/// On the wire this is an impossible name length for the old `ServerDescRes` layout, so servers
+/// that echo the challenge can be told apart from those that don't.
+pub const SERVER_DESC_CHALLENGE: u16 = 0xF0FF;
+
+/// `GlobServStatRes::udp_flags()`: supports `GlobGetSources` with multiple files
+pub const SRV_UDPFLG_EXT_GETSOURCES: u32 = 0x0000_0001;
+/// `GlobServStatRes::udp_flags()`: supports multiple `GlobSearchRes` per packet
+pub const SRV_UDPFLG_EXT_GETFILES: u32 = 0x0000_0002;
+/// `GlobServStatRes::udp_flags()`: sends compact tags
+pub const SRV_UDPFLG_NEWTAGS: u32 = 0x0000_0008;
+/// `GlobServStatRes::udp_flags()`: strings are utf8
+pub const SRV_UDPFLG_UNICODE: u32 = 0x0000_0010;
+/// `GlobServStatRes::udp_flags()`: supports `GlobGetSources2`
+pub const SRV_UDPFLG_EXT_GETSOURCES2: u32 = 0x0000_0020;
+/// `GlobServStatRes::udp_flags()`: supports files over 4GiB
+pub const SRV_UDPFLG_LARGEFILES: u32 = 0x0000_0100;
+/// `GlobServStatRes::udp_flags()`: supports udp obfuscation
+pub const SRV_UDPFLG_UDPOBFUSCATION: u32 = 0x0000_0200;
+/// `GlobServStatRes::udp_flags()`: supports tcp obfuscation
+pub const SRV_UDPFLG_TCPOBFUSCATION: u32 = 0x0000_0400;
+
+/// `ServerDescRes` tag: string
+pub const ST_SERVERNAME: &[u8] = b"\x01";
+/// `ServerDescRes` tag: string
+pub const ST_DESCRIPTION: &[u8] = b"\x0B";
+/// `ServerDescRes` tag: string, the dns name of a server with a dynamic ip
+pub const ST_DYNIP: &[u8] = b"\x85";
+/// `ServerDescRes` tag: string, or a uint of `major << 16 | minor`
+pub const ST_VERSION: &[u8] = b"\x91";
+/// `ServerDescRes` tag: string, comma separated list of additional tcp ports
+pub const ST_AUXPORTSLIST: &[u8] = b"\x93";
+
 /// Representation of an entire `KadOpCode` with the associated data
 ///
 /// Each UDP packet includes 1 of these.
@@ -299,7 +1364,80 @@ pub enum Operation<'a> {
     Req(Req<'a>),
     Res(Res<'a>),
 
+    HelloReq(Hello<'a>),
+    HelloRes(Hello<'a>),
+    HelloResAck(HelloResAck<'a>),
+
+    SearchKeyReq(SearchKeyReq<'a>),
+    SearchSourceReq(SearchSourceReq<'a>),
+    SearchNotesReq(SearchNotesReq<'a>),
     SearchRes(SearchRes<'a>),
+
+    PublishKeyReq(PublishKeyReq<'a>),
+    PublishSourceReq(PublishReq<'a>),
+    PublishNotesReq(PublishReq<'a>),
+    PublishRes(PublishRes<'a>),
+    /// has no content
+    PublishResAck,
+
+    FirewalledReqV1(FirewalledReq<'a>),
+    Firewalled2ReqV1(Firewalled2Req<'a>),
+    FirewalledResV1(FirewalledRes<'a>),
+    /// has no content
+    FirewalledAckResV1,
+    FindBuddyReqV1(FindBuddy<'a>),
+    FindBuddyResV1(FindBuddy<'a>),
+    CallbackReqV1(CallbackReq<'a>),
+    FirewallUdp(FirewallUdp<'a>),
+
+    /// has no content
+    Ping,
+    Pong(Pong<'a>),
+
+    /// Sender's contact details. v0 contacts end with a type byte instead of a version
+    BootstrapReqV0(ResContact<'a>),
+    BootstrapResV0(BootstrapResV0<'a>),
+    HelloReqV0(ResContact<'a>),
+    HelloResV0(ResContact<'a>),
+    ReqV0(Req<'a>),
+    ResV0(Res<'a>),
+    SearchReqV1(SearchReqV1<'a>),
+    SearchResV1(SearchResV1<'a>),
+    PublishReqV1(PublishKeyReq<'a>),
+    PublishResV1(PublishRes<'a>),
+}
+
+impl<'a> Operation<'a> {
+    /// Check the tags and search strings carried by the operation against `limits`
+    ///
+    /// Error offsets count from the start of the operation's payload (after the opcode).
+    pub fn check_limits(&self, limits: &DecodeLimits) -> Result<(), Error> {
+        match self {
+            Operation::HelloReq(h) | Operation::HelloRes(h) => {
+                h.tags().check_limits_within(limits, h.raw)
+            }
+            Operation::HelloResAck(h) => h.tags().check_limits_within(limits, h.raw),
+            Operation::SearchKeyReq(r) => {
+                r.expr().map_or(Ok(()), |e| e.check_limits(limits, r.raw))
+            }
+            Operation::SearchReqV1(r) => r.expr().map_or(Ok(()), |e| e.check_limits(limits, r.raw)),
+            Operation::SearchRes(r) => r
+                .results()?
+                .0
+                .try_for_each(|x| x.tags().check_limits_within(limits, r.raw)),
+            Operation::SearchResV1(r) => r
+                .results()?
+                .0
+                .try_for_each(|x| x.tags().check_limits_within(limits, r.raw)),
+            Operation::PublishKeyReq(r) | Operation::PublishReqV1(r) => r
+                .entries()
+                .try_for_each(|e| e.tags().check_limits_within(limits, r.raw)),
+            Operation::PublishSourceReq(r) | Operation::PublishNotesReq(r) => {
+                r.tags().check_limits_within(limits, r.raw)
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 /// Responce providing a number of arbitrary contacts
@@ -323,6 +1461,7 @@ impl<'a> BootstrapResp<'a> {
         let need = 16 + 2 + 1 + 2;
         if raw.len() < need {
             return Err(Error::BootstrapRespTooShort {
+                offset: 0,
                 have: raw.len(),
                 need,
             });
@@ -332,8 +1471,8 @@ impl<'a> BootstrapResp<'a> {
     }
 
     /// Kad ID of the client that sent this bootstrap responce
-    pub fn client_id(&self) -> u128 {
-        u128::from_le_bytes(self.raw[..16].try_into().unwrap())
+    pub fn client_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
     }
 
     /// configured udp port for the client that sent this responce
@@ -350,7 +1489,9 @@ impl<'a> BootstrapResp<'a> {
     }
 
     pub fn contacts(&self) -> Result<BootstrapRespContacts<'a>, Error> {
-        BootstrapRespContacts::from_slice(self.num_contacts(), &self.raw[(16 + 2 + 1 + 2)..])
+        let pos = 16 + 2 + 1 + 2;
+        BootstrapRespContacts::from_slice(self.num_contacts(), &self.raw[pos..])
+            .map_err(|e| e.offset_by(pos))
     }
 }
 
@@ -395,13 +1536,13 @@ impl<'a> Req<'a> {
     /// Reply with the nodes closest to this node
     ///
     /// FIXME: how is the number of returned nodes determined?
-    pub fn target(&self) -> u128 {
-        u128::from_le_bytes(self.raw[1..17].try_into().unwrap())
+    pub fn target(&self) -> KadId {
+        KadId::from_slice(&self.raw[1..])
     }
 
     /// only process the request if this matches our node id
-    pub fn check(&self) -> u128 {
-        u128::from_le_bytes(self.raw[17..(17 + 16)].try_into().unwrap())
+    pub fn check(&self) -> KadId {
+        KadId::from_slice(&self.raw[17..])
     }
 }
 
@@ -423,27 +1564,28 @@ pub struct Res<'a> {
 impl<'a> Res<'a> {
     pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
         let need = 16 + 1;
-        if raw.len() != need {
+        if raw.len() < need {
             return Err(Error::ResSizeMismatch {
                 have: raw.len(),
                 need,
             });
         }
 
-        let v = Self { raw };
-
-        let mut r = raw;
-        for _ in 0..v.num_contacts() {
-            // TODO: twiddle error to make it more useful
-            let (_, rr) = ResContact::from_slice(r)?;
-            r = rr;
+        let num_contacts = raw[16] as usize;
+        let have = raw.len() - need;
+        if have < num_contacts * RES_CONTACT_LEN {
+            return Err(Error::ResContactTooShort {
+                offset: need + have / RES_CONTACT_LEN * RES_CONTACT_LEN,
+                have: have % RES_CONTACT_LEN,
+                need: RES_CONTACT_LEN,
+            });
         }
 
         Ok(Self { raw })
     }
 
-    pub fn target(&self) -> u128 {
-        u128::from_le_bytes(self.raw[..16].try_into().unwrap())
+    pub fn target(&self) -> KadId {
+        KadId::from_slice(self.raw)
     }
 
     pub fn num_contacts(&self) -> u8 {
@@ -451,7 +1593,8 @@ impl<'a> Res<'a> {
     }
 
     fn contact_bytes(&self) -> &'a [u8] {
-        &self.raw[(16 + 1)..]
+        // trailing bytes past the advertised contacts are ignored, as eMule does
+        &self.raw[(16 + 1)..(16 + 1 + self.num_contacts() as usize * RES_CONTACT_LEN)]
     }
 
     pub fn contacts(&self) -> ResContacts<'a> {
@@ -490,7 +1633,7 @@ impl<'a> Iterator for ResContacts<'a> {
     type Item = ResContact<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.raw.len() == 0 {
+        if self.raw.is_empty() {
             return None;
         }
 
@@ -508,9 +1651,11 @@ pub struct ResContact<'a> {
     raw: &'a [u8],
 }
 
+const RES_CONTACT_LEN: usize = 16 + 4 + 2 + 2 + 1;
+
 impl<'a> ResContact<'a> {
     pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
-        let need = 16 + 4 + 2 + 2 + 1;
+        let need = RES_CONTACT_LEN;
         if raw.len() < need {
             return Err(Error::ResContactSizeMismatch {
                 have: raw.len(),
@@ -523,8 +1668,8 @@ impl<'a> ResContact<'a> {
         Ok((Self { raw: x }, rem))
     }
 
-    pub fn client_id(&self) -> u128 {
-        u128::from_le_bytes(self.raw[..16].try_into().unwrap())
+    pub fn client_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
     }
 
     pub fn ip_addr(&self) -> std::net::Ipv4Addr {
@@ -556,548 +1701,4205 @@ impl<'a> fmt::Debug for ResContact<'a> {
     }
 }
 
-///
+/// kad v0 `BootstrapReqV0`, `HelloReqV0` & `HelloResV0` contain only the sender's contact
+fn contact_v0(raw: &[u8]) -> Result<ResContact<'_>, Error> {
+    let (c, rem) = ResContact::from_slice(raw)?;
+    if !rem.is_empty() {
+        return Err(Error::HelloSpareBytes {
+            offset: raw.len() - rem.len(),
+            spare: rem.len(),
+        });
+    }
+    Ok(c)
+}
+
+/// `HelloReq` & `HelloRes`: a node introducing itself
 ///
 /// ```norust
-/// struct SearchRes {
-///   source_id: le128,
-///   target_id: le128,
-///   result_ct: le16,
-///   // `Result` is variable sized
-///   results: [Result; result_ct],
+/// struct Hello {
+///     client_id: le128,
+///     tcp_port: le16,
+///     version: u8,
+///     // `TagList` is variable sized. See `source_udp_port()` & `misc_options()`
+///     tags: TagList,
 /// }
 /// ```
 #[derive(Clone)]
-pub struct SearchRes<'a> {
+pub struct Hello<'a> {
     raw: &'a [u8],
 }
 
-impl<'a> SearchRes<'a> {
+impl<'a> Hello<'a> {
     pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
-        Ok(SearchRes { raw })
+        let need = 16 + 2 + 1;
+        if raw.len() < need {
+            return Err(Error::HelloTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let (_, rem) = TagList::from_slice(&raw[need..])?;
+        if !rem.is_empty() {
+            return Err(Error::HelloSpareBytes {
+                offset: raw.len() - rem.len(),
+                spare: rem.len(),
+            });
+        }
+
+        Ok(Hello { raw })
+    }
+
+    /// Kad ID of the client that sent this hello
+    pub fn client_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
     }
 
-    pub fn source_id(&self) -> u128 {
-        u128::from_le_bytes(self.raw[..16].try_into().unwrap())
+    pub fn tcp_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[16..(16 + 2)].try_into().unwrap())
     }
 
-    pub fn target_id(&self) -> u128 {
-        u128::from_le_bytes(self.raw[16..(16 + 16)].try_into().unwrap())
+    /// kad version of the client that sent this hello
+    pub fn version(&self) -> u8 {
+        self.raw[16 + 2]
     }
 
-    pub fn result_ct(&self) -> u16 {
-        u16::from_le_bytes(self.raw[(16 + 16)..(16 + 16 + 2)].try_into().unwrap())
+    pub fn tags(&self) -> TagList<'a> {
+        // NOTE: validated in `Hello::from_slice()`
+        TagList::from_slice(&self.raw[(16 + 2 + 1)..]).unwrap().0
     }
 
-    pub fn results(&self) -> Result<(SearchResults<'a>, &'a [u8]), Error> {
-        SearchResults::from_slice(self.result_ct(), &self.raw[(16 + 16 + 2)..])
+    /// `TAG_SOURCEUPORT`: if the sender uses an external kad port, this is its internal udp port
+    pub fn source_udp_port(&self) -> Option<u16> {
+        self.tags()
+            .get(TagId::SourceUdpPort)
+            .and_then(|v| v.as_u64())
+            .and_then(|v| v.try_into().ok())
+    }
+
+    /// `TAG_KADMISCOPTIONS`: firewall state & whether a `HelloResAck` is requested
+    pub fn misc_options(&self) -> Option<MiscOptions> {
+        self.tags()
+            .get(TagId::KadMiscOptions)
+            .and_then(|v| v.as_u64())
+            .map(|v| MiscOptions::from(v as u8))
     }
 }
 
-impl<'a> fmt::Debug for SearchRes<'a> {
+impl<'a> fmt::Debug for Hello<'a> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("SearchRes")
-            .field("source_id", &self.source_id())
-            .field("target_id", &self.target_id())
-            .field("result_ct", &self.result_ct())
-            .field("results", &self.results())
+        fmt.debug_struct("Hello")
+            .field("client_id", &self.client_id())
+            .field("tcp_port", &self.tcp_port())
+            .field("version", &self.version())
+            .field("source_udp_port", &self.source_udp_port())
+            .field("misc_options", &self.misc_options())
+            .field("tags", &self.tags())
             .finish()
     }
 }
 
-/// A series of `SearchResult`s. The size of each is determined by their content
-#[derive(Clone)]
-pub struct SearchResults<'a> {
-    raw: &'a [u8],
+/// Content of the `TAG_KADMISCOPTIONS` tag
+///
+/// ```norust
+/// 5 bits: reserved
+/// 1 bit: requesting HELLO_RES_ACK
+/// 1 bit: tcp firewalled
+/// 1 bit: udp firewalled
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MiscOptions {
+    pub udp_firewalled: bool,
+    pub tcp_firewalled: bool,
+    pub req_ack: bool,
 }
 
-impl<'a> SearchResults<'a> {
-    pub fn from_slice(mut num: u16, raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
-        let mut rem = raw;
-        loop {
-            if num == 0 {
-                return Ok((
-                    SearchResults {
-                        raw: &raw[..(raw.len() - rem.len())],
-                    },
-                    rem,
-                ));
-            }
-
-            num -= 1;
-
-            let (_, rr) = SearchResult::from_slice(rem)?;
-            rem = rr;
+impl From<u8> for MiscOptions {
+    fn from(v: u8) -> Self {
+        MiscOptions {
+            udp_firewalled: v & 0x01 != 0,
+            tcp_firewalled: v & 0x02 != 0,
+            req_ack: v & 0x04 != 0,
         }
     }
 }
 
-impl<'a> fmt::Debug for SearchResults<'a> {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // TODO: add results here
-        fmt.debug_struct("SearchResults").finish()
+impl From<MiscOptions> for u8 {
+    fn from(v: MiscOptions) -> Self {
+        (v.req_ack as u8) << 2 | (v.tcp_firewalled as u8) << 1 | (v.udp_firewalled as u8)
     }
 }
 
-impl<'a> Iterator for SearchResults<'a> {
-    type Item = SearchResult<'a>;
+/// `HelloResAck`: sent in reply to a `HelloRes` that requested it (see `MiscOptions::req_ack`)
+///
+/// ```norust
+/// struct HelloResAck {
+///     client_id: le128,
+///     // emule sends no tags
+///     tags: TagList,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct HelloResAck<'a> {
+    raw: &'a [u8],
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.raw.len() == 0 {
-            return None;
+impl<'a> HelloResAck<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16;
+        if raw.len() < need {
+            return Err(Error::HelloTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
         }
 
-        // NOTE: we require SearchResults to be well formed after construction.
-        let (v, rem) = SearchResult::from_slice(self.raw).unwrap();
-        self.raw = rem;
-        Some(v)
+        let (_, rem) = TagList::from_slice(&raw[need..])?;
+        if !rem.is_empty() {
+            return Err(Error::HelloSpareBytes {
+                offset: raw.len() - rem.len(),
+                spare: rem.len(),
+            });
+        }
+
+        Ok(HelloResAck { raw })
     }
-}
 
-#[derive(Clone)]
-pub struct SearchResult<'a> {
-    raw: &'a [u8],
+    /// Kad ID of the client that sent this ack
+    pub fn client_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn tags(&self) -> TagList<'a> {
+        // NOTE: validated in `HelloResAck::from_slice()`
+        TagList::from_slice(&self.raw[16..]).unwrap().0
+    }
 }
 
-///
-/// ```norust
-/// struct SearchResult {
-///    id: le128,
-///    // `TagList` is variable sized (based on content)
-///    tags: TagList,
+impl<'a> fmt::Debug for HelloResAck<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("HelloResAck")
+            .field("client_id", &self.client_id())
+            .field("tags", &self.tags())
+            .finish()
+    }
+}
+
+/// `SearchKeyReq`: look up files published under a keyword
+///
+/// ```norust
+/// struct SearchKeyReq {
+///     // md4 of the (first) keyword
+///     target: le128,
+///     // high bit set if `expr` is present
+///     start_position: le16,
+///     expr: SearchExpr,
 /// }
-impl<'a> SearchResult<'a> {
-    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
-        let r = &raw[16..];
-        // use taglist to determine the length here
-        let (_, rem) = TagList::from_slice(&r)?;
-        Ok((Self { raw: raw }, rem))
+/// ```
+#[derive(Clone)]
+pub struct SearchKeyReq<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> SearchKeyReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 2;
+        if raw.len() < need {
+            return Err(Error::SearchReqTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let r = SearchKeyReq { raw };
+        if r.is_restrictive() {
+            // like emule, ignore anything following the expression
+            SearchExpr::from_slice(&raw[need..]).map_err(|e| e.offset_by(need))?;
+        }
+
+        Ok(r)
     }
 
-    pub fn id(&self) -> u128 {
-        u128::from_le_bytes(self.raw[..16].try_into().unwrap())
+    pub fn target(&self) -> KadId {
+        KadId::from_slice(self.raw)
     }
 
-    pub fn tags(&self) -> TagList<'a> {
-        TagList::from_slice(&self.raw[16..]).unwrap().0
+    fn start_position_raw(&self) -> u16 {
+        u16::from_le_bytes(self.raw[16..(16 + 2)].try_into().unwrap())
+    }
+
+    /// index of the first result the searcher wants
+    pub fn start_position(&self) -> u16 {
+        self.start_position_raw() & 0x7FFF
+    }
+
+    /// results must match `expr()`
+    pub fn is_restrictive(&self) -> bool {
+        self.start_position_raw() & 0x8000 != 0
+    }
+
+    pub fn expr(&self) -> Option<SearchExpr<'a>> {
+        if !self.is_restrictive() {
+            return None;
+        }
+
+        // NOTE: validated in `SearchKeyReq::from_slice()`
+        Some(SearchExpr::from_slice(&self.raw[(16 + 2)..]).unwrap().0)
     }
 }
 
+impl<'a> fmt::Debug for SearchKeyReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SearchKeyReq")
+            .field("target", &self.target())
+            .field("start_position", &self.start_position())
+            .field("expr", &self.expr())
+            .finish()
+    }
+}
+
+/// Maximum nesting of a `SearchExpr`, counting the root as 1. emule rejects deeper expressions.
+pub const SEARCH_EXPR_MAX_DEPTH: usize = 24;
+
+/// Search expression tree attached to a `SearchKeyReq`
 ///
-///
-/// ```notrust
-/// struct TagList {
-///    count: le32,
-///    // `Tag` size is variable
-///    tags: [Tag; count],
-/// }
-///
-/// struct Tag {
-///     type: u8,
-///     name_len: u16,
-///     // not necessarily null terminated
-///     name: [u8; name_len];
-///     // Value size & interp determined by `type`
-///     value: Value(type),
+/// ```norust
+/// struct SearchExpr {
+///     op: u8,
+///     value: Value(op),
 /// }
 ///
 /// union Value {
-///     Hash([u8;16]),
-///     String(StringUtf8),
-///     Uint64(le64),
-///     Uint32(le32),
-///     Uint16(le16),
-///     Uint8(u8),
-///     Float32(f32),
-///     // comments indicate it is unused
-///     Bsob
+///     // 0x00
+///     Bool { bool_op: u8, left: SearchExpr, right: SearchExpr },
+///     // 0x01
+///     String { len: le16, string: [u8; len] },
+///     // 0x02
+///     MetaTag { value_len: le16, value: [u8; value_len], name_len: le16, name: [u8; name_len] },
+///     // 0x03
+///     Numeric32 { value: le32, op: u8, name_len: le16, name: [u8; name_len] },
+///     // 0x08
+///     Numeric64 { value: le64, op: u8, name_len: le16, name: [u8; name_len] },
 /// }
 /// ```
-#[derive(Clone)]
-pub struct TagList<'a> {
-    raw: &'a [u8],
+#[derive(Clone, PartialEq, Eq)]
+pub enum SearchExpr<'a> {
+    And(Box<SearchExpr<'a>>, Box<SearchExpr<'a>>),
+    Or(Box<SearchExpr<'a>>, Box<SearchExpr<'a>>),
+    /// matches the left side, but not the right
+    AndNot(Box<SearchExpr<'a>>, Box<SearchExpr<'a>>),
+    /// one or more whitespace separated keywords, all of which must match
+    String(&'a [u8]),
+    /// string tag named `name` equals `value`
+    MetaTag {
+        name: &'a [u8],
+        value: &'a [u8],
+    },
+    /// integer tag named `name` compared with `value`
+    Numeric {
+        name: &'a [u8],
+        op: NumericOp,
+        value: u64,
+    },
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Primitive, Debug)]
+/// Comparison in a `SearchExpr::Numeric`: `tag <op> value`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Primitive)]
 #[repr(u8)]
-pub enum TagType {
-    Hash = 0x01,
-    String_ = 0x02,
-    Uint32 = 0x03,
-    Float32 = 0x04,
-    /// XXX: unhandled in emule tag list parsing
-    Bool = 0x05,
-    /// XXX: unhandled in emule tag list parsing
-    BoolArray = 0x06,
-    /// XXX: unhandled in emule tag list parsing
-    Blob = 0x07,
-    Uint16 = 0x08,
-    Uint8 = 0x09,
-    /// XXX: emule comments indicate it is unused
-    Bsob = 0x0A,
-    Uint64 = 0x0B,
+pub enum NumericOp {
+    Eq = 0x00,
+    Gt = 0x01,
+    Lt = 0x02,
+    Ge = 0x03,
+    Le = 0x04,
+    Ne = 0x05,
 }
 
-impl<'a> TagList<'a> {
+impl<'a> SearchExpr<'a> {
+    /// Parse an expression from the start of `raw`, returning the remaining bytes
     pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
-        if raw.len() < 4 {
-            return Err(Error::TagListTooShort {
-                need: 4,
-                have: raw.len(),
+        Self::from_slice_depth(raw, 0)
+    }
+
+    /// Check every string in the expression against `limits`
+    ///
+    /// `raw` holds the bytes the expression was parsed from, error offsets count from its start.
+    pub fn check_limits(&self, limits: &DecodeLimits, raw: &[u8]) -> Result<(), Error> {
+        let check = |s: &[u8]| limits.check_string(s, offset_in(s, raw));
+        match self {
+            SearchExpr::And(l, r) | SearchExpr::Or(l, r) | SearchExpr::AndNot(l, r) => {
+                l.check_limits(limits, raw)?;
+                r.check_limits(limits, raw)
+            }
+            SearchExpr::String(s) => check(s),
+            SearchExpr::MetaTag { name, value } => {
+                check(name)?;
+                check(value)
+            }
+            SearchExpr::Numeric { name, .. } => check(name),
+        }
+    }
+
+    fn from_slice_depth(raw: &'a [u8], depth: usize) -> Result<(Self, &'a [u8]), Error> {
+        if depth >= SEARCH_EXPR_MAX_DEPTH {
+            return Err(Error::SearchExprTooDeep {
+                offset: 0,
+                max: SEARCH_EXPR_MAX_DEPTH,
             });
         }
 
-        let tl = TagList { raw };
+        // errors from nested parts are reported at their offset in `raw`
+        let at = |rem: &[u8]| raw.len() - rem.len();
+        let (&op, rem) = raw.split_first().ok_or(Error::SearchExprTooShort {
+            offset: 0,
+            have: 0,
+            need: 1,
+        })?;
+        match op {
+            0x00 => {
+                let (&bool_op, rem) = rem.split_first().ok_or(Error::SearchExprTooShort {
+                    offset: at(rem),
+                    have: 0,
+                    need: 1,
+                })?;
+                let (left, rem) =
+                    Self::from_slice_depth(rem, depth + 1).map_err(|e| e.offset_by(at(rem)))?;
+                let (right, rem) =
+                    Self::from_slice_depth(rem, depth + 1).map_err(|e| e.offset_by(at(rem)))?;
+                let (left, right) = (Box::new(left), Box::new(right));
+                let e = match bool_op {
+                    0x00 => SearchExpr::And(left, right),
+                    0x01 => SearchExpr::Or(left, right),
+                    0x02 => SearchExpr::AndNot(left, right),
+                    value => return Err(Error::SearchExprInvalidBoolOp { offset: 1, value }),
+                };
+                Ok((e, rem))
+            }
+            0x01 => {
+                let (s, rem) = split_str16(rem).map_err(|e| e.offset_by(at(rem)))?;
+                Ok((SearchExpr::String(s), rem))
+            }
+            0x02 => {
+                let (value, rem) = split_str16(rem).map_err(|e| e.offset_by(at(rem)))?;
+                let (name, rem) = split_str16(rem).map_err(|e| e.offset_by(at(rem)))?;
+                Ok((SearchExpr::MetaTag { name, value }, rem))
+            }
+            0x03 | 0x08 => {
+                let value_len = if op == 0x03 { 4 } else { 8 };
+                let need = value_len + 1;
+                if rem.len() < need {
+                    return Err(Error::SearchExprTooShort {
+                        offset: at(rem),
+                        have: rem.len(),
+                        need,
+                    });
+                }
 
-        let mut ct = tl.count();
-        let mut rem = tl.item_bytes();
-        loop {
-            if ct == 0 {
-                return Ok((
-                    TagList {
-                        raw: &raw[..(raw.len() - rem.len())],
+                let mut v = [0u8; 8];
+                v[..value_len].copy_from_slice(&rem[..value_len]);
+                let value = u64::from_le_bytes(v);
+                let op = NumericOp::from_u8(rem[value_len]).ok_or(
+                    Error::SearchExprInvalidNumericOp {
+                        offset: at(rem) + value_len,
+                        value: rem[value_len],
                     },
-                    rem,
-                ));
+                )?;
+                let rem = &rem[need..];
+                let (name, rem) = split_str16(rem).map_err(|e| e.offset_by(at(rem)))?;
+                Ok((SearchExpr::Numeric { name, op, value }, rem))
             }
+            value => Err(Error::SearchExprInvalidOp { offset: 0, value }),
+        }
+    }
 
-            ct -= 1;
-            let (_, rr) = Tag::from_slice(rem)?;
-            rem = rr;
+    /// Encode the expression, the inverse of `from_slice()`
+    ///
+    /// Numeric values that fit are written as 32-bit, like emule.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            SearchExpr::And(l, r) | SearchExpr::Or(l, r) | SearchExpr::AndNot(l, r) => {
+                let bool_op = match self {
+                    SearchExpr::And(..) => 0x00,
+                    SearchExpr::Or(..) => 0x01,
+                    _ => 0x02,
+                };
+                w.write_all(&[0x00, bool_op])?;
+                l.write_to(w)?;
+                r.write_to(w)
+            }
+            SearchExpr::String(s) => {
+                w.write_all(&[0x01])?;
+                write_str16(w, s)
+            }
+            SearchExpr::MetaTag { name, value } => {
+                w.write_all(&[0x02])?;
+                write_str16(w, value)?;
+                write_str16(w, name)
+            }
+            SearchExpr::Numeric { name, op, value } => {
+                match u32::try_from(*value) {
+                    Ok(v) => {
+                        w.write_all(&[0x03])?;
+                        w.write_all(&v.to_le_bytes())?;
+                    }
+                    Err(_) => {
+                        w.write_all(&[0x08])?;
+                        w.write_all(&value.to_le_bytes())?;
+                    }
+                }
+                w.write_all(&[*op as u8])?;
+                write_str16(w, name)
+            }
         }
     }
 }
 
-impl<'a> TagList<'a> {
-    pub fn count(&self) -> u16 {
-        u16::from_le_bytes(self.raw[..4].try_into().unwrap())
+impl<'a> fmt::Debug for SearchExpr<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchExpr::And(l, r) => fmt.debug_tuple("And").field(l).field(r).finish(),
+            SearchExpr::Or(l, r) => fmt.debug_tuple("Or").field(l).field(r).finish(),
+            SearchExpr::AndNot(l, r) => fmt.debug_tuple("AndNot").field(l).field(r).finish(),
+            SearchExpr::String(s) => fmt
+                .debug_tuple("String")
+                .field(&String::from_utf8_lossy(s))
+                .finish(),
+            SearchExpr::MetaTag { name, value } => fmt
+                .debug_struct("MetaTag")
+                .field("name", name)
+                .field("value", &String::from_utf8_lossy(value))
+                .finish(),
+            SearchExpr::Numeric { name, op, value } => fmt
+                .debug_struct("Numeric")
+                .field("name", name)
+                .field("op", op)
+                .field("value", value)
+                .finish(),
+        }
     }
+}
 
-    fn item_bytes(&self) -> &'a [u8] {
-        &self.raw[4..]
+/// split a `le16` length prefixed byte string off the front of `raw`
+fn split_str16(raw: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    if raw.len() < 2 {
+        return Err(Error::SearchExprTooShort {
+            offset: 0,
+            have: raw.len(),
+            need: 2,
+        });
     }
 
-    pub fn iter(&self) -> TagListIter<'a> {
-        TagListIter::from_slice(self.item_bytes())
+    let len = u16::from_le_bytes(raw[..2].try_into().unwrap()) as usize;
+    let need = 2 + len;
+    if raw.len() < need {
+        return Err(Error::SearchExprTooShort {
+            offset: 0,
+            have: raw.len(),
+            need,
+        });
     }
+
+    Ok((&raw[2..need], &raw[need..]))
 }
 
-// NOTE: we use a seperate iterator here because the prefixed count would otherwise interfere
-pub struct TagListIter<'a> {
+/// `SearchSourceReq`: look up sources for a file
+///
+/// ```norust
+/// struct SearchSourceReq {
+///     // file hash
+///     target: le128,
+///     // high bit is ignored
+///     start_position: le16,
+///     file_size: le64,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SearchSourceReq<'a> {
     raw: &'a [u8],
 }
 
-impl<'a> TagListIter<'a> {
-    pub fn from_slice(raw: &'a [u8]) -> Self {
-        Self { raw }
+impl<'a> SearchSourceReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 2 + 8;
+        if raw.len() < need {
+            return Err(Error::SearchReqTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(SearchSourceReq { raw })
     }
-}
 
-impl<'a> Iterator for TagListIter<'a> {
-    type Item = Result<Tag<'a>, Error>;
+    pub fn target(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match Tag::from_slice(self.raw) {
-            Ok((i, rem)) => {
-                self.raw = rem;
-                Some(Ok(i))
-            }
-            Err(e) => Some(Err(e)),
-        }
+    /// index of the first result the searcher wants
+    pub fn start_position(&self) -> u16 {
+        u16::from_le_bytes(self.raw[16..(16 + 2)].try_into().unwrap()) & 0x7FFF
+    }
+
+    pub fn file_size(&self) -> u64 {
+        u64::from_le_bytes(self.raw[(16 + 2)..(16 + 2 + 8)].try_into().unwrap())
+    }
+}
+
+impl<'a> fmt::Debug for SearchSourceReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SearchSourceReq")
+            .field("target", &self.target())
+            .field("start_position", &self.start_position())
+            .field("file_size", &self.file_size())
+            .finish()
     }
 }
 
+/// `SearchNotesReq`: look up comments & ratings for a file
+///
 /// ```norust
-/// struct Tag {
-///     tag_type: u8,
-///     name_len: u16,
-///     name: [u8;name_len],
-///     value: [u8;tag_size(tag_type)],
+/// struct SearchNotesReq {
+///     // file hash
+///     target: le128,
+///     file_size: le64,
 /// }
 /// ```
-pub struct Tag<'a> {
+#[derive(Clone)]
+pub struct SearchNotesReq<'a> {
     raw: &'a [u8],
 }
 
-impl<'a> Tag<'a> {
-    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
-        let need_size = 1 + 2;
-        if raw.len() < need_size {
-            return Err(Error::TagSizeMismatch {
-                need: need_size,
-                have: raw.len(),
-            });
-        }
-
-        let name_len = u16::from_le_bytes(raw[1..3].try_into().unwrap()) as usize;
-        let need_size = need_size + name_len;
-        if raw.len() < need_size {
-            return Err(Error::TagSizeMismatchName {
-                need: need_size,
+impl<'a> SearchNotesReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 8;
+        if raw.len() < need {
+            return Err(Error::SearchReqTooShort {
+                offset: 0,
                 have: raw.len(),
-                name_len,
+                need,
             });
         }
 
-        let tag_type = match TagType::from_u8(raw[0]) {
-            Some(v) => v,
-            None => return Err(Error::TagInvalid { value: raw[0] }),
-        };
+        Ok(SearchNotesReq { raw })
+    }
 
-        let value_offs = 3 + name_len;
-        let content_bytes = match tag_type {
-            TagType::Hash => 16,
-            TagType::String_ => {
-                let need_size = need_size + 2;
-                if raw.len() < need_size {
-                    return Err(Error::TagSizeMismatchForString {
-                        need: need_size,
-                        have: raw.len(),
-                    });
-                }
+    pub fn target(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
 
-                let s_len =
-                    u16::from_le_bytes(raw[value_offs..(value_offs + 6)].try_into().unwrap())
-                        as usize;
+    pub fn file_size(&self) -> u64 {
+        u64::from_le_bytes(self.raw[16..(16 + 8)].try_into().unwrap())
+    }
+}
 
-                2 + s_len
-            }
-            TagType::Uint64 => 8,
-            TagType::Uint32 => 4,
-            TagType::Uint16 => 2,
-            TagType::Uint8 => 1,
-            TagType::Float32 => 4,
-            TagType::Bsob => {
-                todo!()
-            }
-            _ => {
-                todo!()
-            }
-        };
+impl<'a> fmt::Debug for SearchNotesReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SearchNotesReq")
+            .field("target", &self.target())
+            .field("file_size", &self.file_size())
+            .finish()
+    }
+}
 
-        let need_size = need_size + content_bytes;
+///
+///
+/// ```norust
+/// struct SearchRes {
+///   source_id: le128,
+///   target_id: le128,
+///   result_ct: le16,
+///   // `Result` is variable sized
+///   results: [Result; result_ct],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SearchRes<'a> {
+    raw: &'a [u8],
+}
 
-        if raw.len() < need_size {
-            return Err(Error::TagSizeMismatchContent {
+impl<'a> SearchRes<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 16 + 2;
+        if raw.len() < need {
+            return Err(Error::SearchResTooShort {
+                offset: 0,
                 have: raw.len(),
-                need: need_size,
+                need,
             });
         }
 
-        let (a, rem) = raw.split_at(need_size);
+        let r = SearchRes { raw };
+        let (_, rem) = r.results()?;
+        if !rem.is_empty() {
+            return Err(Error::SearchResSpareBytes {
+                offset: raw.len() - rem.len(),
+                spare: rem.len(),
+            });
+        }
 
-        Ok((Tag { raw: a }, rem))
+        Ok(r)
     }
 
-    fn name_len(&self) -> usize {
-        u16::from_le_bytes(self.raw[1..3].try_into().unwrap()) as usize
+    pub fn source_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn target_id(&self) -> KadId {
+        KadId::from_slice(&self.raw[16..])
+    }
+
+    pub fn result_ct(&self) -> u16 {
+        u16::from_le_bytes(self.raw[(16 + 16)..(16 + 16 + 2)].try_into().unwrap())
+    }
+
+    pub fn results(&self) -> Result<(SearchResults<'a>, &'a [u8]), Error> {
+        let pos = 16 + 16 + 2;
+        SearchResults::from_slice(self.result_ct(), &self.raw[pos..]).map_err(|e| e.offset_by(pos))
+    }
+}
+
+impl<'a> fmt::Debug for SearchRes<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SearchRes")
+            .field("source_id", &self.source_id())
+            .field("target_id", &self.target_id())
+            .field("result_ct", &self.result_ct())
+            .field("results", &self.results())
+            .finish()
+    }
+}
+
+/// A series of `SearchResult`s. The size of each is determined by their content
+#[derive(Clone)]
+pub struct SearchResults<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> SearchResults<'a> {
+    pub fn from_slice(mut num: u16, raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let mut rem = raw;
+        loop {
+            if num == 0 {
+                return Ok((
+                    SearchResults {
+                        raw: &raw[..(raw.len() - rem.len())],
+                    },
+                    rem,
+                ));
+            }
+
+            num -= 1;
+
+            let (_, rr) =
+                SearchResult::from_slice(rem).map_err(|e| e.offset_by(raw.len() - rem.len()))?;
+            rem = rr;
+        }
+    }
+}
+
+impl<'a> fmt::Debug for SearchResults<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a> Iterator for SearchResults<'a> {
+    type Item = SearchResult<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.raw.is_empty() {
+            return None;
+        }
+
+        // NOTE: we require SearchResults to be well formed after construction.
+        let (v, rem) = SearchResult::from_slice(self.raw).unwrap();
+        self.raw = rem;
+        Some(v)
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchResult<'a> {
+    raw: &'a [u8],
+}
+
+/// A single result. For keyword searches `id` is a file hash & the tags describe the file, for
+/// source searches `id` is the user hash of a source & the tags describe how to reach it.
+///
+/// ```norust
+/// struct SearchResult {
+///    id: le128,
+///    // `TagList` is variable sized (based on content)
+///    tags: TagList,
+/// }
+impl<'a> SearchResult<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let need = 16;
+        if raw.len() < need {
+            return Err(Error::SearchResultTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        // use taglist to determine the length here
+        let (_, rem) = TagList::from_slice(&raw[need..])?;
+        Ok((
+            Self {
+                raw: &raw[..(raw.len() - rem.len())],
+            },
+            rem,
+        ))
+    }
+
+    pub fn id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn tags(&self) -> TagList<'a> {
+        // NOTE: validated in `SearchResult::from_slice()`
+        TagList::from_slice(&self.raw[16..]).unwrap().0
+    }
+
+    fn get_u64(&self, id: TagId) -> Option<u64> {
+        self.tags().get(id).and_then(|v| v.as_u64())
+    }
+
+    fn get_bytes(&self, id: TagId) -> Option<&'a [u8]> {
+        self.tags().get(id).and_then(|v| v.as_bytes())
+    }
+
+    /// `TAG_FILENAME`
+    pub fn file_name(&self) -> Option<&'a [u8]> {
+        self.get_bytes(TagId::FileName)
+    }
+
+    /// `TAG_FILESIZE` combined with `TAG_FILESIZE_HI`
+    pub fn file_size(&self) -> Option<u64> {
+        let lo = match self.tags().get(TagId::FileSize)? {
+            TagValue::Bsob(b) if b.len() == 8 => u64::from_le_bytes(b.try_into().unwrap()),
+            v => v.as_u64()?,
+        };
+        let hi = self.get_u64(TagId::FileSizeHi).unwrap_or(0);
+        Some(lo | hi << 32)
+    }
+
+    /// `TAG_FILETYPE`
+    pub fn file_type(&self) -> Option<&'a [u8]> {
+        self.get_bytes(TagId::FileType)
+    }
+
+    /// `TAG_SOURCES`: number of nodes that published the file
+    pub fn sources(&self) -> Option<u32> {
+        self.get_u64(TagId::Sources).and_then(|v| v.try_into().ok())
+    }
+
+    /// `TAG_KADAICHHASHRESULT`: the most popular AICH root hash among publishers of the file
+    pub fn aich_hash(&self) -> Option<&'a [u8]> {
+        let b = match self.tags().get(TagId::KadAichHashResult)? {
+            TagValue::Bsob(b) => b,
+            _ => return None,
+        };
+        let (&ct, entries) = b.split_first()?;
+        entries
+            .chunks_exact(1 + 20)
+            .take(ct as usize)
+            .max_by_key(|e| e[0])
+            .map(|e| &e[1..])
+    }
+
+    /// `TAG_SOURCETYPE`
+    ///
+    /// 1 & 4: open source, reachable at `source_ip()`:`source_port()`
+    /// 3 & 5: firewalled source, reachable via `buddy_*()`
+    /// 6: firewalled source, reachable via a direct udp callback
+    pub fn source_type(&self) -> Option<u8> {
+        self.get_u64(TagId::SourceType).map(|v| v as u8)
+    }
+
+    /// `TAG_SOURCEIP`
+    pub fn source_ip(&self) -> Option<std::net::Ipv4Addr> {
+        self.get_u64(TagId::SourceIp)
+            .map(|v| std::net::Ipv4Addr::from(v as u32))
+    }
+
+    /// `TAG_SOURCEPORT`: tcp port
+    pub fn source_port(&self) -> Option<u16> {
+        self.get_u64(TagId::SourcePort)
+            .and_then(|v| v.try_into().ok())
+    }
+
+    /// `TAG_SOURCEUPORT`: udp port
+    pub fn source_udp_port(&self) -> Option<u16> {
+        self.get_u64(TagId::SourceUdpPort)
+            .and_then(|v| v.try_into().ok())
+    }
+
+    /// `TAG_SERVERIP`
+    pub fn buddy_ip(&self) -> Option<std::net::Ipv4Addr> {
+        self.get_u64(TagId::ServerIp)
+            .map(|v| std::net::Ipv4Addr::from(v as u32))
+    }
+
+    /// `TAG_SERVERPORT`
+    pub fn buddy_port(&self) -> Option<u16> {
+        self.get_u64(TagId::ServerPort)
+            .and_then(|v| v.try_into().ok())
+    }
+
+    /// `TAG_BUDDYHASH`, decoded from hex into wire order
+    pub fn buddy_hash(&self) -> Option<[u8; 16]> {
+        let s = self.get_bytes(TagId::BuddyHash)?;
+        if s.len() != 32 {
+            return None;
+        }
+
+        let mut h = [0u8; 16];
+        for (o, c) in h.iter_mut().zip(s.chunks_exact(2)) {
+            *o = u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok()?;
+        }
+        Some(h)
+    }
+
+    /// `TAG_ENCRYPTION`: obfuscation support bits of the source
+    pub fn encryption(&self) -> Option<u8> {
+        self.get_u64(TagId::Encryption).map(|v| v as u8)
+    }
+
+    /// Tags not covered by one of the accessors above
+    pub fn unknown_tags(&self) -> impl Iterator<Item = Tag<'a>> {
+        const KNOWN: &[TagId] = &[
+            TagId::FileName,
+            TagId::FileSize,
+            TagId::FileSizeHi,
+            TagId::FileType,
+            TagId::Sources,
+            TagId::KadAichHashResult,
+            TagId::SourceType,
+            TagId::SourceIp,
+            TagId::SourcePort,
+            TagId::SourceUdpPort,
+            TagId::ServerIp,
+            TagId::ServerPort,
+            TagId::BuddyHash,
+            TagId::Encryption,
+        ];
+        self.tags()
+            .iter()
+            .filter_map(Result::ok)
+            .filter(|t| !t.id().is_some_and(|id| KNOWN.contains(&id)))
+    }
+}
+
+impl<'a> fmt::Debug for SearchResult<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = fmt.debug_struct("SearchResult");
+        d.field("id", &self.id());
+        if let Some(v) = self.file_name() {
+            d.field("file_name", &String::from_utf8_lossy(v));
+        }
+        if let Some(v) = self.file_size() {
+            d.field("file_size", &v);
+        }
+        if let Some(v) = self.file_type() {
+            d.field("file_type", &String::from_utf8_lossy(v));
+        }
+        if let Some(v) = self.sources() {
+            d.field("sources", &v);
+        }
+        if let Some(v) = self.aich_hash() {
+            d.field("aich_hash", &v);
+        }
+        if let Some(v) = self.source_type() {
+            d.field("source_type", &v);
+        }
+        if let Some(v) = self.source_ip() {
+            d.field("source_ip", &v);
+        }
+        if let Some(v) = self.source_port() {
+            d.field("source_port", &v);
+        }
+        if let Some(v) = self.source_udp_port() {
+            d.field("source_udp_port", &v);
+        }
+        if let Some(v) = self.buddy_ip() {
+            d.field("buddy_ip", &v);
+        }
+        if let Some(v) = self.buddy_port() {
+            d.field("buddy_port", &v);
+        }
+        if let Some(v) = self.buddy_hash() {
+            d.field("buddy_hash", &v);
+        }
+        if let Some(v) = self.encryption() {
+            d.field("encryption", &v);
+        }
+        d.field("unknown_tags", &self.unknown_tags().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// `PublishKeyReq` & `PublishReqV1`: announce files that match a keyword
+///
+/// kad v1 also uses `PublishReqV1` to publish sources, with a single entry.
+///
+/// ```norust
+/// struct PublishKeyReq {
+///     // md4 of the keyword
+///     target_id: le128,
+///     entry_ct: le16,
+///     entries: [PublishKeyEntry; entry_ct],
+/// }
+///
+/// struct PublishKeyEntry {
+///     file_id: le128,
+///     // file name, size, type, etc
+///     tags: TagList,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PublishKeyReq<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> PublishKeyReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 2;
+        if raw.len() < need {
+            return Err(Error::PublishReqTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let r = PublishKeyReq { raw };
+        let mut rem = &raw[need..];
+        for _ in 0..r.entry_ct() {
+            rem = PublishKeyEntry::from_slice(rem)
+                .map_err(|e| e.offset_by(raw.len() - rem.len()))?
+                .1;
+        }
+        if !rem.is_empty() {
+            return Err(Error::PublishReqSpareBytes {
+                offset: raw.len() - rem.len(),
+                spare: rem.len(),
+            });
+        }
+
+        Ok(r)
+    }
+
+    pub fn target_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn entry_ct(&self) -> u16 {
+        u16::from_le_bytes(self.raw[16..(16 + 2)].try_into().unwrap())
+    }
+
+    pub fn entries(&self) -> PublishKeyEntries<'a> {
+        // NOTE: validated in `PublishKeyReq::from_slice()`
+        PublishKeyEntries {
+            raw: &self.raw[(16 + 2)..],
+        }
+    }
+}
+
+impl<'a> fmt::Debug for PublishKeyReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PublishKeyReq")
+            .field("target_id", &self.target_id())
+            .field("entry_ct", &self.entry_ct())
+            .field("entries", &self.entries())
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct PublishKeyEntries<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> fmt::Debug for PublishKeyEntries<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a> Iterator for PublishKeyEntries<'a> {
+    type Item = PublishKeyEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.raw.is_empty() {
+            return None;
+        }
+
+        // NOTE: we require PublishKeyEntries to be well formed after construction.
+        let (v, rem) = PublishKeyEntry::from_slice(self.raw).unwrap();
+        self.raw = rem;
+        Some(v)
+    }
+}
+
+#[derive(Clone)]
+pub struct PublishKeyEntry<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> PublishKeyEntry<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let need = 16;
+        if raw.len() < need {
+            return Err(Error::PublishReqTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let (_, rem) = TagList::from_slice(&raw[need..])?;
+        Ok((
+            PublishKeyEntry {
+                raw: &raw[..(raw.len() - rem.len())],
+            },
+            rem,
+        ))
+    }
+
+    pub fn file_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn tags(&self) -> TagList<'a> {
+        // NOTE: validated in `PublishKeyEntry::from_slice()`
+        TagList::from_slice(&self.raw[16..]).unwrap().0
+    }
+}
+
+impl<'a> fmt::Debug for PublishKeyEntry<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PublishKeyEntry")
+            .field("file_id", &self.file_id())
+            .field("tags", &self.tags())
+            .finish()
+    }
+}
+
+/// `PublishSourceReq` & `PublishNotesReq`: announce ourselves as a source of, or a note about, a
+/// file
+///
+/// ```norust
+/// struct PublishReq {
+///     // file hash
+///     target_id: le128,
+///     // kad id of the source, or user hash of the note author
+///     contact_id: le128,
+///     // source: `TAG_SOURCETYPE`, `TAG_SOURCEPORT`, etc
+///     // notes: `TAG_FILENAME`, `TAG_FILERATING`, `TAG_DESCRIPTION`, etc
+///     tags: TagList,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PublishReq<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> PublishReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 16;
+        if raw.len() < need {
+            return Err(Error::PublishReqTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let (_, rem) = TagList::from_slice(&raw[need..])?;
+        if !rem.is_empty() {
+            return Err(Error::PublishReqSpareBytes {
+                offset: raw.len() - rem.len(),
+                spare: rem.len(),
+            });
+        }
+
+        Ok(PublishReq { raw })
+    }
+
+    pub fn target_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn contact_id(&self) -> KadId {
+        KadId::from_slice(&self.raw[16..])
+    }
+
+    pub fn tags(&self) -> TagList<'a> {
+        // NOTE: validated in `PublishReq::from_slice()`
+        TagList::from_slice(&self.raw[(16 + 16)..]).unwrap().0
+    }
+}
+
+impl<'a> fmt::Debug for PublishReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PublishReq")
+            .field("target_id", &self.target_id())
+            .field("contact_id", &self.contact_id())
+            .field("tags", &self.tags())
+            .finish()
+    }
+}
+
+/// `PublishRes` & `PublishResV1`: reply to any of the publish requests
+///
+/// ```norust
+/// struct PublishRes {
+///     target_id: le128,
+///     // how full the replying node is for `target_id`, 0 to 100
+///     load: Option<u8>,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PublishRes<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> PublishRes<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16;
+        if raw.len() < need {
+            return Err(Error::PublishResTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(PublishRes { raw })
+    }
+
+    pub fn target_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    /// emule delays republishing to nodes reporting a high load. Omitted by old clients.
+    pub fn load(&self) -> Option<u8> {
+        self.raw.get(16).copied()
+    }
+}
+
+impl<'a> fmt::Debug for PublishRes<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PublishRes")
+            .field("target_id", &self.target_id())
+            .field("load", &self.load())
+            .finish()
+    }
+}
+
+/// `FirewalledReqV1`: ask the receiver to try a tcp connection to us, to check if we're firewalled
+///
+/// ```norust
+/// struct FirewalledReq {
+///     tcp_port: le16,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FirewalledReq<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> FirewalledReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 2;
+        if raw.len() < need {
+            return Err(Error::FirewallTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(FirewalledReq { raw })
+    }
+
+    /// tcp port the receiver should try to connect to
+    pub fn tcp_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[..2].try_into().unwrap())
+    }
+}
+
+impl<'a> fmt::Debug for FirewalledReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FirewalledReq")
+            .field("tcp_port", &self.tcp_port())
+            .finish()
+    }
+}
+
+/// `Firewalled2ReqV1`: like `FirewalledReqV1`, but lets the receiver identify us (and use
+/// obfuscation) on the tcp connection
+///
+/// ```norust
+/// struct Firewalled2Req {
+///     tcp_port: le16,
+///     user_hash: [u8; 16],
+///     connect_options: u8,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Firewalled2Req<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Firewalled2Req<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 2 + 16 + 1;
+        if raw.len() < need {
+            return Err(Error::FirewallTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(Firewalled2Req { raw })
+    }
+
+    pub fn tcp_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[..2].try_into().unwrap())
+    }
+
+    /// ed2k user hash of the sender
+    pub fn user_hash(&self) -> [u8; 16] {
+        self.raw[2..(2 + 16)].try_into().unwrap()
+    }
+
+    pub fn connect_options(&self) -> u8 {
+        self.raw[2 + 16]
+    }
+}
+
+impl<'a> fmt::Debug for Firewalled2Req<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Firewalled2Req")
+            .field("tcp_port", &self.tcp_port())
+            .field("user_hash", &Hs(self.user_hash()))
+            .field("connect_options", &self.connect_options())
+            .finish()
+    }
+}
+
+/// `FirewalledResV1`: reply to either firewalled request, sent after the tcp connection attempt.
+/// A `FirewalledAckResV1` is sent if the connection succeeded.
+///
+/// ```norust
+/// struct FirewalledRes {
+///     // the ip the request was recieved from
+///     ip: le32,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FirewalledRes<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> FirewalledRes<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 4;
+        if raw.len() < need {
+            return Err(Error::FirewallTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(FirewalledRes { raw })
+    }
+
+    /// our public ip, as seen by the sender
+    pub fn ip_addr(&self) -> std::net::Ipv4Addr {
+        u32::from_le_bytes(self.raw[..4].try_into().unwrap()).into()
+    }
+}
+
+impl<'a> fmt::Debug for FirewalledRes<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FirewalledRes")
+            .field("ip_addr", &self.ip_addr())
+            .finish()
+    }
+}
+
+/// `FindBuddyReqV1` & `FindBuddyResV1`: a firewalled (LowID) node looking for an open node to
+/// relay callbacks, and the reply from a node willing to do so
+///
+/// ```norust
+/// struct FindBuddy {
+///     // for requests, the kad id of the firewalled node with every bit inverted. responses
+///     // echo the value from the request.
+///     buddy_id: le128,
+///     // ed2k user hash of the sender
+///     client_hash: [u8; 16],
+///     // tcp port of the sender
+///     client_port: le16,
+///     // only sent to contacts with version >= 6
+///     connect_options: Option<u8>,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FindBuddy<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> FindBuddy<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 16 + 2;
+        if raw.len() < need {
+            return Err(Error::FindBuddyTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(FindBuddy { raw })
+    }
+
+    pub fn buddy_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    /// For requests: the kad id of the firewalled node looking for a buddy
+    pub fn requester_id(&self) -> KadId {
+        !self.buddy_id()
+    }
+
+    pub fn client_hash(&self) -> [u8; 16] {
+        self.raw[16..(16 + 16)].try_into().unwrap()
+    }
+
+    pub fn client_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[(16 + 16)..(16 + 16 + 2)].try_into().unwrap())
+    }
+
+    pub fn connect_options(&self) -> Option<u8> {
+        self.raw.get(16 + 16 + 2).copied()
+    }
+}
+
+impl<'a> fmt::Debug for FindBuddy<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FindBuddy")
+            .field("buddy_id", &self.buddy_id())
+            .field("client_hash", &Hs(self.client_hash()))
+            .field("client_port", &self.client_port())
+            .field("connect_options", &self.connect_options())
+            .finish()
+    }
+}
+
+/// `CallbackReqV1`: sent to the buddy of a firewalled node, asking it to have the firewalled node
+/// connect to us
+///
+/// ```norust
+/// struct CallbackReq {
+///     // `buddy_id` from the `FindBuddyReqV1` of the firewalled node
+///     buddy_id: le128,
+///     // file we want from the firewalled node
+///     file_id: le128,
+///     // tcp port the firewalled node should connect to
+///     tcp_port: le16,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct CallbackReq<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> CallbackReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 16 + 2;
+        if raw.len() < need {
+            return Err(Error::CallbackReqTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(CallbackReq { raw })
+    }
+
+    pub fn buddy_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn file_id(&self) -> KadId {
+        KadId::from_slice(&self.raw[16..])
+    }
+
+    pub fn tcp_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[(16 + 16)..(16 + 16 + 2)].try_into().unwrap())
+    }
+}
+
+impl<'a> fmt::Debug for CallbackReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("CallbackReq")
+            .field("buddy_id", &self.buddy_id())
+            .field("file_id", &self.file_id())
+            .field("tcp_port", &self.tcp_port())
+            .finish()
+    }
+}
+
+/// `FirewallUdp`: sent in reply to a hello to check if the receiver's udp port is reachable
+///
+/// ```norust
+/// struct FirewallUdp {
+///     // non-zero if the sender could not perform the check
+///     error_code: u8,
+///     // the udp port the check is sent to
+///     incoming_port: le16,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FirewallUdp<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> FirewallUdp<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 1 + 2;
+        if raw.len() < need {
+            return Err(Error::FirewallTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(FirewallUdp { raw })
+    }
+
+    pub fn error_code(&self) -> u8 {
+        self.raw[0]
+    }
+
+    pub fn incoming_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[1..(1 + 2)].try_into().unwrap())
+    }
+}
+
+impl<'a> fmt::Debug for FirewallUdp<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FirewallUdp")
+            .field("error_code", &self.error_code())
+            .field("incoming_port", &self.incoming_port())
+            .finish()
+    }
+}
+
+/// `Pong`: reply to a `Ping`
+///
+/// ```norust
+/// struct Pong {
+///     // udp port the `Ping` was recieved from, see `extern_port`
+///     recv_port: le16,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Pong<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Pong<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 2;
+        if raw.len() < need {
+            return Err(Error::PongTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(Pong { raw })
+    }
+
+    /// udp port our `Ping` was recieved from, as seen by the sender of the `Pong`
+    pub fn recv_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[..2].try_into().unwrap())
+    }
+}
+
+impl<'a> fmt::Debug for Pong<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Pong")
+            .field("recv_port", &self.recv_port())
+            .finish()
+    }
+}
+
+/// `BootstrapResV0`: kad v0 reply to a `BootstrapReqV0`
+///
+/// ```norust
+/// struct BootstrapResV0 {
+///     num_contacts: le16,
+///     // same layout as `BootstrapResp` contacts, but the last byte is a contact type instead
+///     // of a version
+///     contacts: [Contact;num_contacts],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct BootstrapResV0<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> BootstrapResV0<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 2;
+        if raw.len() < need {
+            return Err(Error::BootstrapRespTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let r = BootstrapResV0 { raw };
+        r.contacts()?;
+        Ok(r)
+    }
+
+    pub fn num_contacts(&self) -> u16 {
+        u16::from_le_bytes(self.raw[..2].try_into().unwrap())
+    }
+
+    pub fn contacts(&self) -> Result<BootstrapRespContacts<'a>, Error> {
+        BootstrapRespContacts::from_slice(self.num_contacts(), &self.raw[2..])
+            .map_err(|e| e.offset_by(2))
+    }
+}
+
+impl<'a> fmt::Debug for BootstrapResV0<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("BootstrapResV0")
+            .field("num_contacts", &self.num_contacts())
+            .field("contacts", &self.contacts())
+            .finish()
+    }
+}
+
+/// `SearchReqV1`: kad v1 keyword or source search
+///
+/// ```norust
+/// struct SearchReqV1 {
+///     target: le128,
+///     // without `expr`: 1 for a source search, 0 for a keyword search
+///     restrictive: u8,
+///     // only present for keyword searches
+///     expr: Option<SearchExpr>,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SearchReqV1<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> SearchReqV1<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 1;
+        if raw.len() < need {
+            return Err(Error::SearchReqTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        if raw.len() > need {
+            SearchExpr::from_slice(&raw[need..]).map_err(|e| e.offset_by(need))?;
+        }
+
+        Ok(SearchReqV1 { raw })
+    }
+
+    pub fn target(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn is_restrictive(&self) -> bool {
+        self.raw[16] != 0
+    }
+
+    /// `true` if this is a search for sources of the file `target`
+    pub fn is_source_search(&self) -> bool {
+        self.is_restrictive() && self.raw.len() == 16 + 1
+    }
+
+    pub fn expr(&self) -> Option<SearchExpr<'a>> {
+        if self.raw.len() == 16 + 1 {
+            return None;
+        }
+
+        // NOTE: validated in `SearchReqV1::from_slice()`
+        Some(SearchExpr::from_slice(&self.raw[(16 + 1)..]).unwrap().0)
+    }
+}
+
+impl<'a> fmt::Debug for SearchReqV1<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SearchReqV1")
+            .field("target", &self.target())
+            .field("is_restrictive", &self.is_restrictive())
+            .field("expr", &self.expr())
+            .finish()
+    }
+}
+
+/// `SearchResV1`: kad v1 search results, like `SearchRes` without a `source_id`
+///
+/// ```norust
+/// struct SearchResV1 {
+///   target_id: le128,
+///   result_ct: le16,
+///   results: [SearchResult; result_ct],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SearchResV1<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> SearchResV1<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 2;
+        if raw.len() < need {
+            return Err(Error::SearchResTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let r = SearchResV1 { raw };
+        let (_, rem) = r.results()?;
+        if !rem.is_empty() {
+            return Err(Error::SearchResSpareBytes {
+                offset: raw.len() - rem.len(),
+                spare: rem.len(),
+            });
+        }
+
+        Ok(r)
+    }
+
+    pub fn target_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn result_ct(&self) -> u16 {
+        u16::from_le_bytes(self.raw[16..(16 + 2)].try_into().unwrap())
+    }
+
+    pub fn results(&self) -> Result<(SearchResults<'a>, &'a [u8]), Error> {
+        let pos = 16 + 2;
+        SearchResults::from_slice(self.result_ct(), &self.raw[pos..]).map_err(|e| e.offset_by(pos))
+    }
+}
+
+impl<'a> fmt::Debug for SearchResV1<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SearchResV1")
+            .field("target_id", &self.target_id())
+            .field("result_ct", &self.result_ct())
+            .field("results", &self.results())
+            .finish()
+    }
+}
+
+/// Availability of the parts of a file
+///
+/// ```norust
+/// struct PartStatus {
+///     // 0 if the sender has the complete file
+///     part_count: le16,
+///     // lsb first
+///     parts: [u8; (part_count + 7) / 8],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PartStatus<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> PartStatus<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        if raw.len() < 2 {
+            return Err(Error::PartStatusTooShort {
+                offset: 0,
+                have: raw.len(),
+                need: 2,
+            });
+        }
+
+        let part_count = u16::from_le_bytes(raw[..2].try_into().unwrap()) as usize;
+        let need = 2 + part_count.div_ceil(8);
+        if raw.len() < need {
+            return Err(Error::PartStatusTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let (raw, rem) = raw.split_at(need);
+        Ok((PartStatus { raw }, rem))
+    }
+
+    pub fn part_count(&self) -> u16 {
+        u16::from_le_bytes(self.raw[..2].try_into().unwrap())
+    }
+
+    /// the sender has every part of the file
+    pub fn is_complete(&self) -> bool {
+        self.part_count() == 0
+    }
+
+    /// `false` for parts beyond `part_count()`, even if `is_complete()`
+    pub fn has_part(&self, part: u16) -> bool {
+        if part >= self.part_count() {
+            return false;
+        }
+
+        let part = part as usize;
+        self.raw[2 + part / 8] & (1 << (part % 8)) != 0
+    }
+}
+
+impl<'a> fmt::Debug for PartStatus<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_complete() {
+            return fmt.write_str("PartStatus(complete)");
+        }
+
+        let have = (0..self.part_count()).filter(|&p| self.has_part(p)).count();
+        write!(fmt, "PartStatus({}/{})", have, self.part_count())
+    }
+}
+
+/// The parts of `ReAskFilePing` that depend on the sender's udp version (from its hello)
+#[derive(Debug, Clone)]
+pub struct ReAskExtended<'a> {
+    /// udp version > 3
+    pub part_status: Option<PartStatus<'a>>,
+    /// udp version > 2. Only sent with a part status if the extended requests version > 1.
+    pub complete_sources: Option<u16>,
+}
+
+/// `ReAskFilePing`: a client in our upload queue checking that it is still queued
+///
+/// ```norust
+/// struct ReAskFilePing {
+///     file_hash: [u8; 16],
+///     // udp version > 3
+///     part_status: Option<PartStatus>,
+///     // udp version > 2
+///     complete_sources: Option<le16>,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ReAskFilePing<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ReAskFilePing<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16;
+        if raw.len() < need {
+            return Err(Error::EmuleOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(ReAskFilePing { raw })
+    }
+
+    pub fn file_hash(&self) -> [u8; 16] {
+        self.raw[..16].try_into().unwrap()
+    }
+
+    /// Everything after `file_hash`, see `extended()`
+    pub fn extended_bytes(&self) -> &'a [u8] {
+        &self.raw[16..]
+    }
+
+    /// The layout of the rest of the packet depends on the udp version the sender reported in
+    /// its hello (which we can't see here).
+    pub fn extended(&self, udp_version: u8) -> Result<ReAskExtended<'a>, Error> {
+        let rem = self.extended_bytes();
+        let (part_status, rem) = if udp_version > 3 {
+            let (p, rem) = PartStatus::from_slice(rem).map_err(|e| e.offset_by(16))?;
+            (Some(p), rem)
+        } else {
+            (None, rem)
+        };
+
+        let complete_sources = if udp_version > 2 && rem.len() >= 2 {
+            Some(u16::from_le_bytes(rem[..2].try_into().unwrap()))
+        } else {
+            None
+        };
+
+        Ok(ReAskExtended {
+            part_status,
+            complete_sources,
+        })
+    }
+}
+
+impl<'a> fmt::Debug for ReAskFilePing<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ReAskFilePing")
+            .field("file_hash", &Hs(self.file_hash()))
+            .field("extended_bytes", &self.extended_bytes())
+            .finish()
+    }
+}
+
+/// `ReAskAck`: reply to a `ReAskFilePing` with our position in the sender's upload queue
+///
+/// ```norust
+/// struct ReAskAck {
+///     // udp version > 3
+///     part_status: Option<PartStatus>,
+///     queue_rank: le16,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ReAskAck<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ReAskAck<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 2;
+        if raw.len() < need {
+            return Err(Error::EmuleOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(ReAskAck { raw })
+    }
+
+    pub fn queue_rank(&self) -> u16 {
+        u16::from_le_bytes(self.raw[(self.raw.len() - 2)..].try_into().unwrap())
+    }
+
+    /// Only present if the sender has udp version > 3
+    pub fn part_status(&self) -> Result<Option<PartStatus<'a>>, Error> {
+        let b = &self.raw[..(self.raw.len() - 2)];
+        if b.is_empty() {
+            return Ok(None);
+        }
+
+        PartStatus::from_slice(b).map(|(p, _)| Some(p))
+    }
+}
+
+impl<'a> fmt::Debug for ReAskAck<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ReAskAck")
+            .field("part_status", &self.part_status())
+            .field("queue_rank", &self.queue_rank())
+            .finish()
+    }
+}
+
+/// `ReAskCallBackUdp`: a `ReAskFilePing` sent via the buddy of a firewalled client
+///
+/// ```norust
+/// struct ReAskCallBackUdp {
+///     buddy_id: le128,
+///     reask: ReAskFilePing,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ReAskCallBackUdp<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ReAskCallBackUdp<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 16 + 16;
+        if raw.len() < need {
+            return Err(Error::EmuleOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(ReAskCallBackUdp { raw })
+    }
+
+    pub fn buddy_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    /// the `ReAskFilePing` to forward to the firewalled client
+    pub fn reask(&self) -> ReAskFilePing<'a> {
+        ReAskFilePing {
+            raw: &self.raw[16..],
+        }
+    }
+}
+
+impl<'a> fmt::Debug for ReAskCallBackUdp<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ReAskCallBackUdp")
+            .field("buddy_id", &self.buddy_id())
+            .field("reask", &self.reask())
+            .finish()
+    }
+}
+
+/// `DirectCallbackReq`: ask a firewalled client (that supports direct callbacks) to connect to
+/// us over tcp
+///
+/// ```norust
+/// struct DirectCallbackReq {
+///     tcp_port: le16,
+///     user_hash: [u8; 16],
+///     connect_options: u8,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DirectCallbackReq<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> DirectCallbackReq<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 2 + 16 + 1;
+        if raw.len() < need {
+            return Err(Error::EmuleOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(DirectCallbackReq { raw })
+    }
+
+    pub fn tcp_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[..2].try_into().unwrap())
+    }
+
+    pub fn user_hash(&self) -> [u8; 16] {
+        self.raw[2..(2 + 16)].try_into().unwrap()
+    }
+
+    pub fn connect_options(&self) -> u8 {
+        self.raw[2 + 16]
+    }
+}
+
+impl<'a> fmt::Debug for DirectCallbackReq<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("DirectCallbackReq")
+            .field("tcp_port", &self.tcp_port())
+            .field("user_hash", &Hs(self.user_hash()))
+            .field("connect_options", &self.connect_options())
+            .finish()
+    }
+}
+
+/// If `raw` continues with another `UdpProto::Edonkey` `opcode` header, skip it.
+///
+/// Servers may pack several responses into one datagram, each with its own header.
+fn skip_server_header(raw: &[u8], opcode: ServerOpCode) -> Option<&[u8]> {
+    match raw {
+        [p, o, rem @ ..] if *p == UdpProto::Edonkey as u8 && *o == opcode as u8 => Some(rem),
+        _ => None,
+    }
+}
+
+/// `GlobGetSources`: ask a server for sources of one or more files
+///
+/// ```norust
+/// struct GetSources {
+///     file_hashes: [[u8; 16]; _],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct GetSources<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> GetSources<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        if raw.is_empty() || !raw.len().is_multiple_of(16) {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need: (raw.len() / 16 + 1) * 16,
+            });
+        }
+
+        Ok(GetSources { raw })
+    }
+
+    pub fn file_hashes(&self) -> impl Iterator<Item = [u8; 16]> + 'a {
+        self.raw.chunks_exact(16).map(|c| c.try_into().unwrap())
+    }
+}
+
+impl<'a> fmt::Debug for GetSources<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list()
+            .entries(self.file_hashes().map(Hs))
+            .finish()
+    }
+}
+
+/// `GlobGetSources2`: like `GetSources`, but including the size of each file
+///
+/// ```norust
+/// struct GetSources2 {
+///     entries: [Entry; _],
+/// }
+///
+/// struct Entry {
+///     file_hash: [u8; 16],
+///     // 0 for files over 4GiB, which are followed by the full size
+///     file_size: le32,
+///     large_file_size: Option<le64>,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct GetSources2<'a> {
+    raw: &'a [u8],
+}
+
+/// `(file_hash, file_size)`
+type FileEntry = ([u8; 16], u64);
+
+impl<'a> GetSources2<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        if raw.is_empty() {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: 0,
+                need: 20,
+            });
+        }
+
+        let mut rem = raw;
+        while !rem.is_empty() {
+            let (_, rr) = Self::split_entry(rem).map_err(|e| e.offset_by(raw.len() - rem.len()))?;
+            rem = rr;
+        }
+
+        Ok(GetSources2 { raw })
+    }
+
+    fn split_entry(raw: &[u8]) -> Result<(FileEntry, &[u8]), Error> {
+        let need = 16 + 4;
+        if raw.len() < need {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let file_hash = raw[..16].try_into().unwrap();
+        let file_size = u32::from_le_bytes(raw[16..need].try_into().unwrap());
+        if file_size != 0 {
+            return Ok(((file_hash, file_size.into()), &raw[need..]));
+        }
+
+        let need = need + 8;
+        if raw.len() < need {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let file_size = u64::from_le_bytes(raw[(need - 8)..need].try_into().unwrap());
+        Ok(((file_hash, file_size), &raw[need..]))
+    }
+
+    /// `(file_hash, file_size)`
+    pub fn entries(&self) -> impl Iterator<Item = ([u8; 16], u64)> + 'a {
+        let mut rem = self.raw;
+        std::iter::from_fn(move || {
+            if rem.is_empty() {
+                return None;
+            }
+
+            // NOTE: validated in `GetSources2::from_slice()`
+            let (e, rr) = Self::split_entry(rem).unwrap();
+            rem = rr;
+            Some(e)
+        })
+    }
+}
+
+impl<'a> fmt::Debug for GetSources2<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.entries()).finish()
+    }
+}
+
+/// `GlobFoundSources`: sources for one or more files, in reply to `GlobGetSources`
+///
+/// ```norust
+/// struct FoundSources {
+///     first: FoundSourcesEntry,
+///     // each prefixed by `[UdpProto::Edonkey, ServerOpCode::GlobFoundSources]`
+///     more: [FoundSourcesEntry; _],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FoundSources<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> FoundSources<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let mut rem = raw;
+        loop {
+            let (_, rr) = FoundSourcesEntry::from_slice(rem)
+                .map_err(|e| e.offset_by(raw.len() - rem.len()))?;
+            match skip_server_header(rr, ServerOpCode::GlobFoundSources) {
+                Some(rr) => rem = rr,
+                None => {
+                    // like emule, ignore anything else following the entries
+                    return Ok(FoundSources {
+                        raw: &raw[..(raw.len() - rr.len())],
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn entries(&self) -> FoundSourcesIter<'a> {
+        FoundSourcesIter { raw: self.raw }
+    }
+}
+
+impl<'a> fmt::Debug for FoundSources<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.entries()).finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct FoundSourcesIter<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Iterator for FoundSourcesIter<'a> {
+    type Item = FoundSourcesEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.raw.is_empty() {
+            return None;
+        }
+
+        // NOTE: validated in `FoundSources::from_slice()`
+        let (e, rem) = FoundSourcesEntry::from_slice(self.raw).unwrap();
+        self.raw = skip_server_header(rem, ServerOpCode::GlobFoundSources).unwrap_or(rem);
+        Some(e)
+    }
+}
+
+/// ```norust
+/// struct FoundSourcesEntry {
+///     file_hash: [u8; 16],
+///     count: u8,
+///     sources: [Source; count],
+/// }
+///
+/// struct Source {
+///     // a client id below 0x1000000 is a low id, and not an address
+///     ip: le32,
+///     tcp_port: le16,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FoundSourcesEntry<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> FoundSourcesEntry<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let need = 16 + 1;
+        if raw.len() < need {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let need = need + raw[16] as usize * (4 + 2);
+        if raw.len() < need {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let (raw, rem) = raw.split_at(need);
+        Ok((FoundSourcesEntry { raw }, rem))
+    }
+
+    pub fn file_hash(&self) -> [u8; 16] {
+        self.raw[..16].try_into().unwrap()
+    }
+
+    pub fn count(&self) -> u8 {
+        self.raw[16]
+    }
+
+    /// `(client_id, tcp_port)`, where a client id is an ip address unless it is a low id
+    pub fn sources(&self) -> impl Iterator<Item = (u32, u16)> + 'a {
+        self.raw[(16 + 1)..].chunks_exact(4 + 2).map(|c| {
+            (
+                u32::from_le_bytes(c[..4].try_into().unwrap()),
+                u16::from_le_bytes(c[4..].try_into().unwrap()),
+            )
+        })
+    }
+}
+
+impl<'a> fmt::Debug for FoundSourcesEntry<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("FoundSourcesEntry")
+            .field("file_hash", &Hs(self.file_hash()))
+            .field("sources", &self.sources().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// `GlobSearchRes`: files matching a `GlobSearchReq`
+///
+/// ```norust
+/// struct GlobSearchRes {
+///     first: ServerSearchResult,
+///     // each prefixed by `[UdpProto::Edonkey, ServerOpCode::GlobSearchRes]`
+///     more: [ServerSearchResult; _],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct GlobSearchRes<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> GlobSearchRes<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let mut rem = raw;
+        loop {
+            let (_, rr) = ServerSearchResult::from_slice(rem)
+                .map_err(|e| e.offset_by(raw.len() - rem.len()))?;
+            match skip_server_header(rr, ServerOpCode::GlobSearchRes) {
+                Some(rr) => rem = rr,
+                None => {
+                    // like emule, ignore anything else following the results
+                    return Ok(GlobSearchRes {
+                        raw: &raw[..(raw.len() - rr.len())],
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn results(&self) -> ServerSearchResults<'a> {
+        ServerSearchResults { raw: self.raw }
+    }
+}
+
+impl<'a> fmt::Debug for GlobSearchRes<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.results()).finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct ServerSearchResults<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Iterator for ServerSearchResults<'a> {
+    type Item = ServerSearchResult<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.raw.is_empty() {
+            return None;
+        }
+
+        // NOTE: validated in `GlobSearchRes::from_slice()`
+        let (r, rem) = ServerSearchResult::from_slice(self.raw).unwrap();
+        self.raw = skip_server_header(rem, ServerOpCode::GlobSearchRes).unwrap_or(rem);
+        Some(r)
+    }
+}
+
+/// A file found by a server search
+///
+/// ```norust
+/// struct ServerSearchResult {
+///     file_hash: [u8; 16],
+///     // of a client sharing the file, often 0
+///     client_id: le32,
+///     client_port: le16,
+///     tags: TagList32,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ServerSearchResult<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ServerSearchResult<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let need = 16 + 4 + 2;
+        if raw.len() < need {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let (_, rem) = TagList32::from_slice(&raw[need..])?;
+        let (raw, rem) = raw.split_at(raw.len() - rem.len());
+        Ok((ServerSearchResult { raw }, rem))
+    }
+
+    pub fn file_hash(&self) -> [u8; 16] {
+        self.raw[..16].try_into().unwrap()
+    }
+
+    pub fn client_id(&self) -> u32 {
+        u32::from_le_bytes(self.raw[16..(16 + 4)].try_into().unwrap())
+    }
+
+    pub fn client_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[(16 + 4)..(16 + 4 + 2)].try_into().unwrap())
+    }
+
+    pub fn tags(&self) -> TagList32<'a> {
+        // NOTE: validated in `ServerSearchResult::from_slice()`
+        TagList32::from_slice(&self.raw[(16 + 4 + 2)..]).unwrap().0
+    }
+
+    /// `TAG_FILENAME`
+    pub fn file_name(&self) -> Option<&'a [u8]> {
+        self.tags().get(TagId::FileName).and_then(|v| v.as_bytes())
+    }
+
+    /// `TAG_FILESIZE` combined with `TAG_FILESIZE_HI`
+    pub fn file_size(&self) -> Option<u64> {
+        let lo = self.tags().get(TagId::FileSize)?.as_u64()?;
+        let hi = self
+            .tags()
+            .get(TagId::FileSizeHi)
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0);
+        Some(lo | hi << 32)
+    }
+
+    /// `TAG_SOURCES`
+    pub fn sources(&self) -> Option<u32> {
+        self.tags()
+            .get(TagId::Sources)
+            .and_then(|v| v.as_u64())
+            .and_then(|v| v.try_into().ok())
+    }
+}
+
+impl<'a> fmt::Debug for ServerSearchResult<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ServerSearchResult")
+            .field("file_hash", &Hs(self.file_hash()))
+            .field("client_id", &self.client_id())
+            .field("client_port", &self.client_port())
+            .field("tags", &self.tags())
+            .finish()
+    }
+}
+
+/// `GlobServStatRes`: server load, in reply to `GlobServStatReq`
+///
+/// Older servers stop after any of the optional fields.
+///
+/// ```norust
+/// struct ServStatRes {
+///     challenge: le32,
+///     users: le32,
+///     files: le32,
+///     max_users: Option<le32>,
+///     soft_files: Option<le32>,
+///     hard_files: Option<le32>,
+///     // `SRV_UDPFLG_*`
+///     udp_flags: Option<le32>,
+///     low_id_users: Option<le32>,
+///     udp_obfuscation_port: Option<le16>,
+///     tcp_obfuscation_port: Option<le16>,
+///     udp_key: Option<le32>,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ServStatRes<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ServStatRes<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let need = 4 + 4 + 4;
+        if raw.len() < need {
+            return Err(Error::ServerOpTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        Ok(ServStatRes { raw })
+    }
+
+    fn u32_at(&self, offs: usize) -> Option<u32> {
+        self.raw
+            .get(offs..(offs + 4))
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u16_at(&self, offs: usize) -> Option<u16> {
+        self.raw
+            .get(offs..(offs + 2))
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    pub fn challenge(&self) -> u32 {
+        self.u32_at(0).unwrap()
+    }
+
+    pub fn users(&self) -> u32 {
+        self.u32_at(4).unwrap()
+    }
+
+    pub fn files(&self) -> u32 {
+        self.u32_at(8).unwrap()
+    }
+
+    pub fn max_users(&self) -> Option<u32> {
+        self.u32_at(12)
+    }
+
+    pub fn soft_files(&self) -> Option<u32> {
+        // emule only reads the soft & hard limits as a pair
+        self.u32_at(20).and(self.u32_at(16))
+    }
+
+    pub fn hard_files(&self) -> Option<u32> {
+        self.u32_at(20)
+    }
+
+    /// `SRV_UDPFLG_*`
+    pub fn udp_flags(&self) -> Option<u32> {
+        self.u32_at(24)
+    }
+
+    pub fn low_id_users(&self) -> Option<u32> {
+        self.u32_at(28)
+    }
+
+    pub fn udp_obfuscation_port(&self) -> Option<u16> {
+        self.udp_key().and(self.u16_at(32))
+    }
+
+    pub fn tcp_obfuscation_port(&self) -> Option<u16> {
+        self.udp_key().and(self.u16_at(34))
+    }
+
+    pub fn udp_key(&self) -> Option<u32> {
+        self.u32_at(36)
+    }
+}
+
+impl<'a> fmt::Debug for ServStatRes<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ServStatRes")
+            .field("challenge", &self.challenge())
+            .field("users", &self.users())
+            .field("files", &self.files())
+            .field("max_users", &self.max_users())
+            .field("soft_files", &self.soft_files())
+            .field("hard_files", &self.hard_files())
+            .field("udp_flags", &self.udp_flags())
+            .field("low_id_users", &self.low_id_users())
+            .field("udp_obfuscation_port", &self.udp_obfuscation_port())
+            .field("tcp_obfuscation_port", &self.tcp_obfuscation_port())
+            .field("udp_key", &self.udp_key())
+            .finish()
+    }
+}
+
+/// `ServerDescRes`: a server's name & description, in reply to `ServerDescReq`
+///
+/// There are 2 layouts, told apart by the first 2 bytes being `SERVER_DESC_CHALLENGE`:
+///
+/// ```norust
+/// struct ServerDescResV0 {
+///     name_len: le16,
+///     name: [u8; name_len],
+///     description_len: le16,
+///     description: [u8; description_len],
+/// }
+///
+/// struct ServerDescRes {
+///     challenge: le32,
+///     // `ST_*`
+///     tags: TagList32,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ServerDescRes<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ServerDescRes<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<Self, Error> {
+        let r = ServerDescRes { raw };
+        if r.has_challenge() {
+            TagList32::from_slice(&raw[4..])?;
+        } else {
+            let (_, rem) = split_str16(raw)?;
+            split_str16(rem).map_err(|e| e.offset_by(raw.len() - rem.len()))?;
+        }
+
+        Ok(r)
+    }
+
+    fn has_challenge(&self) -> bool {
+        self.raw.len() >= 8 && self.raw[..2] == SERVER_DESC_CHALLENGE.to_le_bytes()
+    }
+
+    /// The challenge from the `ServerDescReq`, if the server uses the tagged layout
+    pub fn challenge(&self) -> Option<u32> {
+        if !self.has_challenge() {
+            return None;
+        }
+
+        Some(u32::from_le_bytes(self.raw[..4].try_into().unwrap()))
+    }
+
+    /// `None` for the old layout
+    pub fn tags(&self) -> Option<TagList32<'a>> {
+        if !self.has_challenge() {
+            return None;
+        }
+
+        // NOTE: validated in `ServerDescRes::from_slice()`
+        Some(TagList32::from_slice(&self.raw[4..]).unwrap().0)
+    }
+
+    fn find_bytes(&self, name: &[u8]) -> Option<&'a [u8]> {
+        self.tags()?.find(name).and_then(|t| t.value().as_bytes())
+    }
+
+    /// `ST_SERVERNAME`
+    pub fn name(&self) -> Option<&'a [u8]> {
+        if self.has_challenge() {
+            return self.find_bytes(ST_SERVERNAME);
+        }
+
+        // NOTE: validated in `ServerDescRes::from_slice()`
+        Some(split_str16(self.raw).unwrap().0)
+    }
+
+    /// `ST_DESCRIPTION`
+    pub fn description(&self) -> Option<&'a [u8]> {
+        if self.has_challenge() {
+            return self.find_bytes(ST_DESCRIPTION);
+        }
+
+        // NOTE: validated in `ServerDescRes::from_slice()`
+        let (_, rem) = split_str16(self.raw).unwrap();
+        Some(split_str16(rem).unwrap().0)
+    }
+}
+
+impl<'a> fmt::Debug for ServerDescRes<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ServerDescRes")
+            .field("challenge", &self.challenge())
+            .field("name", &self.name().map(String::from_utf8_lossy))
+            .field(
+                "description",
+                &self.description().map(String::from_utf8_lossy),
+            )
+            .field("tags", &self.tags())
+            .finish()
+    }
+}
+
+///
+///
+/// ```notrust
+/// struct TagList {
+///    // kad uses a single byte count
+///    count: u8,
+///    // `Tag` size is variable
+///    tags: [Tag; count],
+/// }
+///
+/// struct Tag {
+///     // `type | TAG_COMPACT_NAME` for a 1 byte name without `name_len`
+///     type: u8,
+///     name_len: u16,
+///     // not necessarily null terminated
+///     name: [u8; name_len];
+///     // Value size & interp determined by `type`
+///     value: Value(type),
+/// }
+///
+/// union Value {
+///     Hash([u8;16]),
+///     String { len: le16, string: [u8; len] },
+///     Uint64(le64),
+///     Uint32(le32),
+///     Uint16(le16),
+///     Uint8(u8),
+///     Float32(f32),
+///     Bool(u8),
+///     BoolArray { len: le16, bits: [u8; len / 8 + 1] },
+///     Blob { len: le32, blob: [u8; len] },
+///     Bsob { len: u8, bsob: [u8; len] },
+///     // type 0x11..=0x20
+///     StrN([u8; type - 0x10]),
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TagList<'a> {
+    raw: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Primitive, Debug)]
+#[repr(u8)]
+pub enum TagType {
+    Hash = 0x01,
+    String_ = 0x02,
+    Uint32 = 0x03,
+    Float32 = 0x04,
+    /// emule skips these when reading
+    Bool = 0x05,
+    /// emule skips these when reading
+    BoolArray = 0x06,
+    Blob = 0x07,
+    Uint16 = 0x08,
+    Uint8 = 0x09,
+    /// used by kad for binary values under 256 bytes
+    Bsob = 0x0A,
+    Uint64 = 0x0B,
+    /// compact strings, the length (1 to 16) is `tag_type - 0x10` & there is no length prefix
+    Str1 = 0x11,
+    Str2 = 0x12,
+    Str3 = 0x13,
+    Str4 = 0x14,
+    Str5 = 0x15,
+    Str6 = 0x16,
+    Str7 = 0x17,
+    Str8 = 0x18,
+    Str9 = 0x19,
+    Str10 = 0x1A,
+    Str11 = 0x1B,
+    Str12 = 0x1C,
+    Str13 = 0x1D,
+    Str14 = 0x1E,
+    Str15 = 0x1F,
+    Str16 = 0x20,
+}
+
+impl TagType {
+    /// For `TagType::Str1..=TagType::Str16`, the length of the string
+    pub fn compact_str_len(self) -> Option<usize> {
+        match self as u8 {
+            t @ 0x11..=0x20 => Some((t - 0x10) as usize),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> TagList<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        if raw.is_empty() {
+            return Err(Error::TagListTooShort {
+                need: 1,
+                have: raw.len(),
+            });
+        }
+
+        let tl = TagList { raw };
+
+        let mut ct = tl.count();
+        let mut rem = tl.item_bytes();
+        loop {
+            if ct == 0 {
+                return Ok((
+                    TagList {
+                        raw: &raw[..(raw.len() - rem.len())],
+                    },
+                    rem,
+                ));
+            }
+
+            ct -= 1;
+            let (_, rr) = Tag::from_slice(rem)?;
+            rem = rr;
+        }
+    }
+}
+
+impl<'a> TagList<'a> {
+    pub fn count(&self) -> u8 {
+        self.raw[0]
+    }
+
+    fn item_bytes(&self) -> &'a [u8] {
+        &self.raw[1..]
+    }
+
+    pub fn iter(&self) -> TagListIter<'a> {
+        TagListIter::from_slice(self.item_bytes())
+    }
+
+    /// The first tag named `name`
+    pub fn find(&self, name: &[u8]) -> Option<Tag<'a>> {
+        // NOTE: validated in `TagList::from_slice()`
+        self.iter().find_name(name)
+    }
+
+    /// The value of the first tag with `id`, including old string names (see `TagId::from_name`)
+    pub fn get(&self, id: TagId) -> Option<TagValue<'a>> {
+        // NOTE: validated in `TagList::from_slice()`
+        self.iter().get(id)
+    }
+
+    /// Check the tag count, tag names and string values against `limits`
+    pub fn check_limits(&self, limits: &DecodeLimits) -> Result<(), Error> {
+        limits.check_tags(self.count() as usize, self.iter(), self.raw)
+    }
+
+    /// Like `check_limits()`, with offsets counting from the start of `outer`, which holds the
+    /// tag list
+    fn check_limits_within(&self, limits: &DecodeLimits, outer: &[u8]) -> Result<(), Error> {
+        self.check_limits(limits)
+            .map_err(|e| e.offset_by(offset_in(self.raw, outer)))
+    }
+}
+
+impl<'a> fmt::Debug for TagList<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// A `TagList` with a 32-bit count, as used by ed2k servers
+///
+/// ```norust
+/// struct TagList32 {
+///    count: le32,
+///    tags: [Tag; count],
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TagList32<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> TagList32<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        if raw.len() < 4 {
+            return Err(Error::TagListTooShort {
+                need: 4,
+                have: raw.len(),
+            });
+        }
+
+        let tl = TagList32 { raw };
+        let mut rem = tl.item_bytes();
+        for _ in 0..tl.count() {
+            let (_, rr) = Tag::from_slice(rem)?;
+            rem = rr;
+        }
+
+        let (raw, rem) = raw.split_at(raw.len() - rem.len());
+        Ok((TagList32 { raw }, rem))
+    }
+
+    pub fn count(&self) -> u32 {
+        u32::from_le_bytes(self.raw[..4].try_into().unwrap())
+    }
+
+    fn item_bytes(&self) -> &'a [u8] {
+        &self.raw[4..]
+    }
+
+    pub fn iter(&self) -> TagListIter<'a> {
+        TagListIter::from_slice(self.item_bytes())
+    }
+
+    /// The first tag named `name`
+    pub fn find(&self, name: &[u8]) -> Option<Tag<'a>> {
+        // NOTE: validated in `TagList32::from_slice()`
+        self.iter().find_name(name)
+    }
+
+    /// The value of the first tag with `id`, including old string names (see `TagId::from_name`)
+    pub fn get(&self, id: TagId) -> Option<TagValue<'a>> {
+        // NOTE: validated in `TagList32::from_slice()`
+        self.iter().get(id)
+    }
+
+    /// Check the tag count, tag names and string values against `limits`
+    pub fn check_limits(&self, limits: &DecodeLimits) -> Result<(), Error> {
+        limits.check_tags(self.count() as usize, self.iter(), self.raw)
+    }
+
+    /// Like `check_limits()`, with offsets counting from the start of `outer`, which holds the
+    /// tag list
+    fn check_limits_within(&self, limits: &DecodeLimits, outer: &[u8]) -> Result<(), Error> {
+        self.check_limits(limits)
+            .map_err(|e| e.offset_by(offset_in(self.raw, outer)))
+    }
+}
+
+impl<'a> fmt::Debug for TagList32<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// NOTE: we use a seperate iterator here because the prefixed count would otherwise interfere
+#[derive(Clone)]
+pub struct TagListIter<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> TagListIter<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Self {
+        Self { raw }
+    }
+
+    /// The first tag named `name`, skipping invalid tags
+    pub fn find_name(self, name: &[u8]) -> Option<Tag<'a>> {
+        self.filter_map(Result::ok).find(|t| t.name() == name)
+    }
+
+    /// The value of the first tag with `id`, skipping invalid tags
+    pub fn get(self, id: TagId) -> Option<TagValue<'a>> {
+        self.filter_map(Result::ok)
+            .find(|t| t.id() == Some(id))
+            .map(|t| t.value())
+    }
+}
+
+impl<'a> Iterator for TagListIter<'a> {
+    type Item = Result<Tag<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.raw.is_empty() {
+            return None;
+        }
+
+        match Tag::from_slice(self.raw) {
+            Ok((i, rem)) => {
+                self.raw = rem;
+                Some(Ok(i))
+            }
+            Err(e) => {
+                // don't keep returning the same error
+                self.raw = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Set in the type byte of a tag with a 1 byte name (and no `name_len`)
+pub const TAG_COMPACT_NAME: u8 = 0x80;
+
+/// ```norust
+/// struct Tag {
+///     // `TAG_COMPACT_NAME` may be set
+///     tag_type: u8,
+///     // only if `TAG_COMPACT_NAME` is clear
+///     name_len: u16,
+///     // 1 byte if `TAG_COMPACT_NAME` is set
+///     name: [u8;name_len],
+///     value: [u8;tag_size(tag_type)],
+/// }
+/// ```
+pub struct Tag<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Tag<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let need_size = 1;
+        if raw.len() < need_size {
+            return Err(Error::TagSizeMismatch {
+                need: need_size,
+                have: raw.len(),
+            });
+        }
+
+        let (value_offs, name_len) = if raw[0] & TAG_COMPACT_NAME != 0 {
+            (1 + 1, 1)
+        } else {
+            let need_size = 1 + 2;
+            if raw.len() < need_size {
+                return Err(Error::TagSizeMismatch {
+                    need: need_size,
+                    have: raw.len(),
+                });
+            }
+
+            let name_len = u16::from_le_bytes(raw[1..3].try_into().unwrap()) as usize;
+            (1 + 2 + name_len, name_len)
+        };
+
+        let need_size = value_offs;
+        if raw.len() < need_size {
+            return Err(Error::TagSizeMismatchName {
+                need: need_size,
+                have: raw.len(),
+                name_len,
+            });
+        }
+
+        let tag_type = match TagType::from_u8(raw[0] & !TAG_COMPACT_NAME) {
+            Some(v) => v,
+            None => return Err(Error::TagInvalid { value: raw[0] }),
+        };
+
+        // the size of any length prefix, which must be present to find the content size
+        let prefix_bytes = match tag_type {
+            TagType::String_ | TagType::BoolArray => 2,
+            TagType::Blob => 4,
+            TagType::Bsob => 1,
+            _ => 0,
+        };
+        let need_size = need_size + prefix_bytes;
+        if raw.len() < need_size {
+            return Err(match tag_type {
+                TagType::String_ => Error::TagSizeMismatchForString {
+                    need: need_size,
+                    have: raw.len(),
+                },
+                _ => Error::TagSizeMismatchContent {
+                    need: need_size,
+                    have: raw.len(),
+                },
+            });
+        }
+
+        let prefix = &raw[value_offs..need_size];
+        let content_bytes = match tag_type {
+            TagType::Hash => 16,
+            TagType::String_ => 2 + u16::from_le_bytes(prefix.try_into().unwrap()) as usize,
+            TagType::Uint64 => 8,
+            TagType::Uint32 => 4,
+            TagType::Uint16 => 2,
+            TagType::Uint8 => 1,
+            TagType::Float32 => 4,
+            TagType::Bool => 1,
+            // emule before 0.42e used `(len + 7) / 8`, and everyone since has sent a spare byte
+            TagType::BoolArray => {
+                2 + u16::from_le_bytes(prefix.try_into().unwrap()) as usize / 8 + 1
+            }
+            TagType::Blob => 4 + u32::from_le_bytes(prefix.try_into().unwrap()) as usize,
+            TagType::Bsob => 1 + prefix[0] as usize,
+            compact => compact.compact_str_len().unwrap(),
+        };
+
+        let need_size = value_offs + content_bytes;
+
+        if raw.len() < need_size {
+            return Err(Error::TagSizeMismatchContent {
+                have: raw.len(),
+                need: need_size,
+            });
+        }
+
+        let (a, rem) = raw.split_at(need_size);
+
+        Ok((Tag { raw: a }, rem))
+    }
+
+    /// `true` if the name was sent as a single byte with `TAG_COMPACT_NAME`
+    pub fn has_compact_name(&self) -> bool {
+        self.raw[0] & TAG_COMPACT_NAME != 0
+    }
+
+    fn name_offs(&self) -> usize {
+        if self.has_compact_name() {
+            1
+        } else {
+            1 + 2
+        }
+    }
+
+    fn name_len(&self) -> usize {
+        if self.has_compact_name() {
+            1
+        } else {
+            u16::from_le_bytes(self.raw[1..3].try_into().unwrap()) as usize
+        }
+    }
+
+    pub fn name(&self) -> &'a [u8] {
+        &self.raw[self.name_offs()..(self.name_offs() + self.name_len())]
+    }
+
+    /// The well known name, if this is one
+    pub fn id(&self) -> Option<TagId> {
+        TagId::from_name(self.name())
+    }
+
+    /// The type as sent, for compact strings this is one of `TagType::Str1..=TagType::Str16`
+    pub fn tag_type(&self) -> TagType {
+        TagType::from_u8(self.raw[0] & !TAG_COMPACT_NAME).unwrap()
+    }
+
+    pub fn value_bytes(&self) -> &'a [u8] {
+        &self.raw[(self.name_offs() + self.name_len())..]
+    }
+
+    pub fn value(&self) -> TagValue<'a> {
+        let v = self.value_bytes();
+        match self.tag_type() {
+            TagType::Hash => TagValue::Hash(v),
+            TagType::String_ => TagValue::String_(&v[2..]),
+            TagType::Uint64 => TagValue::Uint64(u64::from_le_bytes(v.try_into().unwrap())),
+            TagType::Uint32 => TagValue::Uint32(u32::from_le_bytes(v.try_into().unwrap())),
+            TagType::Uint16 => TagValue::Uint16(u16::from_le_bytes(v.try_into().unwrap())),
+            TagType::Uint8 => TagValue::Uint8(v[0]),
+            TagType::Float32 => TagValue::Float32(f32::from_le_bytes(v.try_into().unwrap())),
+            TagType::Bool => TagValue::Bool(v[0] != 0),
+            TagType::BoolArray => TagValue::BoolArray {
+                len: u16::from_le_bytes(v[..2].try_into().unwrap()),
+                bits: &v[2..],
+            },
+            TagType::Blob => TagValue::Blob(&v[4..]),
+            TagType::Bsob => TagValue::Bsob(&v[1..]),
+            // compact strings have no length prefix
+            _ => TagValue::String_(v),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Tag<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Tag")
+            .field("name", &self.name())
+            .field("tag_type", &self.tag_type())
+            .field("value", &self.value())
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum TagValue<'a> {
+    Hash(&'a [u8]),
+    /// Both `TagType::String_` and the compact `TagType::Str1..=TagType::Str16`
+    String_(&'a [u8]),
+    Uint64(u64),
+    Uint32(u32),
+    Uint16(u16),
+    Uint8(u8),
+    Float32(f32),
+    Bool(bool),
+    /// `len` bools, lsb first. `bits` may have a spare byte.
+    BoolArray {
+        len: u16,
+        bits: &'a [u8],
+    },
+    Blob(&'a [u8]),
+    Bsob(&'a [u8]),
+}
+
+impl<'a> TagValue<'a> {
+    /// emule treats all the integer types as interchangable
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            TagValue::Uint64(v) => Some(v),
+            TagValue::Uint32(v) => Some(v.into()),
+            TagValue::Uint16(v) => Some(v.into()),
+            TagValue::Uint8(v) => Some(v.into()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            TagValue::String_(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BootstrapRespContacts<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> BootstrapRespContacts<'a> {
+    pub fn from_slice(num: u16, raw: &'a [u8]) -> Result<Self, Error> {
+        // We don't use `num` except for validation.
+        let each_size = 16 + 4 + 2 + 2 + 1;
+        let need_size = each_size * num as usize;
+        if raw.len() < need_size {
+            return Err(Error::BootstrapRespContactTooShort {
+                offset: raw.len() / each_size * each_size,
+                have: raw.len() % each_size,
+                need: each_size,
+            });
+        }
+        if raw.len() != need_size {
+            return Err(Error::BootstrapRespContactsSizeMismatch {
+                need: need_size,
+                have: raw.len(),
+            });
+        }
+
+        Ok(BootstrapRespContacts { raw })
+    }
+}
+
+impl<'a> fmt::Debug for BootstrapRespContacts<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a> Iterator for BootstrapRespContacts<'a> {
+    type Item = BootstrapRespContact<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.raw.is_empty() {
+            // NOTE: validated in `BootstrapRespContacts::from_slice()`
+            let (r, rem) = BootstrapRespContact::from_slice(self.raw).unwrap();
+            self.raw = rem;
+            Some(r)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct BootstrapRespContact<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> BootstrapRespContact<'a> {
+    pub fn from_slice(raw: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let need = 16 + 4 + 2 + 2 + 1;
+        if raw.len() < need {
+            return Err(Error::BootstrapRespContactTooShort {
+                offset: 0,
+                have: raw.len(),
+                need,
+            });
+        }
+
+        let (raw, rem) = raw.split_at(need);
+
+        Ok((BootstrapRespContact { raw }, rem))
+    }
+
+    pub fn client_id(&self) -> KadId {
+        KadId::from_slice(self.raw)
+    }
+
+    pub fn raw_ip_addr(&self) -> u32 {
+        u32::from_le_bytes(self.raw[16..(16 + 4)].try_into().unwrap())
+    }
+
+    pub fn ip_addr(&self) -> std::net::Ipv4Addr {
+        std::net::Ipv4Addr::from(self.raw_ip_addr())
+    }
+
+    pub fn udp_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[(16 + 4)..(16 + 4 + 2)].try_into().unwrap())
+    }
+
+    pub fn tcp_port(&self) -> u16 {
+        u16::from_le_bytes(self.raw[(16 + 4 + 2)..(16 + 4 + 2 + 2)].try_into().unwrap())
+    }
+
+    pub fn version(&self) -> u8 {
+        self.raw[16 + 4 + 2 + 2]
+    }
+}
+
+impl<'a> fmt::Debug for BootstrapRespContact<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("BootstrapRespContact")
+            .field("client_id", &self.client_id())
+            .field("ip_addr", &self.ip_addr())
+            .field("udp_port", &self.udp_port())
+            .field("tcp_port", &self.tcp_port())
+            .field("version", &self.version())
+            .finish()
+    }
+}
+
+/// Owned, non-parsing version of `Operation`
+pub enum OperationBuf {
+    BootstrapReq,
+
+    /// kad v0 bootstrap, which includes our contact details. Current clients ignore it.
+    BootstrapReqV0(ContactBuf),
+
+    /// Reply to a `BootstrapReq` with some of our contacts
+    BootstrapResp {
+        client_id: KadId,
+        client_port: u16,
+        client_version: u8,
+        contacts: Vec<ContactBuf>,
+    },
+
+    /// Reply to a `Req` with the contacts we know closest to `target`
+    Res {
+        target: KadId,
+        contacts: Vec<ContactBuf>,
+    },
+
+    /// Ask for a `Pong`, used to discover our external udp port
+    Ping,
+
+    /// Reply to a `Ping`
+    Pong {
+        /// udp port the `Ping` was recived from
+        recv_port: u16,
+    },
+
+    /// `KADEMLIA2_HELLO_REQ`, sent to introduce ourselves to a node
+    HelloReq(Details),
+
+    // details packet?
+    // "Contact"
+    /// `KADEMLIA2_HELLO_RES`, `KADEMLIA2_HELLO_RES` uses this form
+    ///
+    /// in the `FindNodeIDByIP` flow, this is sent as a `KAD2_HELLO_REQ`
+    HelloRes(Details),
+
+    /// Announce `entries` (files) under the keyword `target_id`
+    PublishKeyReq {
+        target_id: KadId,
+        entries: Vec<PublishEntryBuf>,
+    },
+
+    /// Announce `contact_id` as a source of the file `target_id`
+    ///
+    /// PublishReqV1 has a similar form with `1: u16` between the 2 ids
+    PublishSourceReq {
+        target_id: KadId,
+        contact_id: KadId,
+        tags: Vec<TagBuf>,
+    },
+
+    /// Announce a note (comment & rating) by `contact_id` about the file `target_id`
+    PublishNotesReq {
+        target_id: KadId,
+        contact_id: KadId,
+        tags: Vec<TagBuf>,
+    },
+
+    /// Reply to any of the publish requests
+    PublishRes {
+        target_id: KadId,
+        load: u8,
+    },
+
+    PublishResAck,
+
+    FindBuddyReqV1 {
+        buddy_id: KadId,
+        src_client_hash: [u8; 16],
+        // our port (for reply)
+        // XXX: unclear why the source port is not used by the client recieving this.
+        src_client_port: u16,
+        /// our connect options, only included if the contact version is >= 6
+        connect_options: Option<u8>,
+    },
+
+    /// Reply to a `FindBuddyReqV1`, offering to be the buddy of the requester
+    FindBuddyResV1 {
+        /// `buddy_id` from the request
+        buddy_id: KadId,
+        src_client_hash: [u8; 16],
+        src_client_port: u16,
+        /// only included if the request was obfuscated
+        connect_options: Option<u8>,
+    },
+
+    /// Ask the buddy of a firewalled node to have it connect to us at `tcp_port`
+    CallbackReqV1 {
+        buddy_id: KadId,
+        file_id: KadId,
+        tcp_port: u16,
+    },
+
+    /// Ask the receiver to try a tcp connection to `tcp_port`
+    FirewalledReqV1 {
+        tcp_port: u16,
+    },
+
+    /// Like `FirewalledReqV1`, also identifying us to the receiver
+    Firewalled2ReqV1 {
+        tcp_port: u16,
+        user_hash: [u8; 16],
+        connect_options: u8,
+    },
+
+    /// Reply to a firewalled request, with the ip it was recieved from
+    FirewalledResV1 {
+        ip_addr: std::net::Ipv4Addr,
+    },
+
+    /// Sent after a successful tcp connection requested by a firewalled request
+    FirewalledAckResV1,
+
+    FirewallUdp {
+        error_code: u8,
+        incoming_port: u16,
+    },
+}
+
+impl OperationBuf {
+    /// Emit wire encoded data into `w`.
+    /// This is done in pieces (not all at once), so be sure to buffer it prior to sending as a udp
+    /// packet.
+    ///
+    /// Note: this never compresses. See `write_packed_to` for compression and
+    /// `write_obfuscated_to` for encryption.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            OperationBuf::BootstrapReq => w.write_all(&[
+                UdpProto::KademliaHeader as u8,
+                KadOpCode::BootstrapReq as u8,
+            ]),
+            OperationBuf::BootstrapReqV0(contact) => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::BootstrapReqV0 as u8,
+                ])?;
+                contact.write_to(w)
+            }
+            OperationBuf::BootstrapResp {
+                client_id,
+                client_port,
+                client_version,
+                contacts,
+            } => {
+                let num_contacts: u16 = contacts.len().try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "too many contacts")
+                })?;
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::BootstrapResp as u8,
+                ])?;
+                w.write_all(&client_id.to_wire())?;
+                w.write_all(&client_port.to_le_bytes())?;
+                w.write_all(&[*client_version])?;
+                w.write_all(&num_contacts.to_le_bytes())?;
+                for contact in contacts {
+                    contact.write_to(w)?;
+                }
+                Ok(())
+            }
+            OperationBuf::Res { target, contacts } => {
+                let num_contacts: u8 = contacts.len().try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "too many contacts")
+                })?;
+                w.write_all(&[UdpProto::KademliaHeader as u8, KadOpCode::Res as u8])?;
+                w.write_all(&target.to_wire())?;
+                w.write_all(&[num_contacts])?;
+                for contact in contacts {
+                    contact.write_to(w)?;
+                }
+                Ok(())
+            }
+            OperationBuf::Ping => {
+                w.write_all(&[UdpProto::KademliaHeader as u8, KadOpCode::Ping as u8])
+            }
+            OperationBuf::Pong { recv_port } => {
+                w.write_all(&[UdpProto::KademliaHeader as u8, KadOpCode::Pong as u8])?;
+                w.write_all(&recv_port.to_le_bytes())
+            }
+            OperationBuf::HelloReq(details) => {
+                w.write_all(&[UdpProto::KademliaHeader as u8, KadOpCode::HelloReq as u8])?;
+                details.write_to(w)
+            }
+            OperationBuf::HelloRes(details) => {
+                w.write_all(&[UdpProto::KademliaHeader as u8, KadOpCode::HelloRes as u8])?;
+                details.write_to(w)
+            }
+            OperationBuf::PublishKeyReq { target_id, entries } => {
+                let entry_ct: u16 = entries
+                    .len()
+                    .try_into()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many entries"))?;
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::PublishKeyReq as u8,
+                ])?;
+                w.write_all(&target_id.to_wire())?;
+                w.write_all(&entry_ct.to_le_bytes())?;
+                for entry in entries {
+                    entry.write_to(w)?;
+                }
+                Ok(())
+            }
+            OperationBuf::PublishSourceReq {
+                target_id,
+                contact_id,
+                tags,
+            } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::PublishSourceReq as u8,
+                ])?;
+                w.write_all(&target_id.to_wire())?;
+                w.write_all(&contact_id.to_wire())?;
+                write_tag_list(w, tags)
+            }
+            OperationBuf::PublishNotesReq {
+                target_id,
+                contact_id,
+                tags,
+            } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::PublishNotesReq as u8,
+                ])?;
+                w.write_all(&target_id.to_wire())?;
+                w.write_all(&contact_id.to_wire())?;
+                write_tag_list(w, tags)
+            }
+            OperationBuf::PublishRes { target_id, load } => {
+                w.write_all(&[UdpProto::KademliaHeader as u8, KadOpCode::PublishRes as u8])?;
+                w.write_all(&target_id.to_wire())?;
+                w.write_all(&[*load])
+            }
+            OperationBuf::PublishResAck => w.write_all(&[
+                UdpProto::KademliaHeader as u8,
+                KadOpCode::PublishResAck as u8,
+            ]),
+            OperationBuf::FindBuddyReqV1 {
+                buddy_id,
+                src_client_hash,
+                src_client_port,
+                connect_options,
+            } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::FindBuddyReqV1 as u8,
+                ])?;
+                w.write_all(&buddy_id.to_wire())?;
+                w.write_all(src_client_hash)?;
+                w.write_all(&src_client_port.to_le_bytes())?;
+                if let Some(connect_options) = connect_options {
+                    w.write_all(&[*connect_options])?;
+                }
+                Ok(())
+            }
+            OperationBuf::FindBuddyResV1 {
+                buddy_id,
+                src_client_hash,
+                src_client_port,
+                connect_options,
+            } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::FindBuddyResV1 as u8,
+                ])?;
+                w.write_all(&buddy_id.to_wire())?;
+                w.write_all(src_client_hash)?;
+                w.write_all(&src_client_port.to_le_bytes())?;
+                if let Some(connect_options) = connect_options {
+                    w.write_all(&[*connect_options])?;
+                }
+                Ok(())
+            }
+            OperationBuf::CallbackReqV1 {
+                buddy_id,
+                file_id,
+                tcp_port,
+            } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::CallbackReqV1 as u8,
+                ])?;
+                w.write_all(&buddy_id.to_wire())?;
+                w.write_all(&file_id.to_wire())?;
+                w.write_all(&tcp_port.to_le_bytes())
+            }
+            OperationBuf::FirewalledReqV1 { tcp_port } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::FirewalledReqV1 as u8,
+                ])?;
+                w.write_all(&tcp_port.to_le_bytes())
+            }
+            OperationBuf::Firewalled2ReqV1 {
+                tcp_port,
+                user_hash,
+                connect_options,
+            } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::Firewalled2ReqV1 as u8,
+                ])?;
+                w.write_all(&tcp_port.to_le_bytes())?;
+                w.write_all(user_hash)?;
+                w.write_all(&[*connect_options])
+            }
+            OperationBuf::FirewalledResV1 { ip_addr } => {
+                w.write_all(&[
+                    UdpProto::KademliaHeader as u8,
+                    KadOpCode::FirewalledResV1 as u8,
+                ])?;
+                w.write_all(&u32::from(*ip_addr).to_le_bytes())
+            }
+            OperationBuf::FirewalledAckResV1 => w.write_all(&[
+                UdpProto::KademliaHeader as u8,
+                KadOpCode::FirewalledAckResV1 as u8,
+            ]),
+            OperationBuf::FirewallUdp {
+                error_code,
+                incoming_port,
+            } => {
+                w.write_all(&[UdpProto::KademliaHeader as u8, KadOpCode::FirewallUdp as u8])?;
+                w.write_all(&[*error_code])?;
+                w.write_all(&incoming_port.to_le_bytes())
+            }
+        }
+    }
+
+    /// Emit the wire encoding into `w`, compressed (as `UdpProto::KademliaPacked`) if emule
+    /// would compress it. See `pack_kad_packet`.
+    pub fn write_packed_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut plain = Vec::new();
+        self.write_to(&mut plain)?;
+        w.write_all(&pack_kad_packet(&plain)?)
+    }
+
+    /// Emit the obfuscated (encrypted) wire encoding into `w`. Like emule, the operation is
+    /// compressed (if that helps) prior to encryption.
+    ///
+    /// The verify keys in `keys` are included in the packet so the target can obfuscate its
+    /// replies. See `SendKeys::write_to` for `max_padding`.
+    pub fn write_obfuscated_to<W: io::Write, R: rand::Rng + ?Sized>(
+        &self,
+        w: &mut W,
+        keys: &SendKeys,
+        max_padding: u8,
+        rng: &mut R,
+    ) -> io::Result<()> {
+        let mut packed = Vec::new();
+        self.write_packed_to(&mut packed)?;
+        keys.write_to(w, &packed, max_padding, rng)
+    }
+}
+
+/// Owned `UdpProto::Emule` operations, for sending
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmuleOperationBuf {
+    ReAskFilePing {
+        file_hash: [u8; 16],
+        /// only for targets with udp version > 3. An empty list indicates the complete file.
+        part_status: Option<Vec<bool>>,
+        /// only for targets with udp version > 2
+        complete_sources: Option<u16>,
+    },
+    ReAskAck {
+        /// only for targets with udp version > 3. An empty list indicates the complete file.
+        part_status: Option<Vec<bool>>,
+        queue_rank: u16,
+    },
+    FileNotFound,
+    QueueFull,
+    ReAskCallBackUdp {
+        buddy_id: KadId,
+        file_hash: [u8; 16],
+        part_status: Option<Vec<bool>>,
+        complete_sources: Option<u16>,
+    },
+    DirectCallbackReq {
+        tcp_port: u16,
+        user_hash: [u8; 16],
+        connect_options: u8,
+    },
+    PortTest {
+        value: u8,
+    },
+}
+
+impl EmuleOperationBuf {
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let opcode = match self {
+            EmuleOperationBuf::ReAskFilePing { .. } => EmuleOpCode::ReAskFilePing,
+            EmuleOperationBuf::ReAskAck { .. } => EmuleOpCode::ReAskAck,
+            EmuleOperationBuf::FileNotFound => EmuleOpCode::FileNotFound,
+            EmuleOperationBuf::QueueFull => EmuleOpCode::QueueFull,
+            EmuleOperationBuf::ReAskCallBackUdp { .. } => EmuleOpCode::ReAskCallBackUdp,
+            EmuleOperationBuf::DirectCallbackReq { .. } => EmuleOpCode::DirectCallbackReq,
+            EmuleOperationBuf::PortTest { .. } => EmuleOpCode::PortTest,
+        };
+        w.write_all(&[UdpProto::Emule as u8, opcode as u8])?;
+
+        match self {
+            EmuleOperationBuf::ReAskFilePing {
+                file_hash,
+                part_status,
+                complete_sources,
+            } => {
+                w.write_all(file_hash)?;
+                write_reask_extended(w, part_status.as_deref(), *complete_sources)
+            }
+            EmuleOperationBuf::ReAskAck {
+                part_status,
+                queue_rank,
+            } => {
+                if let Some(part_status) = part_status {
+                    write_part_status(w, part_status)?;
+                }
+                w.write_all(&queue_rank.to_le_bytes())
+            }
+            EmuleOperationBuf::FileNotFound | EmuleOperationBuf::QueueFull => Ok(()),
+            EmuleOperationBuf::ReAskCallBackUdp {
+                buddy_id,
+                file_hash,
+                part_status,
+                complete_sources,
+            } => {
+                w.write_all(&buddy_id.to_wire())?;
+                w.write_all(file_hash)?;
+                write_reask_extended(w, part_status.as_deref(), *complete_sources)
+            }
+            EmuleOperationBuf::DirectCallbackReq {
+                tcp_port,
+                user_hash,
+                connect_options,
+            } => {
+                w.write_all(&tcp_port.to_le_bytes())?;
+                w.write_all(user_hash)?;
+                w.write_all(&[*connect_options])
+            }
+            EmuleOperationBuf::PortTest { value } => w.write_all(&[*value]),
+        }
     }
+}
 
-    pub fn name(&self) -> &'a [u8] {
-        &self.raw[3..(3 + self.name_len())]
+fn write_reask_extended<W: io::Write>(
+    w: &mut W,
+    part_status: Option<&[bool]>,
+    complete_sources: Option<u16>,
+) -> io::Result<()> {
+    if let Some(part_status) = part_status {
+        write_part_status(w, part_status)?;
     }
-
-    pub fn tag_type(&self) -> TagType {
-        TagType::from_u8(self.raw[0]).unwrap()
+    if let Some(complete_sources) = complete_sources {
+        w.write_all(&complete_sources.to_le_bytes())?;
     }
+    Ok(())
+}
 
-    pub fn value_bytes(&self) -> &'a [u8] {
-        &self.raw[(3 + self.name_len())..]
+/// See `PartStatus`
+fn write_part_status<W: io::Write>(w: &mut W, parts: &[bool]) -> io::Result<()> {
+    let part_count: u16 = parts
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many parts"))?;
+    w.write_all(&part_count.to_le_bytes())?;
+    for chunk in parts.chunks(8) {
+        let b = chunk
+            .iter()
+            .enumerate()
+            .fold(0u8, |b, (i, &has)| b | (has as u8) << i);
+        w.write_all(&[b])?;
     }
+    Ok(())
+}
 
-    pub fn value(&self) -> TagValue<'a> {
-        match self.tag_type() {
-            TagType::Hash => TagValue::Hash(self.value_bytes()),
-            TagType::String_ => {
-                let s_len =
-                    u16::from_le_bytes(self.value_bytes()[..2].try_into().unwrap()) as usize;
-                TagValue::String_(&self.value_bytes()[2..(2 + s_len)])
+/// Owned `UdpProto::Edonkey` operations, for sending
+#[derive(Debug, PartialEq)]
+pub enum ServerOperationBuf {
+    /// `expr` is an encoded `SearchExpr`, see `SearchExpr::write_to()`
+    GlobSearchReq {
+        expr: Vec<u8>,
+    },
+    GlobSearchReq2 {
+        expr: Vec<u8>,
+    },
+    /// Written as one datagram containing each result
+    GlobSearchRes {
+        results: Vec<ServerSearchResultBuf>,
+    },
+    GlobGetSources {
+        file_hashes: Vec<[u8; 16]>,
+    },
+    /// `(file_hash, file_size)`
+    GlobGetSources2 {
+        files: Vec<([u8; 16], u64)>,
+    },
+    /// Written as one datagram containing each entry
+    GlobFoundSources {
+        entries: Vec<FoundSourcesBuf>,
+    },
+    GlobServStatReq {
+        challenge: u32,
+    },
+    GlobServStatRes(ServStatResBuf),
+    ServerDescReq {
+        challenge: Option<u32>,
+    },
+    ServerDescRes {
+        challenge: u32,
+        tags: TagListBuf,
+    },
+    /// The layout used by servers that ignore the `ServerDescReq` challenge
+    ServerDescResV0 {
+        name: Vec<u8>,
+        description: Vec<u8>,
+    },
+}
+
+impl ServerOperationBuf {
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let opcode = match self {
+            ServerOperationBuf::GlobSearchReq { .. } => ServerOpCode::GlobSearchReq,
+            ServerOperationBuf::GlobSearchReq2 { .. } => ServerOpCode::GlobSearchReq2,
+            ServerOperationBuf::GlobSearchRes { .. } => ServerOpCode::GlobSearchRes,
+            ServerOperationBuf::GlobGetSources { .. } => ServerOpCode::GlobGetSources,
+            ServerOperationBuf::GlobGetSources2 { .. } => ServerOpCode::GlobGetSources2,
+            ServerOperationBuf::GlobFoundSources { .. } => ServerOpCode::GlobFoundSources,
+            ServerOperationBuf::GlobServStatReq { .. } => ServerOpCode::GlobServStatReq,
+            ServerOperationBuf::GlobServStatRes(..) => ServerOpCode::GlobServStatRes,
+            ServerOperationBuf::ServerDescReq { .. } => ServerOpCode::ServerDescReq,
+            ServerOperationBuf::ServerDescRes { .. }
+            | ServerOperationBuf::ServerDescResV0 { .. } => ServerOpCode::ServerDescRes,
+        };
+        let header = [UdpProto::Edonkey as u8, opcode as u8];
+        w.write_all(&header)?;
+
+        match self {
+            ServerOperationBuf::GlobSearchReq { expr }
+            | ServerOperationBuf::GlobSearchReq2 { expr } => w.write_all(expr),
+            ServerOperationBuf::GlobSearchRes { results } => {
+                for (i, result) in results.iter().enumerate() {
+                    if i != 0 {
+                        w.write_all(&header)?;
+                    }
+                    result.write_to(w)?;
+                }
+                Ok(())
+            }
+            ServerOperationBuf::GlobGetSources { file_hashes } => {
+                for file_hash in file_hashes {
+                    w.write_all(file_hash)?;
+                }
+                Ok(())
             }
-            TagType::Uint64 => {
-                TagValue::Uint64(u64::from_le_bytes(self.value_bytes().try_into().unwrap()))
+            ServerOperationBuf::GlobGetSources2 { files } => {
+                for (file_hash, file_size) in files {
+                    w.write_all(file_hash)?;
+                    match u32::try_from(*file_size) {
+                        Ok(s) if s != 0 => w.write_all(&s.to_le_bytes())?,
+                        _ => {
+                            w.write_all(&0u32.to_le_bytes())?;
+                            w.write_all(&file_size.to_le_bytes())?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ServerOperationBuf::GlobFoundSources { entries } => {
+                for (i, entry) in entries.iter().enumerate() {
+                    if i != 0 {
+                        w.write_all(&header)?;
+                    }
+                    entry.write_to(w)?;
+                }
+                Ok(())
             }
-            TagType::Uint32 => {
-                TagValue::Uint32(u32::from_le_bytes(self.value_bytes().try_into().unwrap()))
+            ServerOperationBuf::GlobServStatReq { challenge } => {
+                w.write_all(&challenge.to_le_bytes())
             }
-            TagType::Uint16 => {
-                TagValue::Uint16(u16::from_le_bytes(self.value_bytes().try_into().unwrap()))
+            ServerOperationBuf::GlobServStatRes(res) => res.write_to(w),
+            ServerOperationBuf::ServerDescReq { challenge } => match challenge {
+                Some(challenge) => w.write_all(&challenge.to_le_bytes()),
+                None => Ok(()),
+            },
+            ServerOperationBuf::ServerDescRes { challenge, tags } => {
+                w.write_all(&challenge.to_le_bytes())?;
+                tags.write32_to(w)
             }
-            TagType::Uint8 => TagValue::Uint8(self.value_bytes()[0]),
-            TagType::Float32 => {
-                TagValue::Float32(f32::from_le_bytes(self.value_bytes().try_into().unwrap()))
+            ServerOperationBuf::ServerDescResV0 { name, description } => {
+                write_str16(w, name)?;
+                write_str16(w, description)
             }
-            _ => panic!("unhandled tag_type, sync `Tag::value` and `Tag::from_slice`"),
         }
     }
 }
 
-impl<'a> fmt::Debug for Tag<'a> {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("Tag")
-            .field("name", &self.name())
-            .field("tag_type", &self.tag_type())
-            .field("value", &self.value())
-            .finish()
-    }
+/// See `ServerSearchResult`
+#[derive(Debug, PartialEq)]
+pub struct ServerSearchResultBuf {
+    pub file_hash: [u8; 16],
+    pub client_id: u32,
+    pub client_port: u16,
+    pub tags: TagListBuf,
 }
 
-#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
-pub enum TagValue<'a> {
-    Hash(&'a [u8]),
-    String_(&'a [u8]),
-    Uint64(u64),
-    Uint32(u32),
-    Uint16(u16),
-    Uint8(u8),
-    Float32(f32),
-    Bsob(&'a [u8]),
+impl ServerSearchResultBuf {
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.file_hash)?;
+        w.write_all(&self.client_id.to_le_bytes())?;
+        w.write_all(&self.client_port.to_le_bytes())?;
+        self.tags.write32_to(w)
+    }
 }
 
-#[derive(Clone)]
-pub struct BootstrapRespContacts<'a> {
-    raw: &'a [u8],
+/// See `FoundSourcesEntry`
+#[derive(Debug, PartialEq, Eq)]
+pub struct FoundSourcesBuf {
+    pub file_hash: [u8; 16],
+    /// `(client_id, tcp_port)`
+    pub sources: Vec<(u32, u16)>,
 }
 
-impl<'a> BootstrapRespContacts<'a> {
-    pub fn from_slice(num: u16, raw: &'a [u8]) -> Result<Self, Error> {
-        // We don't use `num` except for validation.
-        let each_size = 16 + 4 + 2 + 2 + 1;
-        let need_size = each_size * num as usize;
-        if raw.len() != need_size {
-            return Err(Error::BootstrapRespContactsSizeMismatch {
-                need: need_size,
-                have: raw.len(),
-            });
+impl FoundSourcesBuf {
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let count: u8 = self
+            .sources
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many sources"))?;
+        w.write_all(&self.file_hash)?;
+        w.write_all(&[count])?;
+        for (client_id, tcp_port) in &self.sources {
+            w.write_all(&client_id.to_le_bytes())?;
+            w.write_all(&tcp_port.to_le_bytes())?;
         }
-
-        Ok(BootstrapRespContacts { raw })
+        Ok(())
     }
 }
 
-impl<'a> fmt::Debug for BootstrapRespContacts<'a> {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_list().entries(self.clone()).finish()
-    }
+/// See `ServStatRes`. Always written with every optional field.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct ServStatResBuf {
+    pub challenge: u32,
+    pub users: u32,
+    pub files: u32,
+    pub max_users: u32,
+    pub soft_files: u32,
+    pub hard_files: u32,
+    pub udp_flags: u32,
+    pub low_id_users: u32,
+    pub udp_obfuscation_port: u16,
+    pub tcp_obfuscation_port: u16,
+    pub udp_key: u32,
 }
 
-impl<'a> Iterator for BootstrapRespContacts<'a> {
-    type Item = BootstrapRespContact<'a>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.raw.len() > 0 {
-            let (r, rem) = BootstrapRespContact::from_slice(self.raw);
-            self.raw = rem;
-            Some(r)
-        } else {
-            None
+impl ServStatResBuf {
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        for v in [
+            self.challenge,
+            self.users,
+            self.files,
+            self.max_users,
+            self.soft_files,
+            self.hard_files,
+            self.udp_flags,
+            self.low_id_users,
+        ] {
+            w.write_all(&v.to_le_bytes())?;
         }
+        w.write_all(&self.udp_obfuscation_port.to_le_bytes())?;
+        w.write_all(&self.tcp_obfuscation_port.to_le_bytes())?;
+        w.write_all(&self.udp_key.to_le_bytes())
     }
 }
 
-pub struct BootstrapRespContact<'a> {
-    raw: &'a [u8],
+/// write a `le16` length prefixed byte string, the inverse of `split_str16`
+fn write_str16<W: io::Write>(w: &mut W, s: &[u8]) -> io::Result<()> {
+    let len: u16 = s
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "string too long"))?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(s)
 }
 
-impl<'a> BootstrapRespContact<'a> {
-    pub fn from_slice(raw: &'a [u8]) -> (Self, &'a [u8]) {
-        let n = 16 + 4 + 2 + 2 + 1;
-        if raw.len() < n {
-            panic!("bad brc len: have: {}, need: {}", raw.len(), n);
-        }
-
-        let (raw, rem) = raw.split_at(n);
+/// emule compresses kad packets with payloads (excluding the `UdpProto` and opcode bytes) larger
+/// than this many bytes
+pub const KAD_PACK_THRESHOLD: usize = 200;
 
-        (BootstrapRespContact { raw }, rem)
+/// Compress a complete `UdpProto::KademliaHeader` packet into a `UdpProto::KademliaPacked` one.
+///
+/// Like emule, only payloads larger than `KAD_PACK_THRESHOLD` are compressed, the opcode is left
+/// uncompressed, and if compression doesn't shrink the payload the packet is returned unchanged.
+pub fn pack_kad_packet(packet: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    if packet.len() < 2
+        || packet[0] != UdpProto::KademliaHeader as u8
+        || packet.len() - 2 <= KAD_PACK_THRESHOLD
+    {
+        return Ok(packet.into());
     }
 
-    pub fn client_id(&self) -> u128 {
-        u128::from_le_bytes(self.raw[..16].try_into().unwrap())
-    }
+    let payload = &packet[2..];
+    let mut out = Vec::with_capacity(packet.len());
+    out.push(UdpProto::KademliaPacked as u8);
+    out.push(packet[1]);
+    let mut z = flate2::write::ZlibEncoder::new(out, flate2::Compression::best());
+    z.write_all(payload)?;
+    let out = z.finish()?;
 
-    pub fn raw_ip_addr(&self) -> u32 {
-        u32::from_le_bytes(self.raw[16..(16 + 4)].try_into().unwrap())
+    if out.len() - 2 >= payload.len() {
+        return Ok(packet.into());
     }
 
-    pub fn ip_addr(&self) -> std::net::Ipv4Addr {
-        std::net::Ipv4Addr::from(self.raw_ip_addr())
-    }
+    event!(Level::DEBUG, "PACKING {} -> {}", packet.len(), out.len());
 
-    pub fn udp_port(&self) -> u16 {
-        u16::from_le_bytes(self.raw[(16 + 4)..(16 + 4 + 2)].try_into().unwrap())
-    }
+    Ok(out.into())
+}
 
-    pub fn tcp_port(&self) -> u16 {
-        u16::from_le_bytes(self.raw[(16 + 4 + 2)..(16 + 4 + 2 + 2)].try_into().unwrap())
-    }
+/// A contact in `BootstrapResp` and `Res`, see `BootstrapRespContact` and `ResContact`
+///
+/// In kad v0, `version` is instead the contact type (0 for ourselves).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContactBuf {
+    pub client_id: KadId,
+    pub ip_addr: std::net::Ipv4Addr,
+    pub udp_port: u16,
+    pub tcp_port: u16,
+    pub version: u8,
+}
 
-    pub fn version(&self) -> u8 {
-        self.raw[16 + 4 + 2 + 2]
+impl ContactBuf {
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.client_id.to_wire())?;
+        w.write_all(&u32::from(self.ip_addr).to_le_bytes())?;
+        w.write_all(&self.udp_port.to_le_bytes())?;
+        w.write_all(&self.tcp_port.to_le_bytes())?;
+        w.write_all(&[self.version])
     }
 }
 
-impl<'a> fmt::Debug for BootstrapRespContact<'a> {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("BootstrapRespContact")
-            .field("client_id", &self.client_id())
-            .field("ip_addr", &self.ip_addr())
-            .field("udp_port", &self.udp_port())
-            .field("tcp_port", &self.tcp_port())
-            .field("version", &self.version())
-            .finish()
+/// A file in `OperationBuf::PublishKeyReq`, see `PublishKeyEntry`
+#[derive(Debug, PartialEq)]
+pub struct PublishEntryBuf {
+    pub file_id: KadId,
+    pub tags: Vec<TagBuf>,
+}
+
+impl PublishEntryBuf {
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.file_id.to_wire())?;
+        write_tag_list(w, &self.tags)
     }
 }
 
-/// Owned, non-parsing version of `Operation`
-pub enum OperationBuf {
-    BootstrapReq,
+/// emule's well known tag names (`FT_*` & kad's `TAG_*`), which are sent as a single byte
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Primitive)]
+#[repr(u8)]
+pub enum TagId {
+    /// string
+    FileName = 0x01,
+    /// uint, low 32 bits if `FileSizeHi` is present. kad may send an 8 byte bsob.
+    FileSize = 0x02,
+    /// string, for example "Audio" or "Pro"
+    FileType = 0x03,
+    /// string, the file extension
+    FileFormat = 0x04,
+    LastSeenComplete = 0x05,
+    Transferred = 0x08,
+    GapStart = 0x09,
+    GapEnd = 0x0A,
+    PartFileName = 0x12,
+    OldDlPriority = 0x13,
+    Status = 0x14,
+    /// uint, number of sources known for a file
+    Sources = 0x15,
+    Permissions = 0x16,
+    OldUlPriority = 0x17,
+    DlPriority = 0x18,
+    UlPriority = 0x19,
+    Compression = 0x1A,
+    Corrupted = 0x1B,
+    KadLastPublishKey = 0x20,
+    KadLastPublishSrc = 0x21,
+    Flags = 0x22,
+    DlActiveTime = 0x23,
+    CorruptedParts = 0x24,
+    DlPreview = 0x25,
+    KadLastPublishNotes = 0x26,
+    AichHash = 0x27,
+    FileHash = 0x28,
+    /// uint, number of sources with the complete file
+    CompleteSources = 0x30,
+    CollectionAuthor = 0x31,
+    CollectionAuthorKey = 0x32,
+    /// uint, sent in keyword publishes
+    PublishInfo = 0x33,
+    LastShared = 0x34,
+    AichHashSet = 0x35,
+    KadAichHashPub = 0x36,
+    /// bsob of `count: u8, [popularity: u8, hash: [u8;20]; count]`
+    KadAichHashResult = 0x37,
+    /// uint, high 32 bits of the file size, sent by old clients
+    FileSizeHi = 0x3A,
+    AtTransferred = 0x50,
+    AtRequested = 0x51,
+    AtAccepted = 0x52,
+    Category = 0x53,
+    AtTransferredHi = 0x54,
+    MaxSources = 0x55,
+    /// string, also sent by old clients as "Artist"
+    MediaArtist = 0xD0,
+    /// string, also sent by old clients as "Album"
+    MediaAlbum = 0xD1,
+    /// string, also sent by old clients as "Title"
+    MediaTitle = 0xD2,
+    /// uint seconds, also sent by old clients as "length"
+    MediaLength = 0xD3,
+    /// uint kbit/s, also sent by old clients as "bitrate"
+    MediaBitrate = 0xD4,
+    /// string, also sent by old clients as "codec"
+    MediaCodec = 0xD5,
+    /// uint, bitfield of firewall state & ack request sent in hellos
+    KadMiscOptions = 0xF2,
+    /// uint, obfuscation capabilities of a source
+    Encryption = 0xF3,
+    UserCount = 0xF4,
+    FileCount = 0xF5,
+    /// string
+    FileComment = 0xF6,
+    /// uint, 0 to 5
+    FileRating = 0xF7,
+    /// string, for firewalled sources the hex encoded kad id of their buddy
+    BuddyHash = 0xF8,
+    ClientLowId = 0xF9,
+    /// uint, for firewalled sources the udp port of their buddy
+    ServerPort = 0xFA,
+    /// uint, for firewalled sources the ip of their buddy
+    ServerIp = 0xFB,
+    /// uint, the (internal) udp port of a source or node
+    SourceUdpPort = 0xFC,
+    /// uint, tcp port of a source
+    SourcePort = 0xFD,
+    /// uint, public ip of a source
+    SourceIp = 0xFE,
+    /// uint, how to reach a source, see `SearchResult::source_type()`
+    SourceType = 0xFF,
+}
 
-    Pong {
-        /// udp port the `Ping` was recived from
-        recv_port: u16,
-    },
+/// Every single byte name, so `TagId::name()` can return a static slice
+static TAG_ID_NAMES: [u8; 256] = {
+    let mut names = [0u8; 256];
+    let mut i = 0;
+    while i < names.len() {
+        names[i] = i as u8;
+        i += 1;
+    }
+    names
+};
+
+/// The string names old clients use for media tags
+const TAG_ID_ED2K_NAMES: &[(TagId, &[u8])] = &[
+    (TagId::MediaArtist, b"Artist"),
+    (TagId::MediaAlbum, b"Album"),
+    (TagId::MediaTitle, b"Title"),
+    (TagId::MediaLength, b"length"),
+    (TagId::MediaBitrate, b"bitrate"),
+    (TagId::MediaCodec, b"codec"),
+];
+
+impl TagId {
+    /// Recognize the single byte name, or one of the old string names
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            [id] => Self::from_u8(*id),
+            _ => TAG_ID_ED2K_NAMES
+                .iter()
+                .find(|(_, n)| *n == name)
+                .map(|(id, _)| *id),
+        }
+    }
 
-    // details packet?
-    // "Contact"
-    /// `KADEMLIA2_HELLO_RES`, `KADEMLIA2_HELLO_RES` uses this form
-    ///
-    /// in the `FindNodeIDByIP` flow, this is sent as a `KAD2_HELLO_REQ`
-    HelloRes(Details),
+    /// The single byte name
+    pub fn name(self) -> &'static [u8] {
+        let i = self as usize;
+        &TAG_ID_NAMES[i..(i + 1)]
+    }
 
-    /// PublishReqV1 has a similar form with `1: u16` between the 2 ids
-    PublishSourceReq {
-        target_id: u128,
-        contact_id: u128,
-        /* tags: Vec<Tag> */
-    },
-    FindBuddyReqV1 {
-        buddy_id: u128,
-        src_client_hash: u128,
-        // our port (for reply)
-        // XXX: unclear why the source port is not used by the client recieving this.
-        src_client_port: u16,
-        // if contact version > 6, then include the client's udp key and id
-    },
+    /// The string name used by old clients, if there is one
+    pub fn ed2k_name(self) -> Option<&'static [u8]> {
+        TAG_ID_ED2K_NAMES
+            .iter()
+            .find(|(id, _)| *id == self)
+            .map(|(_, n)| *n)
+    }
 }
 
-impl OperationBuf {
-    /// Emit wire encoded data into `w`.
-    /// This is done in pieces (not all at once), so be sure to buffer it prior to sending as a udp
-    /// packet.
-    ///
-    /// Note: we don't perform encryption or compression for any operation right now.
-    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
-        match self {
-            OperationBuf::BootstrapReq => w.write_all(&[
-                UdpProto::KademliaHeader as u8,
-                KadOpCode::BootstrapReq as u8,
-            ]),
-            _ => todo!(),
-        }
+impl From<TagId> for u8 {
+    fn from(id: TagId) -> u8 {
+        id as u8
     }
 }
 
+/// Content of `HelloReq` and `HelloRes`
+///
+/// ```norust
+/// struct Details {
+///     src_kad_id: le128,
+///     src_port: le16,
+///     kad_version: u8,
+///     // note: the count is a single byte
+///     tag_ct: u8,
+///     tags: [Tag; tag_ct],
+/// }
+/// ```
 pub struct Details {
-    pub src_kad_id: u128,
+    pub src_kad_id: KadId,
+    /// tcp port
     pub src_port: u16,
     pub kad_version: u8,
 
@@ -1110,6 +5912,9 @@ pub struct Details {
     //  - ack package requested,
     //  - prefs indicate wirewalled
     //  - firewall test indicates udp firewalled
+    //
+    // emule only sends this to contacts with kad version >= 8. We include it if any of these are
+    // `Some`.
     /// default: false
     pub udp_firewalled: Option<bool>,
     /// default: false
@@ -1119,18 +5924,324 @@ pub struct Details {
     pub req_ack: Option<bool>,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+impl Details {
+    /// `TAG_KADMISCOPTIONS` value, if any of the options are set
+    ///
+    /// ```norust
+    /// 5 bits: reserved
+    /// 1 bit: requesting HELLO_RES_ACK
+    /// 1 bit: tcp firewalled
+    /// 1 bit: udp firewalled
+    /// ```
+    pub fn misc_options(&self) -> Option<u8> {
+        if self.udp_firewalled.is_none() && self.tcp_firewalled.is_none() && self.req_ack.is_none()
+        {
+            return None;
+        }
+
+        Some(
+            (self.req_ack.unwrap_or(false) as u8) << 2
+                | (self.tcp_firewalled.unwrap_or(false) as u8) << 1
+                | (self.udp_firewalled.unwrap_or(false) as u8),
+        )
+    }
+
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.src_kad_id.to_wire())?;
+        w.write_all(&self.src_port.to_le_bytes())?;
+        w.write_all(&[self.kad_version])?;
+
+        let mut tags = Vec::new();
+        if let Some(src_port_internal) = self.src_port_internal {
+            tags.push(TagBuf::with_id(
+                TagId::SourceUdpPort,
+                TagValueBuf::Uint16(src_port_internal),
+            ));
+        }
+        if let Some(misc_options) = self.misc_options() {
+            tags.push(TagBuf::with_id(
+                TagId::KadMiscOptions,
+                TagValueBuf::Uint8(misc_options),
+            ));
+        }
+
+        write_tag_list(w, &tags)
+    }
+}
+
+/// Kad tag lists are prefixed with a single byte count
+fn write_tag_list<W: io::Write>(w: &mut W, tags: &[TagBuf]) -> io::Result<()> {
+    let count: u8 = tags
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many tags"))?;
+    w.write_all(&[count])?;
+    for tag in tags {
+        tag.write_to(w)?;
+    }
+    Ok(())
+}
+
+/// How a `TagBuf` is laid out on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TagEncoding {
+    /// Every name has a `name_len` & every string a length prefix. Kad only accepts this.
+    #[default]
+    Standard,
+    /// ed2k "new tags": 1 byte names use `TAG_COMPACT_NAME` & strings of 1 to 16 bytes use
+    /// `TagType::Str1..=TagType::Str16`. Accepted by clients & by servers with
+    /// `SRV_UDPFLG_NEWTAGS`.
+    Compact,
+}
+
+impl TagEncoding {
+    /// The encoding emule would have used to write `tags`
+    fn of<'a>(mut tags: impl Iterator<Item = Tag<'a>>) -> Self {
+        if tags.any(|t| t.has_compact_name() || t.tag_type().compact_str_len().is_some()) {
+            TagEncoding::Compact
+        } else {
+            TagEncoding::Standard
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct TagBuf {
     pub name: Vec<u8>,
     pub value: TagValueBuf,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+impl TagBuf {
+    pub fn with_id(id: TagId, value: TagValueBuf) -> Self {
+        TagBuf {
+            name: id.name().to_vec(),
+            value,
+        }
+    }
+
+    /// Write with `TagEncoding::Standard`
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.write_encoded_to(w, TagEncoding::Standard)
+    }
+
+    pub fn write_encoded_to<W: io::Write>(
+        &self,
+        w: &mut W,
+        encoding: TagEncoding,
+    ) -> io::Result<()> {
+        let compact = encoding == TagEncoding::Compact;
+        let compact_str = match &self.value {
+            TagValueBuf::String(s) if compact && (1..=16).contains(&s.len()) => Some(s),
+            _ => None,
+        };
+        let tag_type = match compact_str {
+            Some(s) => 0x10 + s.len() as u8,
+            None => self.value.tag_type() as u8,
+        };
+
+        if compact && self.name.len() == 1 {
+            w.write_all(&[tag_type | TAG_COMPACT_NAME, self.name[0]])?;
+        } else {
+            let name_len: u16 =
+                self.name.len().try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "tag name too long")
+                })?;
+            w.write_all(&[tag_type])?;
+            w.write_all(&name_len.to_le_bytes())?;
+            w.write_all(&self.name)?;
+        }
+
+        match compact_str {
+            Some(s) => w.write_all(s),
+            None => self.value.write_to(w),
+        }
+    }
+}
+
+impl<'a> From<&Tag<'a>> for TagBuf {
+    fn from(tag: &Tag<'a>) -> Self {
+        TagBuf {
+            name: tag.name().to_vec(),
+            value: tag.value().into(),
+        }
+    }
+}
+
+/// Owned version of `TagList` & `TagList32`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TagListBuf {
+    pub encoding: TagEncoding,
+    pub tags: Vec<TagBuf>,
+}
+
+impl TagListBuf {
+    /// With a `u8` count, like `TagList`
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let count: u8 = self
+            .tags
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many tags"))?;
+        w.write_all(&[count])?;
+        self.write_tags_to(w)
+    }
+
+    /// With a `le32` count, like `TagList32`
+    pub fn write32_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let count: u32 = self
+            .tags
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many tags"))?;
+        w.write_all(&count.to_le_bytes())?;
+        self.write_tags_to(w)
+    }
+
+    fn write_tags_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        for tag in &self.tags {
+            tag.write_encoded_to(w, self.encoding)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists written by emule convert back to identical bytes. Lists mixing compact & standard tags
+/// are written with `TagEncoding::Compact`.
+impl<'a> From<&TagList<'a>> for TagListBuf {
+    fn from(tags: &TagList<'a>) -> Self {
+        // NOTE: validated in `TagList::from_slice()`
+        tags.iter().into()
+    }
+}
+
+/// See `From<&TagList>`
+impl<'a> From<&TagList32<'a>> for TagListBuf {
+    fn from(tags: &TagList32<'a>) -> Self {
+        // NOTE: validated in `TagList32::from_slice()`
+        tags.iter().into()
+    }
+}
+
+/// Invalid tags are skipped
+impl<'a> From<TagListIter<'a>> for TagListBuf {
+    fn from(tags: TagListIter<'a>) -> Self {
+        let tags = tags.filter_map(Result::ok);
+        TagListBuf {
+            encoding: TagEncoding::of(tags.clone()),
+            tags: tags.map(|t| TagBuf::from(&t)).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TagValueBuf {
     Uint8(u8),
     Uint16(u16),
     Uint32(u32),
     Uint64(u64),
+    /// Written as `TagType::Str1..=TagType::Str16` with `TagEncoding::Compact` when it fits
+    String(Vec<u8>),
+    Hash([u8; 16]),
+    Float32(f32),
+    Bool(bool),
+    /// `len` bools, lsb first. `bits` should have `len / 8 + 1` bytes.
+    BoolArray {
+        len: u16,
+        bits: Vec<u8>,
+    },
+    Blob(Vec<u8>),
+    /// At most 255 bytes
+    Bsob(Vec<u8>),
+}
+
+impl TagValueBuf {
+    /// Use the smallest integer type that can hold `v`, like emule's `CKadTagUInt`
+    pub fn uint(v: u64) -> Self {
+        if let Ok(v) = u8::try_from(v) {
+            TagValueBuf::Uint8(v)
+        } else if let Ok(v) = u16::try_from(v) {
+            TagValueBuf::Uint16(v)
+        } else if let Ok(v) = u32::try_from(v) {
+            TagValueBuf::Uint32(v)
+        } else {
+            TagValueBuf::Uint64(v)
+        }
+    }
+
+    /// The type with `TagEncoding::Standard`
+    pub fn tag_type(&self) -> TagType {
+        match self {
+            TagValueBuf::Uint8(_) => TagType::Uint8,
+            TagValueBuf::Uint16(_) => TagType::Uint16,
+            TagValueBuf::Uint32(_) => TagType::Uint32,
+            TagValueBuf::Uint64(_) => TagType::Uint64,
+            TagValueBuf::String(_) => TagType::String_,
+            TagValueBuf::Hash(_) => TagType::Hash,
+            TagValueBuf::Float32(_) => TagType::Float32,
+            TagValueBuf::Bool(_) => TagType::Bool,
+            TagValueBuf::BoolArray { .. } => TagType::BoolArray,
+            TagValueBuf::Blob(_) => TagType::Blob,
+            TagValueBuf::Bsob(_) => TagType::Bsob,
+        }
+    }
+
+    /// Write with `TagEncoding::Standard`
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            TagValueBuf::Uint8(v) => w.write_all(&[*v]),
+            TagValueBuf::Uint16(v) => w.write_all(&v.to_le_bytes()),
+            TagValueBuf::Uint32(v) => w.write_all(&v.to_le_bytes()),
+            TagValueBuf::Uint64(v) => w.write_all(&v.to_le_bytes()),
+            TagValueBuf::String(v) => write_str16(w, v),
+            TagValueBuf::Hash(v) => w.write_all(v),
+            TagValueBuf::Float32(v) => w.write_all(&v.to_le_bytes()),
+            TagValueBuf::Bool(v) => w.write_all(&[*v as u8]),
+            TagValueBuf::BoolArray { len, bits } => {
+                w.write_all(&len.to_le_bytes())?;
+                w.write_all(bits)
+            }
+            TagValueBuf::Blob(v) => {
+                let len: u32 = v
+                    .len()
+                    .try_into()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "blob too long"))?;
+                w.write_all(&len.to_le_bytes())?;
+                w.write_all(v)
+            }
+            TagValueBuf::Bsob(v) => {
+                let len: u8 = v
+                    .len()
+                    .try_into()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bsob too long"))?;
+                w.write_all(&[len])?;
+                w.write_all(v)
+            }
+        }
+    }
+}
+
+impl<'a> From<TagValue<'a>> for TagValueBuf {
+    fn from(value: TagValue<'a>) -> Self {
+        match value {
+            TagValue::Hash(v) => {
+                // NOTE: validated in `Tag::from_slice()`
+                TagValueBuf::Hash(v.try_into().unwrap())
+            }
+            TagValue::String_(v) => TagValueBuf::String(v.to_vec()),
+            TagValue::Uint64(v) => TagValueBuf::Uint64(v),
+            TagValue::Uint32(v) => TagValueBuf::Uint32(v),
+            TagValue::Uint16(v) => TagValueBuf::Uint16(v),
+            TagValue::Uint8(v) => TagValueBuf::Uint8(v),
+            TagValue::Float32(v) => TagValueBuf::Float32(v),
+            TagValue::Bool(v) => TagValueBuf::Bool(v),
+            TagValue::BoolArray { len, bits } => TagValueBuf::BoolArray {
+                len,
+                bits: bits.to_vec(),
+            },
+            TagValue::Blob(v) => TagValueBuf::Blob(v.to_vec()),
+            TagValue::Bsob(v) => TagValueBuf::Bsob(v.to_vec()),
+        }
+    }
 }
 
 impl<'a> PartialEq<Tag<'a>> for TagBuf {
@@ -1147,23 +6258,25 @@ impl<'a> PartialEq<TagBuf> for Tag<'a> {
 
 impl<'a> PartialEq<TagValue<'a>> for TagValueBuf {
     fn eq(&self, other: &TagValue<'a>) -> bool {
-        match self {
-            TagValueBuf::Uint8(a) => match other {
-                TagValue::Uint8(b) if a == b => true,
-                _ => false,
-            },
-            TagValueBuf::Uint16(a) => match other {
-                TagValue::Uint16(b) if a == b => true,
-                _ => false,
-            },
-            TagValueBuf::Uint32(a) => match other {
-                TagValue::Uint32(b) if a == b => true,
-                _ => false,
-            },
-            TagValueBuf::Uint64(a) => match other {
-                TagValue::Uint64(b) if a == b => true,
-                _ => false,
-            },
+        match (self, *other) {
+            (TagValueBuf::Uint8(a), TagValue::Uint8(b)) => *a == b,
+            (TagValueBuf::Uint16(a), TagValue::Uint16(b)) => *a == b,
+            (TagValueBuf::Uint32(a), TagValue::Uint32(b)) => *a == b,
+            (TagValueBuf::Uint64(a), TagValue::Uint64(b)) => *a == b,
+            (TagValueBuf::String(a), TagValue::String_(b)) => a == b,
+            (TagValueBuf::Hash(a), TagValue::Hash(b)) => a[..] == *b,
+            (TagValueBuf::Float32(a), TagValue::Float32(b)) => *a == b,
+            (TagValueBuf::Bool(a), TagValue::Bool(b)) => *a == b,
+            (
+                TagValueBuf::BoolArray { len, bits },
+                TagValue::BoolArray {
+                    len: b_len,
+                    bits: b_bits,
+                },
+            ) => *len == b_len && bits == b_bits,
+            (TagValueBuf::Blob(a), TagValue::Blob(b)) => a == b,
+            (TagValueBuf::Bsob(a), TagValue::Bsob(b)) => a == b,
+            _ => false,
         }
     }
 }
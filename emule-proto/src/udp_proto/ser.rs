@@ -0,0 +1,531 @@
+//! `Serialize` for decoded packets, producing a dissection of each field
+//!
+//! The schema of a `Packet`:
+//!
+//! ```norust
+//! {
+//!     "udp_proto": u8,           // first byte on the wire
+//!     "packed": bool,            // `UdpProto::KademliaPacked`
+//!     "kind": "kad" | "emule" | "server" | null,
+//!     "opcode": u8 | null,
+//!     "operation": string | null, // name of the opcode, if we know it
+//!     "fields": object | null,    // the decoded operation
+//!     "error": string | null,     // why the packet (or operation) couldn't be decoded
+//!     "unknown": hex | null,      // bytes we couldn't decode
+//! }
+//! ```
+//!
+//! kad ids are upper case hex, as emule displays them (see `KadId`). ed2k hashes and other
+//! unstructured bytes are hex in wire order, and names and strings are lossy utf-8.
+use super::*;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+
+/// Bytes without a known structure, as hex
+struct Hex<T>(T);
+
+impl<T: AsRef<[u8]>> fmt::Display for Hex<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0
+            .as_ref()
+            .iter()
+            .try_for_each(|b| write!(fmt, "{:02x}", b))
+    }
+}
+
+impl<T: AsRef<[u8]>> Serialize for Hex<T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// A name or other string, which emule sends as utf-8
+struct Str<'a>(&'a [u8]);
+
+impl Serialize for Str<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&String::from_utf8_lossy(self.0))
+    }
+}
+
+/// Enum names, as in their `Debug` output
+struct Name<T>(T);
+
+impl<T: fmt::Debug> Serialize for Name<T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(&format_args!("{:?}", self.0))
+    }
+}
+
+/// Operations without any content
+struct Empty;
+
+impl Serialize for Empty {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_struct("Empty", 0)?.end()
+    }
+}
+
+/// Serialize a view as a struct of (mostly) its accessors
+macro_rules! view {
+    ($ty:ident, |$v:ident| { $($key:literal: $value:expr),* $(,)? }) => {
+        impl Serialize for $ty<'_> {
+            fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                let $v = self;
+                let mut st = s.serialize_struct(stringify!($ty), [$($key),*].len())?;
+                $(st.serialize_field($key, &$value)?;)*
+                st.end()
+            }
+        }
+    };
+}
+
+impl Serialize for Packet<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        Dissection::new(self, self.kind()).serialize(s)
+    }
+}
+
+/// Serializes like a `Packet`, but with a `Kind` that was already decoded (for example by a
+/// `DecodeContext`), so packed packets aren't decompressed again
+pub struct Dissection<'a, 'p> {
+    packet: &'a Packet<'p>,
+    kind: Result<Kind<'a>, Error>,
+}
+
+impl<'a, 'p> Dissection<'a, 'p> {
+    /// `kind` is the result of decoding `packet`
+    pub fn new(packet: &'a Packet<'p>, kind: Result<Kind<'a>, Error>) -> Self {
+        Dissection { packet, kind }
+    }
+}
+
+impl Serialize for Dissection<'_, '_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let raw = &self.packet.raw;
+        let mut st = s.serialize_struct("Packet", 8)?;
+        st.serialize_field("udp_proto", &raw[0])?;
+        st.serialize_field("packed", &self.packet.is_packed())?;
+        match &self.kind {
+            Ok(Kind::Kad(k)) => {
+                st.serialize_field("kind", "kad")?;
+                operation_fields(&mut st, &k.raw, k.opcode(), k.try_operation())?;
+            }
+            Ok(Kind::Emule(e)) => {
+                st.serialize_field("kind", "emule")?;
+                operation_fields(&mut st, &e.raw, e.opcode(), e.try_operation())?;
+            }
+            Ok(Kind::Server(p)) => {
+                st.serialize_field("kind", "server")?;
+                operation_fields(&mut st, &p.raw, p.opcode(), p.try_operation())?;
+            }
+            Err(e) => {
+                st.serialize_field("kind", &None::<&str>)?;
+                st.serialize_field("opcode", &raw.get(1))?;
+                st.serialize_field("operation", &None::<&str>)?;
+                st.serialize_field("fields", &None::<Empty>)?;
+                st.serialize_field("error", &e.to_string())?;
+                st.serialize_field("unknown", &Hex(&raw[1..]))?;
+            }
+        }
+        st.end()
+    }
+}
+
+/// `raw` starts with the opcode
+fn operation_fields<S: SerializeStruct, O: fmt::Debug, T: Serialize>(
+    st: &mut S,
+    raw: &[u8],
+    opcode: Option<O>,
+    op: Result<Option<T>, Error>,
+) -> Result<(), S::Error> {
+    st.serialize_field("opcode", &raw[0])?;
+    st.serialize_field("operation", &opcode.map(Name))?;
+    let (fields, error, unknown) = match op {
+        Ok(Some(op)) => (Some(op), None, None),
+        Ok(None) => (None, None, Some(Hex(&raw[1..]))),
+        Err(e) => (None, Some(e.to_string()), Some(Hex(&raw[1..]))),
+    };
+    st.serialize_field("fields", &fields)?;
+    st.serialize_field("error", &error)?;
+    st.serialize_field("unknown", &unknown)
+}
+
+impl Serialize for Operation<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Operation::BootstrapResp(v) => v.serialize(s),
+            Operation::Req(v) | Operation::ReqV0(v) => v.serialize(s),
+            Operation::Res(v) | Operation::ResV0(v) => v.serialize(s),
+            Operation::HelloReq(v) | Operation::HelloRes(v) => v.serialize(s),
+            Operation::HelloResAck(v) => v.serialize(s),
+            Operation::SearchKeyReq(v) => v.serialize(s),
+            Operation::SearchSourceReq(v) => v.serialize(s),
+            Operation::SearchNotesReq(v) => v.serialize(s),
+            Operation::SearchRes(v) => v.serialize(s),
+            Operation::PublishKeyReq(v) | Operation::PublishReqV1(v) => v.serialize(s),
+            Operation::PublishSourceReq(v) | Operation::PublishNotesReq(v) => v.serialize(s),
+            Operation::PublishRes(v) | Operation::PublishResV1(v) => v.serialize(s),
+            Operation::FirewalledReqV1(v) => v.serialize(s),
+            Operation::Firewalled2ReqV1(v) => v.serialize(s),
+            Operation::FirewalledResV1(v) => v.serialize(s),
+            Operation::FindBuddyReqV1(v) | Operation::FindBuddyResV1(v) => v.serialize(s),
+            Operation::CallbackReqV1(v) => v.serialize(s),
+            Operation::FirewallUdp(v) => v.serialize(s),
+            Operation::Pong(v) => v.serialize(s),
+            Operation::BootstrapReqV0(v) | Operation::HelloReqV0(v) | Operation::HelloResV0(v) => {
+                v.serialize(s)
+            }
+            Operation::BootstrapResV0(v) => v.serialize(s),
+            Operation::SearchReqV1(v) => v.serialize(s),
+            Operation::SearchResV1(v) => v.serialize(s),
+            Operation::PublishResAck | Operation::FirewalledAckResV1 | Operation::Ping => {
+                Empty.serialize(s)
+            }
+        }
+    }
+}
+
+impl Serialize for EmuleOperation<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            EmuleOperation::ReAskFilePing(v) => v.serialize(s),
+            EmuleOperation::ReAskAck(v) => v.serialize(s),
+            EmuleOperation::ReAskCallBackUdp(v) => v.serialize(s),
+            EmuleOperation::DirectCallbackReq(v) => v.serialize(s),
+            EmuleOperation::PortTest { value } => {
+                let mut st = s.serialize_struct("PortTest", 1)?;
+                st.serialize_field("value", value)?;
+                st.end()
+            }
+            EmuleOperation::FileNotFound | EmuleOperation::QueueFull => Empty.serialize(s),
+        }
+    }
+}
+
+impl Serialize for ServerOperation<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ServerOperation::GlobSearchReq(expr) | ServerOperation::GlobSearchReq2(expr) => {
+                let mut st = s.serialize_struct("GlobSearchReq", 1)?;
+                st.serialize_field("expr", expr)?;
+                st.end()
+            }
+            ServerOperation::GlobSearchRes(v) => v.serialize(s),
+            ServerOperation::GlobGetSources(v) => v.serialize(s),
+            ServerOperation::GlobGetSources2(v) => v.serialize(s),
+            ServerOperation::GlobFoundSources(v) => v.serialize(s),
+            ServerOperation::GlobServStatReq { challenge } => {
+                let mut st = s.serialize_struct("GlobServStatReq", 1)?;
+                st.serialize_field("challenge", challenge)?;
+                st.end()
+            }
+            ServerOperation::GlobServStatRes(v) => v.serialize(s),
+            ServerOperation::ServerDescReq { challenge } => {
+                let mut st = s.serialize_struct("ServerDescReq", 1)?;
+                st.serialize_field("challenge", challenge)?;
+                st.end()
+            }
+            ServerOperation::ServerDescRes(v) => v.serialize(s),
+        }
+    }
+}
+
+view!(BootstrapResp, |v| {
+    "client_id": v.client_id(),
+    "client_port": v.client_port(),
+    "client_version": v.client_version(),
+    "contacts": v.contacts().ok().map(Iterator::collect::<Vec<_>>),
+});
+
+view!(BootstrapRespContact, |v| {
+    "client_id": v.client_id(),
+    "ip_addr": v.ip_addr(),
+    "udp_port": v.udp_port(),
+    "tcp_port": v.tcp_port(),
+    "version": v.version(),
+});
+
+view!(Req, |v| {
+    "type": v.type_(),
+    "target": v.target(),
+    "check": v.check(),
+});
+
+view!(Res, |v| {
+    "target": v.target(),
+    "contacts": v.contacts().collect::<Vec<_>>(),
+});
+
+view!(ResContact, |v| {
+    "client_id": v.client_id(),
+    "ip_addr": v.ip_addr(),
+    "udp_port": v.udp_port(),
+    "tcp_port": v.tcp_port(),
+    "version": v.version(),
+});
+
+view!(Hello, |v| {
+    "client_id": v.client_id(),
+    "tcp_port": v.tcp_port(),
+    "version": v.version(),
+    "tags": v.tags(),
+});
+
+view!(HelloResAck, |v| {
+    "client_id": v.client_id(),
+    "tags": v.tags(),
+});
+
+view!(SearchKeyReq, |v| {
+    "target": v.target(),
+    "start_position": v.start_position(),
+    "is_restrictive": v.is_restrictive(),
+    "expr": v.expr(),
+});
+
+view!(SearchSourceReq, |v| {
+    "target": v.target(),
+    "start_position": v.start_position(),
+    "file_size": v.file_size(),
+});
+
+view!(SearchNotesReq, |v| {
+    "target": v.target(),
+    "file_size": v.file_size(),
+});
+
+view!(SearchRes, |v| {
+    "source_id": v.source_id(),
+    "target_id": v.target_id(),
+    "results": v.results().ok().map(|(r, _)| r.collect::<Vec<_>>()),
+});
+
+view!(SearchResult, |v| {
+    "id": v.id(),
+    "tags": v.tags(),
+});
+
+view!(PublishKeyReq, |v| {
+    "target_id": v.target_id(),
+    "entries": v.entries().collect::<Vec<_>>(),
+});
+
+view!(PublishKeyEntry, |v| {
+    "file_id": v.file_id(),
+    "tags": v.tags(),
+});
+
+view!(PublishReq, |v| {
+    "target_id": v.target_id(),
+    "contact_id": v.contact_id(),
+    "tags": v.tags(),
+});
+
+view!(PublishRes, |v| {
+    "target_id": v.target_id(),
+    "load": v.load(),
+});
+
+view!(FirewalledReq, |v| {
+    "tcp_port": v.tcp_port(),
+});
+
+view!(Firewalled2Req, |v| {
+    "tcp_port": v.tcp_port(),
+    "user_hash": Hex(v.user_hash()),
+    "connect_options": v.connect_options(),
+});
+
+view!(FirewalledRes, |v| {
+    "ip_addr": v.ip_addr(),
+});
+
+view!(FindBuddy, |v| {
+    "buddy_id": v.buddy_id(),
+    "requester_id": v.requester_id(),
+    "client_hash": Hex(v.client_hash()),
+    "client_port": v.client_port(),
+    "connect_options": v.connect_options(),
+});
+
+view!(CallbackReq, |v| {
+    "buddy_id": v.buddy_id(),
+    "file_id": v.file_id(),
+    "tcp_port": v.tcp_port(),
+});
+
+view!(FirewallUdp, |v| {
+    "error_code": v.error_code(),
+    "incoming_port": v.incoming_port(),
+});
+
+view!(Pong, |v| {
+    "recv_port": v.recv_port(),
+});
+
+view!(BootstrapResV0, |v| {
+    "contacts": v.contacts().ok().map(Iterator::collect::<Vec<_>>),
+});
+
+view!(SearchReqV1, |v| {
+    "target": v.target(),
+    "is_restrictive": v.is_restrictive(),
+    "is_source_search": v.is_source_search(),
+    "expr": v.expr(),
+});
+
+view!(SearchResV1, |v| {
+    "target_id": v.target_id(),
+    "results": v.results().ok().map(|(r, _)| r.collect::<Vec<_>>()),
+});
+
+view!(ReAskFilePing, |v| {
+    "file_hash": Hex(v.file_hash()),
+    // layout depends on the sender's udp version, which isn't in the packet
+    "extended": Hex(v.extended_bytes()),
+});
+
+view!(ReAskAck, |v| {
+    "part_status": v.part_status().ok().flatten(),
+    "queue_rank": v.queue_rank(),
+});
+
+view!(PartStatus, |v| {
+    "part_count": v.part_count(),
+    "is_complete": v.is_complete(),
+    "parts": (0..v.part_count()).map(|p| v.has_part(p)).collect::<Vec<_>>(),
+});
+
+view!(ReAskCallBackUdp, |v| {
+    "buddy_id": v.buddy_id(),
+    "reask": v.reask(),
+});
+
+view!(DirectCallbackReq, |v| {
+    "tcp_port": v.tcp_port(),
+    "user_hash": Hex(v.user_hash()),
+    "connect_options": v.connect_options(),
+});
+
+view!(GetSources, |v| {
+    "file_hashes": v.file_hashes().map(Hex).collect::<Vec<_>>(),
+});
+
+view!(GetSources2, |v| {
+    // `(file_hash, file_size)`
+    "files": v.entries().map(|(h, size)| (Hex(h), size)).collect::<Vec<_>>(),
+});
+
+view!(FoundSources, |v| {
+    "entries": v.entries().collect::<Vec<_>>(),
+});
+
+view!(FoundSourcesEntry, |v| {
+    "file_hash": Hex(v.file_hash()),
+    // `(client_id, tcp_port)`
+    "sources": v.sources().collect::<Vec<_>>(),
+});
+
+view!(GlobSearchRes, |v| {
+    "results": v.results().collect::<Vec<_>>(),
+});
+
+view!(ServerSearchResult, |v| {
+    "file_hash": Hex(v.file_hash()),
+    "client_id": v.client_id(),
+    "client_port": v.client_port(),
+    "tags": v.tags(),
+});
+
+view!(ServStatRes, |v| {
+    "challenge": v.challenge(),
+    "users": v.users(),
+    "files": v.files(),
+    "max_users": v.max_users(),
+    "soft_files": v.soft_files(),
+    "hard_files": v.hard_files(),
+    "udp_flags": v.udp_flags(),
+    "low_id_users": v.low_id_users(),
+    "udp_obfuscation_port": v.udp_obfuscation_port(),
+    "tcp_obfuscation_port": v.tcp_obfuscation_port(),
+    "udp_key": v.udp_key(),
+});
+
+view!(ServerDescRes, |v| {
+    "challenge": v.challenge(),
+    "name": v.name().map(Str),
+    "description": v.description().map(Str),
+    "tags": v.tags(),
+});
+
+view!(Tag, |v| {
+    "id": v.id().map(Name),
+    "name": Hex(v.name()),
+    "type": Name(v.tag_type()),
+    "value": v.value(),
+});
+
+impl Serialize for TagList<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        // NOTE: validated in `TagList::from_slice()`
+        s.collect_seq(self.iter().filter_map(Result::ok))
+    }
+}
+
+impl Serialize for TagList32<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        // NOTE: validated in `TagList32::from_slice()`
+        s.collect_seq(self.iter().filter_map(Result::ok))
+    }
+}
+
+impl Serialize for TagValue<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            TagValue::Hash(v) | TagValue::Blob(v) | TagValue::Bsob(v) => Hex(v).serialize(s),
+            TagValue::String_(v) => Str(v).serialize(s),
+            TagValue::Uint64(v) => s.serialize_u64(v),
+            TagValue::Uint32(v) => s.serialize_u32(v),
+            TagValue::Uint16(v) => s.serialize_u16(v),
+            TagValue::Uint8(v) => s.serialize_u8(v),
+            TagValue::Float32(v) => s.serialize_f32(v),
+            TagValue::Bool(v) => s.serialize_bool(v),
+            TagValue::BoolArray { len, bits } => {
+                let mut st = s.serialize_struct("BoolArray", 2)?;
+                st.serialize_field("len", &len)?;
+                st.serialize_field("bits", &Hex(bits))?;
+                st.end()
+            }
+        }
+    }
+}
+
+impl Serialize for SearchExpr<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStructVariant;
+        match self {
+            SearchExpr::And(l, r) => s.serialize_newtype_variant("SearchExpr", 0, "And", &(l, r)),
+            SearchExpr::Or(l, r) => s.serialize_newtype_variant("SearchExpr", 1, "Or", &(l, r)),
+            SearchExpr::AndNot(l, r) => {
+                s.serialize_newtype_variant("SearchExpr", 2, "AndNot", &(l, r))
+            }
+            SearchExpr::String(v) => {
+                s.serialize_newtype_variant("SearchExpr", 3, "String", &Str(v))
+            }
+            SearchExpr::MetaTag { name, value } => {
+                let mut st = s.serialize_struct_variant("SearchExpr", 4, "MetaTag", 2)?;
+                st.serialize_field("name", &Hex(name))?;
+                st.serialize_field("value", &Str(value))?;
+                st.end()
+            }
+            SearchExpr::Numeric { name, op, value } => {
+                let mut st = s.serialize_struct_variant("SearchExpr", 5, "Numeric", 3)?;
+                st.serialize_field("name", &Hex(name))?;
+                st.serialize_field("op", &Name(op))?;
+                st.serialize_field("value", value)?;
+                st.end()
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use emule_proto::extern_port::*;
+use std::net::IpAddr;
+
+fn ip(n: u8) -> IpAddr {
+    [10, 0, 0, n].into()
+}
+
+#[test]
+fn agreeing_hosts() {
+    let mut l = ExternPortLookup::new();
+    for n in 1..=4 {
+        l.add_ping(ip(n));
+    }
+    assert!(l.is_searching());
+    assert_eq!(l.add_pong(ip(1), 5000), None);
+    // repeat replies from the same host don't count
+    assert_eq!(l.add_pong(ip(1), 5000), None);
+    assert_eq!(l.add_pong(ip(2), 6000), None);
+    assert_eq!(l.add_pong(ip(3), 5000), Some(5000));
+    assert!(!l.is_searching());
+    assert_eq!(l.extern_port(), Some(5000));
+
+    // later replies are ignored
+    assert_eq!(l.add_pong(ip(4), 6000), Some(5000));
+}
+
+#[test]
+fn disagreeing_hosts() {
+    let mut l = ExternPortLookup::new();
+    for n in 0..EXTERNAL_PORT_ASK_IPS {
+        assert!(l.is_searching());
+        l.add_ping(ip(n as u8));
+        assert_eq!(l.add_pong(ip(n as u8), 5000 + n as u16), None);
+    }
+    assert!(!l.is_searching());
+    assert_eq!(l.extern_port(), None);
+
+    l.reset();
+    assert!(l.is_searching());
+}
+
+#[test]
+fn unsolicited_pongs() {
+    let mut l = ExternPortLookup::new();
+    l.add_ping(ip(1));
+    // hosts we didn't ping can't pick our port
+    assert_eq!(l.add_pong(ip(2), 6000), None);
+    assert_eq!(l.add_pong(ip(3), 6000), None);
+    assert!(l.is_searching());
+
+    assert_eq!(l.add_pong(ip(1), 5000), None);
+    l.add_ping(ip(2));
+    assert_eq!(l.add_pong(ip(2), 5000), Some(5000));
+}
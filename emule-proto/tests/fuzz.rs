@@ -0,0 +1,333 @@
+//! Randomized inputs, which must never panic the decoders
+use emule_proto::udp_proto::*;
+use emule_proto::KadId;
+use hex_literal::hex;
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+const ITERATIONS: usize = 100_000;
+
+/// Used unless `REMULE_FUZZ_SEED` is set, so failures reproduce
+const DEFAULT_SEED: u64 = 0x72656d756c65;
+
+fn keys() -> Keys<'static> {
+    const USER_HASH: [u8; 16] = hex!("a0a1a2a3a4a5a6a7a8a9aaabacadaeaf");
+    Keys {
+        kad_id: KadId::from_wire(hex!("000102030405060708090a0b0c0d0e0f")),
+        user_hash: &USER_HASH,
+        source_ip: "192.168.1.2".parse().unwrap(),
+        source_key: Some(5),
+    }
+}
+
+fn tags() -> Vec<TagBuf> {
+    vec![
+        TagBuf::with_id(TagId::FileName, TagValueBuf::String(b"foo.mp3".to_vec())),
+        TagBuf::with_id(TagId::FileSize, TagValueBuf::uint(5 << 32)),
+        TagBuf::with_id(TagId::KadAichHashResult, TagValueBuf::Bsob(vec![1; 22])),
+        TagBuf::with_id(TagId::BuddyHash, TagValueBuf::String(vec![b'a'; 32])),
+        TagBuf::with_id(TagId::Flags, TagValueBuf::Blob(vec![1, 2, 3])),
+    ]
+}
+
+/// Well formed packets of most kinds, to be mutated
+fn corpus() -> Vec<Vec<u8>> {
+    let contact = |client_id: u128| ContactBuf {
+        client_id: client_id.into(),
+        ip_addr: [1, 2, 3, 4].into(),
+        udp_port: 4672,
+        tcp_port: 4662,
+        version: 9,
+    };
+    let kad = [
+        OperationBuf::BootstrapReq,
+        OperationBuf::BootstrapReqV0(contact(1)),
+        OperationBuf::BootstrapResp {
+            client_id: KadId::from(1),
+            client_port: 2,
+            client_version: 9,
+            contacts: (0..3).map(contact).collect(),
+        },
+        OperationBuf::Res {
+            target: KadId::from(1),
+            contacts: (0..3).map(contact).collect(),
+        },
+        OperationBuf::Pong { recv_port: 5 },
+        OperationBuf::HelloReq(Details {
+            src_kad_id: KadId::from(1),
+            src_port: 2,
+            kad_version: 9,
+            src_port_internal: Some(3),
+            udp_firewalled: Some(true),
+            tcp_firewalled: None,
+            req_ack: Some(true),
+        }),
+        OperationBuf::PublishKeyReq {
+            target_id: KadId::from(1),
+            entries: vec![PublishEntryBuf {
+                file_id: KadId::from(2),
+                tags: tags(),
+            }],
+        },
+        OperationBuf::PublishSourceReq {
+            target_id: KadId::from(1),
+            contact_id: KadId::from(2),
+            tags: tags(),
+        },
+        OperationBuf::PublishRes {
+            target_id: KadId::from(1),
+            load: 2,
+        },
+        OperationBuf::FindBuddyReqV1 {
+            buddy_id: KadId::from(1),
+            src_client_hash: [2; 16],
+            src_client_port: 3,
+            connect_options: Some(4),
+        },
+        OperationBuf::CallbackReqV1 {
+            buddy_id: KadId::from(1),
+            file_id: KadId::from(2),
+            tcp_port: 3,
+        },
+        OperationBuf::Firewalled2ReqV1 {
+            tcp_port: 1,
+            user_hash: [2; 16],
+            connect_options: 3,
+        },
+        OperationBuf::FirewallUdp {
+            error_code: 1,
+            incoming_port: 2,
+        },
+    ];
+    let emule = [
+        EmuleOperationBuf::ReAskFilePing {
+            file_hash: [1; 16],
+            part_status: Some(vec![true, false, true]),
+            complete_sources: Some(2),
+        },
+        EmuleOperationBuf::ReAskAck {
+            part_status: Some(vec![true; 20]),
+            queue_rank: 3,
+        },
+        EmuleOperationBuf::ReAskCallBackUdp {
+            buddy_id: KadId::from(1),
+            file_hash: [2; 16],
+            part_status: Some(vec![false; 9]),
+            complete_sources: None,
+        },
+        EmuleOperationBuf::DirectCallbackReq {
+            tcp_port: 1,
+            user_hash: [2; 16],
+            connect_options: 3,
+        },
+    ];
+    let mut expr = Vec::new();
+    SearchExpr::And(
+        Box::new(SearchExpr::String(b"foo")),
+        Box::new(SearchExpr::Numeric {
+            name: b"\x02",
+            op: NumericOp::Gt,
+            value: 5,
+        }),
+    )
+    .write_to(&mut expr)
+    .unwrap();
+    let tag_list = TagListBuf {
+        encoding: TagEncoding::Compact,
+        tags: tags(),
+    };
+    let server = [
+        ServerOperationBuf::GlobSearchReq { expr },
+        ServerOperationBuf::GlobSearchRes {
+            results: (0..2)
+                .map(|i| ServerSearchResultBuf {
+                    file_hash: [i; 16],
+                    client_id: 1,
+                    client_port: 2,
+                    tags: tag_list.clone(),
+                })
+                .collect(),
+        },
+        ServerOperationBuf::GlobGetSources2 {
+            files: vec![([1; 16], 2), ([3; 16], 4 << 32)],
+        },
+        ServerOperationBuf::GlobFoundSources {
+            entries: (0..2)
+                .map(|i| FoundSourcesBuf {
+                    file_hash: [i; 16],
+                    sources: vec![(1, 2), (3, 4)],
+                })
+                .collect(),
+        },
+        ServerOperationBuf::GlobServStatRes(ServStatResBuf::default()),
+        ServerOperationBuf::ServerDescRes {
+            challenge: SERVER_DESC_CHALLENGE.into(),
+            tags: tag_list.clone(),
+        },
+    ];
+
+    let mut corpus = Vec::new();
+    for op in kad {
+        let mut b = Vec::new();
+        op.write_to(&mut b).unwrap();
+        corpus.push(b);
+    }
+    for op in emule {
+        let mut b = Vec::new();
+        op.write_to(&mut b).unwrap();
+        corpus.push(b);
+    }
+    for op in server {
+        let mut b = Vec::new();
+        op.write_to(&mut b).unwrap();
+        corpus.push(b);
+    }
+    corpus.extend([
+        // SearchKeyReq
+        hex!("e4 33 000102030405060708090a0b0c0d0e0f 0580 00 00 01 0300 666f6f 02 0300 6d7033 0100 03")
+            .to_vec(),
+        // SearchRes
+        hex!("e4 3b 01000000000000000000000000000000 02000000000000000000000000000000 0100 03000000000000000000000000000000 02 02 0100 01 0300 666f6f 89 15 07")
+            .to_vec(),
+        // SearchReqV1
+        hex!("e4 30 01000000000000000000000000000000 01 01 0300 666f6f").to_vec(),
+    ]);
+    corpus
+}
+
+fn mutate(rng: &mut StdRng, corpus: &[Vec<u8>]) -> Vec<u8> {
+    let mut v = match rng.random_range(0..4) {
+        // random header, random body
+        0 => {
+            let proto = [0xE4, 0xE5, 0xC5, 0xE3, rng.random()][rng.random_range(0..5)];
+            let len = rng.random_range(0..80);
+            let mut v = vec![proto, rng.random()];
+            v.extend((0..len).map(|_| rng.random::<u8>()));
+            return v;
+        }
+        _ => corpus[rng.random_range(0..corpus.len())].clone(),
+    };
+
+    for _ in 0..rng.random_range(1..4) {
+        match rng.random_range(0..4) {
+            0 if !v.is_empty() => {
+                let i = rng.random_range(0..v.len());
+                v[i] = rng.random();
+            }
+            1 if !v.is_empty() => {
+                v.truncate(rng.random_range(0..v.len()));
+            }
+            2 => {
+                let i = rng.random_range(0..=v.len());
+                v.insert(i, rng.random());
+            }
+            _ => {
+                let i = rng.random_range(0..=v.len());
+                let b = [0x00, 0x01, 0x7f, 0x80, 0xff][rng.random_range(0..5)];
+                v.insert(i, b);
+            }
+        }
+    }
+    v
+}
+
+/// Decode everything reachable from `raw`
+fn exercise(raw: &[u8]) {
+    let Ok(mut p) = Packet::from_slice(raw) else {
+        return;
+    };
+    let _ = p.decrypt(&keys());
+    let _ = format!("{:?}", p);
+
+    match p.kind() {
+        Ok(Kind::Kad(k)) => match k.operation() {
+            Some(Operation::SearchRes(r)) => {
+                if let Ok((results, _)) = r.results() {
+                    for r in results {
+                        let _ = (r.file_name(), r.file_size(), r.aich_hash(), r.buddy_hash());
+                        let _ = TagListBuf::from(&r.tags());
+                    }
+                }
+            }
+            Some(Operation::PublishKeyReq(r)) => {
+                for e in r.entries() {
+                    let _ = TagListBuf::from(&e.tags());
+                }
+            }
+            op => {
+                let _ = format!("{:?}", op);
+            }
+        },
+        Ok(Kind::Emule(e)) => match e.operation() {
+            Some(EmuleOperation::ReAskFilePing(r)) => {
+                for v in 0..6 {
+                    let _ = format!("{:?}", r.extended(v));
+                }
+            }
+            Some(EmuleOperation::ReAskCallBackUdp(r)) => {
+                for v in 0..6 {
+                    let _ = format!("{:?}", r.reask().extended(v));
+                }
+            }
+            op => {
+                let _ = format!("{:?}", op);
+            }
+        },
+        Ok(Kind::Server(s)) => match s.operation() {
+            Some(ServerOperation::GlobSearchRes(r)) => {
+                for r in r.results() {
+                    let _ = (r.file_name(), r.file_size(), r.sources());
+                    let _ = TagListBuf::from(&r.tags());
+                }
+            }
+            op => {
+                let _ = format!("{:?}", op);
+            }
+        },
+        Err(_) => {}
+    }
+
+    // the payload, as a tag list of either kind
+    if raw.len() > 2 {
+        if let Ok((t, _)) = TagList::from_slice(&raw[2..]) {
+            let _ = format!("{:?}", t);
+            let _ = TagListBuf::from(&t);
+        }
+        if let Ok((t, _)) = TagList32::from_slice(&raw[2..]) {
+            let _ = format!("{:?}", t);
+            let _ = TagListBuf::from(&t);
+        }
+        if let Ok((e, _)) = SearchExpr::from_slice(&raw[2..]) {
+            let _ = format!("{:?}", e);
+        }
+    }
+}
+
+#[test]
+fn random_packets_do_not_panic() {
+    let seed = match std::env::var("REMULE_FUZZ_SEED") {
+        Ok(s) => s.parse().expect("REMULE_FUZZ_SEED must be a u64"),
+        Err(_) => DEFAULT_SEED,
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+    let corpus = corpus();
+
+    // keep test output readable, we report the input ourselves
+    std::panic::set_hook(Box::new(|_| {}));
+    for _ in 0..ITERATIONS {
+        let v = mutate(&mut rng, &corpus);
+        if let Err(e) = catch_unwind(AssertUnwindSafe(|| exercise(&v))) {
+            let _ = std::panic::take_hook();
+            let msg = e
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()));
+            panic!(
+                "REMULE_FUZZ_SEED={}: input {:02x?} panicked: {:?}",
+                seed, v, msg
+            );
+        }
+    }
+    let _ = std::panic::take_hook();
+}
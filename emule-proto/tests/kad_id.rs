@@ -0,0 +1,62 @@
+use emule_proto::kad_id::ParseKadIdError;
+use emule_proto::KadId;
+use hex_literal::hex;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+#[test]
+fn wire_and_hex() {
+    let wire = hex!("00010203 04050607 08090a0b 0c0d0e0f");
+    let id = KadId::from_wire(wire);
+    // each word is little endian, most significant word first
+    assert_eq!(u128::from(id), 0x03020100_07060504_0b0a0908_0f0e0d0c);
+    assert_eq!(id.to_string(), "03020100070605040B0A09080F0E0D0C");
+    assert_eq!(format!("{:x}", id), "3020100070605040b0a09080f0e0d0c");
+    assert_eq!(id.to_wire(), wire);
+
+    assert_eq!("03020100070605040b0a09080f0e0d0c".parse(), Ok(id));
+    assert_eq!(
+        "0302".parse::<KadId>(),
+        Err(ParseKadIdError::Length { len: 4 })
+    );
+    assert_eq!(
+        "0302010007060504 b0a09080f0e0d0c".parse::<KadId>(),
+        Err(ParseKadIdError::Digit { c: ' ' })
+    );
+}
+
+#[test]
+fn distance() {
+    let a = KadId::from(0b1100 << 124);
+    let b = KadId::from(0b1010 << 124);
+    let c = KadId::from(0b1110 << 124);
+
+    assert_eq!(a.distance(b), KadId::from(0b0110 << 124));
+    assert_eq!(a.distance(a), KadId::ZERO);
+    // c is closer to a, as it shares the first 2 bits
+    assert!(a.distance(c) < a.distance(b));
+    assert_eq!(a.common_prefix_len(b), 1);
+    assert_eq!(a.common_prefix_len(c), 2);
+    assert_eq!(a.common_prefix_len(a), 128);
+
+    assert!(a.bit(0));
+    assert!(a.bit(1));
+    assert!(!a.bit(2));
+    assert!(!a.bit(127));
+    assert!(KadId::from(1).bit(127));
+
+    assert_eq!(!KadId::ZERO, KadId::from(u128::MAX));
+}
+
+#[test]
+fn random_with_prefix() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let prefix: KadId = "F0000000000000000000000000000000".parse().unwrap();
+    for prefix_len in [0, 1, 4, 100, 128] {
+        for _ in 0..100 {
+            let id = KadId::random_with_prefix(prefix, prefix_len, &mut rng);
+            assert!(id.common_prefix_len(prefix) >= prefix_len);
+        }
+    }
+    assert_ne!(KadId::random(&mut rng), KadId::random(&mut rng));
+}
@@ -1,4 +1,4 @@
-use remule::nodes::*;
+use emule_proto::nodes::*;
 use std::fs;
 
 #[test]
@@ -8,9 +8,9 @@ fn load_1() {
     let n = parse(&d[..]).unwrap();
 
     assert_eq!(n.version, 2);
-    assert_eq!(n.is_bootstrap, false);
+    assert!(!n.is_bootstrap);
     assert_eq!(n.contacts[0], Contact {
-        id: 92080831125886507272668723008887820410,
+        id: "9503907A7626D238BA3EB2B445461F7C".parse().unwrap(),
         ip: "190.215.228.231".parse().unwrap(),
         udp_port: 4672,
         tcp_port: 4662,
@@ -19,9 +19,12 @@ fn load_1() {
         kad_udp_key: Some((1182285559, 1289133357)),
         verified: Some(1)
     });
+    let key = n.contacts[0].udp_key().unwrap();
+    assert_eq!(key.key, 1182285559);
+    assert_eq!(key.ip, "45.157.214.76".parse::<std::net::Ipv4Addr>().unwrap());
 
     assert_eq!(n.contacts[n.contacts.len() - 1],  Contact {
-        id: 137127252135864945998695557671398454457,
+        id: "68F8B4B9488F940801823D146729BF84".parse().unwrap(),
         ip: "70.44.85.250".parse().unwrap(),
         udp_port: 3912,
         tcp_port: 3911,
@@ -31,3 +34,85 @@ fn load_1() {
         verified: Some(1)
     });
 }
+
+#[test]
+fn round_trip() {
+    for name in ["1", "2", "3", "4", "emule_0_50a_normal"] {
+        let d = fs::read(format!("tests/nodes-dat/{}", name)).unwrap();
+        let n = parse(&d[..]).unwrap();
+
+        let mut b = Vec::new();
+        n.write_to(&mut b).unwrap();
+        assert!(b == d, "{} differs after writing", name);
+    }
+}
+
+#[test]
+fn write_versions() {
+    let d = fs::read("tests/nodes-dat/1").unwrap();
+    let mut n = parse(&d[..]).unwrap();
+
+    // version 3 only adds the (0) bootstrap edition to the header
+    n.version = 3;
+    let mut b = Vec::new();
+    n.write_to(&mut b).unwrap();
+    assert_eq!(b.len(), d.len() + 4);
+    assert_eq!(&b[..16], &[0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, d[8], d[9], d[10], d[11]]);
+    let n3 = parse(&b[..]).unwrap();
+    assert_eq!(n3.version, 3);
+    assert_eq!(n3.contacts, n.contacts);
+
+    // version 0 has no header, and a type instead of the contact version
+    let contacts = n.contacts.iter().map(|c| Contact {
+        contact_version: None,
+        by_type: Some(3),
+        kad_udp_key: None,
+        verified: None,
+        ..*c
+    }).collect();
+    let n0 = Nodes { version: 0, is_bootstrap: false, contacts };
+    let mut b = Vec::new();
+    n0.write_to(&mut b).unwrap();
+    assert_eq!(b.len(), 4 + n0.contacts.len() * 25);
+    assert_eq!(parse(&b[..]).unwrap().contacts, n0.contacts);
+
+    let empty = Nodes { version: 0, is_bootstrap: false, contacts: Vec::new() };
+    assert!(empty.write_to(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn bootstrap() {
+    let d = fs::read("tests/nodes-dat/1").unwrap();
+    let mut n = parse(&d[..]).unwrap();
+    n.version = 3;
+    n.is_bootstrap = true;
+    for c in &mut n.contacts {
+        c.kad_udp_key = None;
+        c.verified = None;
+    }
+
+    let mut b = Vec::new();
+    n.write_to(&mut b).unwrap();
+    assert_eq!(&b[..12], &[0, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0]);
+    assert_eq!(b.len(), 16 + n.contacts.len() * 25);
+    // first entry: id, ip, udp port, tcp port, version
+    assert_eq!(&b[16..41], &d[12..37]);
+
+    let nb = parse(&b[..]).unwrap();
+    assert_eq!(nb.version, 3);
+    assert!(nb.is_bootstrap);
+    assert_eq!(nb.contacts, n.contacts);
+
+    let mut b2 = Vec::new();
+    nb.write_to(&mut b2).unwrap();
+    assert!(b2 == b);
+
+    // truncated entry
+    assert!(parse(&b[..b.len() - 1]).is_err());
+    // unknown edition
+    b[8] = 2;
+    assert!(parse(&b[..]).is_err());
+
+    n.version = 2;
+    assert!(n.write_to(&mut Vec::new()).is_err());
+}
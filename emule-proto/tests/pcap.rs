@@ -0,0 +1,153 @@
+use emule_proto::pcap::*;
+use hex_literal::hex;
+use std::time::Duration;
+
+/// ipv4 + udp from 1.2.3.4:4672 to 5.6.7.8:4665
+fn ipv4_udp(payload: &[u8]) -> Vec<u8> {
+    let mut v = Vec::new();
+    v.extend_from_slice(&hex!("45 00"));
+    v.extend_from_slice(&(20 + 8 + payload.len() as u16).to_be_bytes());
+    v.extend_from_slice(&hex!("0000 4000 40 11 0000 01020304 05060708"));
+    v.extend_from_slice(&hex!("1240 1239"));
+    v.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    v.extend_from_slice(&hex!("0000"));
+    v.extend_from_slice(payload);
+    v
+}
+
+fn ethernet(ethertype: [u8; 2], ip: &[u8]) -> Vec<u8> {
+    let mut v = hex!("020000000001 020000000002").to_vec();
+    v.extend_from_slice(&ethertype);
+    v.extend_from_slice(ip);
+    v
+}
+
+#[test]
+fn pcap_ethernet() {
+    let ping = hex!("e4 60");
+    let frame = ethernet(hex!("0800"), &ipv4_udp(&ping));
+    // an arp frame, which is skipped
+    let arp = ethernet(hex!("0806"), &[0; 28]);
+
+    let mut v = hex!("d4c3b2a1 0200 0400 00000000 00000000 ffff0000 01000000").to_vec();
+    for (ts_sec, ts_usec, frame) in [(10u32, 5u32, &arp), (11, 250_000, &frame)] {
+        v.extend_from_slice(&ts_sec.to_le_bytes());
+        v.extend_from_slice(&ts_usec.to_le_bytes());
+        v.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        v.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        v.extend_from_slice(frame);
+    }
+
+    let d: Vec<_> = datagrams(&v).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(
+        d,
+        vec![Datagram {
+            timestamp: Duration::from_millis(11_250),
+            src: "1.2.3.4:4672".parse().unwrap(),
+            dst: "5.6.7.8:4665".parse().unwrap(),
+            payload: &ping,
+        }]
+    );
+}
+
+#[test]
+fn pcap_big_endian_nanos() {
+    // raw ip, big endian, nanosecond timestamps
+    let ip6 = hex!(
+        "60000000 000a 11 40 20010db8000000000000000000000001 20010db8000000000000000000000002
+         1240 1240 000a 0000 e460"
+    );
+    let mut v = hex!("a1b23c4d 0002 0004 00000000 00000000 0000ffff 00000065").to_vec();
+    v.extend_from_slice(&hex!("00000001 00000007"));
+    v.extend_from_slice(&(ip6.len() as u32).to_be_bytes());
+    v.extend_from_slice(&(ip6.len() as u32).to_be_bytes());
+    v.extend_from_slice(&ip6);
+
+    let d: Vec<_> = datagrams(&v).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].timestamp, Duration::new(1, 7));
+    assert_eq!(d[0].src, "[2001:db8::1]:4672".parse().unwrap());
+    assert_eq!(d[0].payload, hex!("e460"));
+}
+
+#[test]
+fn pcapng() {
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let len = 12 + body.len().next_multiple_of(4) as u32;
+        let mut v = Vec::new();
+        v.extend_from_slice(&block_type.to_le_bytes());
+        v.extend_from_slice(&len.to_le_bytes());
+        v.extend_from_slice(body);
+        v.resize(len as usize - 4, 0);
+        v.extend_from_slice(&len.to_le_bytes());
+        v
+    }
+
+    let frame = ethernet(
+        hex!("8100"),
+        &[&hex!("0001 0800")[..], &ipv4_udp(&hex!("c5 90 01"))].concat(),
+    );
+    let mut v = block(0x0a0d0d0a, &hex!("4d3c2b1a 0100 0000 ffffffffffffffff"));
+    // ethernet, if_tsresol of milliseconds
+    v.extend(block(
+        1,
+        &hex!("0100 0000 00000000 0900 0100 03000000 0000 0000"),
+    ));
+    let mut epb = hex!("00000000 00000000").to_vec();
+    epb.extend_from_slice(&1_500u32.to_le_bytes());
+    epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&frame);
+    v.extend(block(6, &epb));
+    // simple packet block
+    let mut spb = (frame.len() as u32).to_le_bytes().to_vec();
+    spb.extend_from_slice(&frame);
+    v.extend(block(3, &spb));
+
+    let d: Vec<_> = datagrams(&v).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(d.len(), 2);
+    assert_eq!(d[0].timestamp, Duration::from_millis(1_500));
+    assert_eq!(d[0].dst, "5.6.7.8:4665".parse().unwrap());
+    assert_eq!(d[0].payload, hex!("c5 90 01"));
+    assert_eq!(d[1].timestamp, Duration::ZERO);
+    assert_eq!(d[1].payload, hex!("c5 90 01"));
+
+    // packet before any interface
+    let mut v = block(0x0a0d0d0a, &hex!("4d3c2b1a 0100 0000 ffffffffffffffff"));
+    v.extend(block(6, &epb));
+    let mut d = datagrams(&v).unwrap();
+    assert!(matches!(
+        d.next(),
+        Some(Err(Error::UnknownInterface { interface: 0, .. }))
+    ));
+    assert!(d.next().is_none());
+}
+
+#[test]
+fn truncated() {
+    assert!(matches!(
+        datagrams(&hex!("0102")),
+        Err(Error::HeaderTooShort { have: 2, need: 4 })
+    ));
+    assert!(matches!(
+        datagrams(&hex!("01020304")),
+        Err(Error::UnrecognizedMagic { magic: 0x04030201 })
+    ));
+
+    let mut v = hex!("d4c3b2a1 0200 0400 00000000 00000000 ffff0000 01000000").to_vec();
+    v.extend_from_slice(&hex!("00000000 00000000 10000000 10000000 0102"));
+    let mut d = datagrams(&v).unwrap();
+    assert!(matches!(
+        d.next(),
+        Some(Err(Error::RecordTooShort {
+            offset: 24,
+            have: 18,
+            need: 32
+        }))
+    ));
+    assert!(d.next().is_none());
+    assert_eq!(
+        datagrams(&hex!("0102")).unwrap_err().variant_name(),
+        "HeaderTooShort"
+    );
+}
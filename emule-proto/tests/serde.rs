@@ -0,0 +1,153 @@
+#![cfg(feature = "serde")]
+use emule_proto::udp_proto::*;
+use emule_proto::KadId;
+use hex_literal::hex;
+use serde_json::json;
+
+fn dissect(v: &[u8]) -> serde_json::Value {
+    serde_json::to_value(Packet::from_slice(v).unwrap()).unwrap()
+}
+
+#[test]
+fn dissect_kad() {
+    let mut b = Vec::new();
+    OperationBuf::BootstrapResp {
+        client_id: KadId::from(5),
+        client_port: 4672,
+        client_version: 9,
+        contacts: vec![ContactBuf {
+            client_id: KadId::from(1),
+            ip_addr: [1, 2, 3, 4].into(),
+            udp_port: 4672,
+            tcp_port: 4662,
+            version: 8,
+        }],
+    }
+    .write_to(&mut b)
+    .unwrap();
+
+    assert_eq!(
+        dissect(&b),
+        json!({
+            "udp_proto": 0xe4,
+            "packed": false,
+            "kind": "kad",
+            "opcode": 0x09,
+            "operation": "BootstrapResp",
+            "fields": {
+                "client_id": "00000000000000000000000000000005",
+                "client_port": 4672,
+                "client_version": 9,
+                "contacts": [{
+                    "client_id": "00000000000000000000000000000001",
+                    "ip_addr": "1.2.3.4",
+                    "udp_port": 4672,
+                    "tcp_port": 4662,
+                    "version": 8,
+                }],
+            },
+            "error": null,
+            "unknown": null,
+        })
+    );
+}
+
+#[test]
+fn dissect_tags() {
+    let mut b = Vec::new();
+    OperationBuf::PublishSourceReq {
+        target_id: KadId::from(1),
+        contact_id: KadId::from(2),
+        tags: vec![
+            TagBuf::with_id(TagId::FileName, TagValueBuf::String(b"foo.mp3".to_vec())),
+            TagBuf {
+                name: b"x-custom".to_vec(),
+                value: TagValueBuf::Blob(vec![1, 2]),
+            },
+        ],
+    }
+    .write_packed_to(&mut b)
+    .unwrap();
+
+    let d = dissect(&b);
+    assert_eq!(d["operation"], "PublishSourceReq");
+    assert_eq!(
+        d["fields"]["tags"],
+        json!([
+            {"id": "FileName", "name": "01", "type": "String_", "value": "foo.mp3"},
+            {"id": null, "name": "782d637573746f6d", "type": "Blob", "value": "0102"},
+        ])
+    );
+}
+
+#[test]
+fn dissect_packed() {
+    let mut b = Vec::new();
+    OperationBuf::BootstrapResp {
+        client_id: KadId::from(5),
+        client_port: 4672,
+        client_version: 9,
+        contacts: (0..20)
+            .map(|i| ContactBuf {
+                client_id: KadId::from(i),
+                ip_addr: [1, 2, 3, i as u8].into(),
+                udp_port: 4672,
+                tcp_port: 4662,
+                version: 8,
+            })
+            .collect(),
+    }
+    .write_packed_to(&mut b)
+    .unwrap();
+
+    let p = Packet::from_slice(&b).unwrap();
+    assert!(p.is_packed());
+    let d = dissect(&b);
+    assert_eq!(d["packed"], true);
+    assert_eq!(d["fields"]["contacts"].as_array().unwrap().len(), 20);
+
+    // a packet already decoded by a `DecodeContext` serializes the same
+    let mut ctx = DecodeContext::new();
+    let kind = ctx.kind(&p);
+    assert_eq!(serde_json::to_value(Dissection::new(&p, kind)).unwrap(), d);
+}
+
+#[test]
+fn dissect_hashes() {
+    let mut b = Vec::new();
+    OperationBuf::Firewalled2ReqV1 {
+        tcp_port: 4662,
+        user_hash: hex!("000102030405060708090a0b0c0d0e0f"),
+        connect_options: 1,
+    }
+    .write_to(&mut b)
+    .unwrap();
+
+    // in wire order
+    let d = dissect(&b);
+    assert_eq!(d["fields"]["user_hash"], "000102030405060708090a0b0c0d0e0f");
+}
+
+#[test]
+fn dissect_unknown() {
+    // unhandled opcode
+    let d = dissect(&hex!("e4 ee 0102"));
+    assert_eq!(d["kind"], "kad");
+    assert_eq!(d["operation"], serde_json::Value::Null);
+    assert_eq!(d["unknown"], "0102");
+
+    // too short to parse
+    let d = dissect(&hex!("e4 60 01"));
+    assert_eq!(d["operation"], "Ping");
+    assert_eq!(d["fields"], json!({}));
+    let d = dissect(&hex!("e4 61 01"));
+    assert_eq!(d["operation"], "Pong");
+    assert!(d["error"].is_string());
+    assert_eq!(d["unknown"], "01");
+
+    // obfuscated, or not emule at all
+    let d = dissect(&hex!("42 0102"));
+    assert_eq!(d["kind"], serde_json::Value::Null);
+    assert!(d["error"].is_string());
+    assert_eq!(d["unknown"], "0102");
+}
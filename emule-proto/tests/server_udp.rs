@@ -0,0 +1,285 @@
+use emule_proto::udp_proto::*;
+use hex_literal::hex;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+fn write(op: ServerOperationBuf) -> Vec<u8> {
+    let mut b = Vec::new();
+    op.write_to(&mut b).unwrap();
+    b
+}
+
+fn server_operation_of(v: &[u8], f: impl FnOnce(Option<ServerOperation<'_>>)) {
+    let p = Packet::from_slice(v).unwrap();
+    assert_eq!(p.udp_proto(), Some(UdpProto::Edonkey));
+    let Kind::Server(s) = p.kind().unwrap() else {
+        panic!("expected a server packet");
+    };
+    f(s.operation())
+}
+
+/// Answers requests like an ed2k server would, until it is sent an empty datagram
+fn mock_server() -> (UdpSocket, std::thread::JoinHandle<()>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = socket.try_clone().unwrap();
+    let join = std::thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        loop {
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            if len == 0 {
+                return;
+            }
+
+            let p = Packet::from_slice(&buf[..len]).unwrap();
+            let Kind::Server(s) = p.kind().unwrap() else {
+                panic!("expected a server packet");
+            };
+            let reply = match s.operation().unwrap() {
+                ServerOperation::GlobServStatReq { challenge } => {
+                    ServerOperationBuf::GlobServStatRes(ServStatResBuf {
+                        challenge,
+                        users: 1000,
+                        files: 50_000,
+                        max_users: 5000,
+                        udp_flags: SRV_UDPFLG_EXT_GETSOURCES | SRV_UDPFLG_LARGEFILES,
+                        udp_obfuscation_port: 4665,
+                        tcp_obfuscation_port: 4661,
+                        udp_key: 0xdeadbeef,
+                        ..Default::default()
+                    })
+                }
+                ServerOperation::GlobGetSources(g) => ServerOperationBuf::GlobFoundSources {
+                    entries: g
+                        .file_hashes()
+                        .map(|file_hash| FoundSourcesBuf {
+                            file_hash,
+                            sources: vec![(0x0100007f, 4662), (5, 4663)],
+                        })
+                        .collect(),
+                },
+                ServerOperation::GlobSearchReq(SearchExpr::String(s)) => {
+                    assert_eq!(s, b"foo");
+                    ServerOperationBuf::GlobSearchRes {
+                        results: (1..=2)
+                            .map(|i| ServerSearchResultBuf {
+                                file_hash: [i; 16],
+                                client_id: 0,
+                                client_port: 0,
+                                tags: TagListBuf {
+                                    encoding: TagEncoding::Compact,
+                                    tags: vec![
+                                        TagBuf {
+                                            name: TagId::FileName.name().to_vec(),
+                                            value: TagValueBuf::String(b"foo.mp3".to_vec()),
+                                        },
+                                        TagBuf {
+                                            name: TagId::FileSize.name().to_vec(),
+                                            value: TagValueBuf::uint(i as u64 * 100),
+                                        },
+                                        TagBuf {
+                                            name: TagId::Sources.name().to_vec(),
+                                            value: TagValueBuf::uint(7),
+                                        },
+                                    ],
+                                },
+                            })
+                            .collect(),
+                    }
+                }
+                ServerOperation::ServerDescReq { challenge: None } => {
+                    ServerOperationBuf::ServerDescResV0 {
+                        name: b"mock".to_vec(),
+                        description: b"a mock server".to_vec(),
+                    }
+                }
+                op => panic!("unexpected operation: {:?}", op),
+            };
+
+            server.send_to(&write(reply), from).unwrap();
+        }
+    });
+
+    (socket, join)
+}
+
+#[test]
+fn mock_server_round_trip() {
+    let (server, join) = mock_server();
+    let server_addr = server.local_addr().unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut buf = [0u8; 1500];
+    let mut request = |op: ServerOperationBuf, f: &mut dyn FnMut(Option<ServerOperation<'_>>)| {
+        client.send_to(&write(op), server_addr).unwrap();
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        server_operation_of(&buf[..len], f);
+    };
+
+    let challenge = GLOBSERVSTAT_CHALLENGE | 0x1234;
+    request(
+        ServerOperationBuf::GlobServStatReq { challenge },
+        &mut |op| match op {
+            Some(ServerOperation::GlobServStatRes(r)) => {
+                assert_eq!(r.challenge(), challenge);
+                assert_eq!(r.users(), 1000);
+                assert_eq!(r.files(), 50_000);
+                assert_eq!(r.max_users(), Some(5000));
+                assert_eq!(
+                    r.udp_flags(),
+                    Some(SRV_UDPFLG_EXT_GETSOURCES | SRV_UDPFLG_LARGEFILES)
+                );
+                assert_eq!(r.udp_obfuscation_port(), Some(4665));
+                assert_eq!(r.tcp_obfuscation_port(), Some(4661));
+                assert_eq!(r.udp_key(), Some(0xdeadbeef));
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    request(
+        ServerOperationBuf::GlobGetSources {
+            file_hashes: vec![[1; 16], [2; 16]],
+        },
+        &mut |op| match op {
+            Some(ServerOperation::GlobFoundSources(f)) => {
+                let e: Vec<_> = f
+                    .entries()
+                    .map(|e| (e.file_hash(), e.sources().collect::<Vec<_>>()))
+                    .collect();
+                let sources = vec![(0x0100007f, 4662), (5, 4663)];
+                assert_eq!(e, vec![([1; 16], sources.clone()), ([2; 16], sources)]);
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    let mut expr = Vec::new();
+    SearchExpr::String(b"foo").write_to(&mut expr).unwrap();
+    request(
+        ServerOperationBuf::GlobSearchReq { expr },
+        &mut |op| match op {
+            Some(ServerOperation::GlobSearchRes(r)) => {
+                let results: Vec<_> = r
+                    .results()
+                    .map(|r| (r.file_hash(), r.file_name(), r.file_size(), r.sources()))
+                    .collect();
+                let name = Some(&b"foo.mp3"[..]);
+                assert_eq!(
+                    results,
+                    vec![
+                        ([1; 16], name, Some(100), Some(7)),
+                        ([2; 16], name, Some(200), Some(7))
+                    ]
+                );
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    request(
+        ServerOperationBuf::ServerDescReq { challenge: None },
+        &mut |op| match op {
+            Some(ServerOperation::ServerDescRes(r)) => {
+                assert_eq!(r.challenge(), None);
+                assert_eq!(r.name(), Some(&b"mock"[..]));
+                assert_eq!(r.description(), Some(&b"a mock server"[..]));
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    client.send_to(&[], server_addr).unwrap();
+    join.join().unwrap();
+}
+
+#[test]
+fn parse_serv_stat_res_short() {
+    // an old server, without any of the optional fields
+    let v = hex!("e3 97 3412aa55 0a000000 14000000");
+    server_operation_of(&v, |op| match op {
+        Some(ServerOperation::GlobServStatRes(r)) => {
+            assert_eq!(r.challenge(), 0x55aa1234);
+            assert_eq!(r.users(), 10);
+            assert_eq!(r.files(), 20);
+            assert_eq!(r.max_users(), None);
+            assert_eq!(r.udp_flags(), None);
+            assert_eq!(r.udp_key(), None);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+}
+
+#[test]
+fn parse_server_desc_res_tagged() {
+    let v = hex!(
+        "e3 a3 fff03412 02000000"
+        "02 0100 01 0400 6d6f636b"
+        "02 0100 0b 0300 616263"
+    );
+    server_operation_of(&v, |op| match op {
+        Some(ServerOperation::ServerDescRes(r)) => {
+            assert_eq!(
+                r.challenge(),
+                Some(0x1234 << 16 | SERVER_DESC_CHALLENGE as u32)
+            );
+            assert_eq!(r.name(), Some(&b"mock"[..]));
+            assert_eq!(r.description(), Some(&b"abc"[..]));
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+}
+
+#[test]
+fn get_sources2_round_trip() {
+    let files = vec![([1; 16], 1000), ([2; 16], 5 << 32)];
+    let v = write(ServerOperationBuf::GlobGetSources2 {
+        files: files.clone(),
+    });
+    server_operation_of(&v, |op| match op {
+        Some(ServerOperation::GlobGetSources2(g)) => {
+            assert_eq!(g.entries().collect::<Vec<_>>(), files);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    // the large file size of the second entry is cut short
+    assert!(matches!(
+        GetSources2::from_slice(&v[2..(v.len() - 3)]),
+        Err(Error::ServerOpTooShort {
+            offset: 20,
+            have: 25,
+            need: 28
+        })
+    ));
+}
+
+#[test]
+fn search_expr_round_trip() {
+    let expr = SearchExpr::And(
+        Box::new(SearchExpr::String(b"foo bar")),
+        Box::new(SearchExpr::And(
+            Box::new(SearchExpr::MetaTag {
+                name: TagId::FileType.name(),
+                value: b"Audio",
+            }),
+            Box::new(SearchExpr::Numeric {
+                name: TagId::FileSize.name(),
+                op: NumericOp::Gt,
+                value: 5 << 32,
+            }),
+        )),
+    );
+    let v = write(ServerOperationBuf::GlobSearchReq2 {
+        expr: {
+            let mut b = Vec::new();
+            expr.write_to(&mut b).unwrap();
+            b
+        },
+    });
+    server_operation_of(&v, |op| match op {
+        Some(ServerOperation::GlobSearchReq2(e)) => assert_eq!(e, expr),
+        o => panic!("unexpected operation: {:?}", o),
+    });
+}
@@ -1,12 +1,1440 @@
 use hex_literal::hex;
-use remule::udp_proto::*;
+use emule_proto::obfuscate::{udp_verify_key, KadUdpKey, KeyKind, SendKeys};
+use emule_proto::udp_proto::*;
+use emule_proto::KadId;
 
 #[test]
 fn tag_basic() {
     let v = [ TagType::Uint8 as u8, 1, 0, b'a', 5, 0xff, 0xee ];
     let a = Tag::from_slice(&v).unwrap();
-    let b = (TagBuf { name: vec![b'a'], value: TagValueBuf::Uint8(5)}, &[0xff as u8, 0xee][..]);
+    let b = (TagBuf { name: vec![b'a'], value: TagValueBuf::Uint8(5)}, &[0xff_u8, 0xee][..]);
     assert_eq!(a.0, b.0);
     assert_eq!(a.1, b.1);
 }
 
+#[test]
+fn tag_types() {
+    let v = hex!(
+        "09"
+        "05 0100 61 01"
+        "06 0100 62 0a00 ff02"
+        "07 0100 63 03000000 010203"
+        "0a 0100 64 02 0405"
+        "13 0100 65 666f6f"
+        "20 0100 66 30313233343536373839616263646566"
+        "89 15 07"
+        "93 01 626172"
+        "02 0100 67 0300 62617a"
+    );
+    let (tags, rem) = TagList::from_slice(&v).unwrap();
+    assert!(rem.is_empty());
+    let tags: Vec<_> = tags.iter().map(Result::unwrap).collect();
+    let values: Vec<_> = tags.iter().map(|t| (t.name(), t.value())).collect();
+    assert_eq!(
+        values,
+        vec![
+            (&b"a"[..], TagValue::Bool(true)),
+            (
+                b"b",
+                TagValue::BoolArray {
+                    len: 10,
+                    bits: &[0xff, 0x02]
+                }
+            ),
+            (b"c", TagValue::Blob(&[1, 2, 3])),
+            (b"d", TagValue::Bsob(&[4, 5])),
+            (b"e", TagValue::String_(b"foo")),
+            (b"f", TagValue::String_(b"0123456789abcdef")),
+            (TagId::Sources.name(), TagValue::Uint8(7)),
+            (TagId::FileName.name(), TagValue::String_(b"bar")),
+            (b"g", TagValue::String_(b"baz")),
+        ]
+    );
+    assert_eq!(tags[4].tag_type(), TagType::Str3);
+    assert!(tags[6].has_compact_name());
+    assert!(!tags[8].has_compact_name());
+
+    // unknown types, and truncated content, are errors rather than panics
+    assert!(Tag::from_slice(&hex!("0c 0100 61 00")).is_err());
+    assert!(Tag::from_slice(&hex!("87 01 ffffffff")).is_err());
+    assert!(Tag::from_slice(&hex!("20 0100 61 3031")).is_err());
+}
+
+#[test]
+fn tag_list_round_trip() {
+    // compact tags, as sent by ed2k servers & clients
+    let v = hex!(
+        "05"
+        "89 15 07"
+        "93 01 626172"
+        "94 02 62617a21"
+        "11 0200 6162 78"
+        "84 66 0000803f"
+    );
+    let (tags, _) = TagList::from_slice(&v).unwrap();
+    let b = TagListBuf::from(&tags);
+    assert_eq!(b.encoding, TagEncoding::Compact);
+    assert_eq!(b.tags[4].value, TagValueBuf::Float32(1.0));
+    let mut out = Vec::new();
+    b.write_to(&mut out).unwrap();
+    assert_eq!(out, v);
+
+    // kad never uses compact tags
+    let v = hex!(
+        "04"
+        "02 0100 01 0300 626172"
+        "01 0100 62 000102030405060708090a0b0c0d0e0f"
+        "07 0100 63 02000000 0102"
+        "06 0100 64 0300 05"
+    );
+    let (tags, _) = TagList::from_slice(&v).unwrap();
+    let b = TagListBuf::from(&tags);
+    assert_eq!(b.encoding, TagEncoding::Standard);
+    for (a, b) in tags.iter().zip(&b.tags) {
+        assert_eq!(a.unwrap(), *b);
+    }
+    let mut out = Vec::new();
+    b.write_to(&mut out).unwrap();
+    assert_eq!(out, v);
+
+    // the same tags, written compactly
+    let b = TagListBuf {
+        encoding: TagEncoding::Compact,
+        ..b
+    };
+    let mut out = Vec::new();
+    b.write32_to(&mut out).unwrap();
+    assert_eq!(
+        out,
+        hex!(
+            "04000000"
+            "93 01 626172"
+            "81 62 000102030405060708090a0b0c0d0e0f"
+            "87 63 02000000 0102"
+            "86 64 0300 05"
+        )
+    );
+    let (tags, rem) = TagList32::from_slice(&out).unwrap();
+    assert!(rem.is_empty());
+    assert_eq!(TagListBuf::from(&tags), b);
+}
+
+#[test]
+fn tag_ids() {
+    assert_eq!(TagId::from_name(b"\x02"), Some(TagId::FileSize));
+    assert_eq!(TagId::from_name(b"Artist"), Some(TagId::MediaArtist));
+    assert_eq!(TagId::from_name(b"\x06"), None);
+    assert_eq!(TagId::from_name(b"artist"), None);
+    assert_eq!(TagId::FileSizeHi.name(), b"\x3A");
+    assert_eq!(TagId::MediaCodec.ed2k_name(), Some(&b"codec"[..]));
+    assert_eq!(u8::from(TagId::SourceType), 0xFF);
+
+    let v = hex!(
+        "03"
+        "02 0100 01 0300 626172"
+        "03 0100 02 00000001"
+        "02 0600 417274697374 0300 666f6f"
+    );
+    let (tags, _) = TagList::from_slice(&v).unwrap();
+    assert_eq!(tags.get(TagId::FileName), Some(TagValue::String_(b"bar")));
+    assert_eq!(tags.get(TagId::FileSize), Some(TagValue::Uint32(1 << 24)));
+    assert_eq!(
+        tags.get(TagId::MediaArtist),
+        Some(TagValue::String_(b"foo"))
+    );
+    assert_eq!(tags.get(TagId::Sources), None);
+    assert_eq!(
+        TagBuf::with_id(TagId::FileName, TagValueBuf::String(b"bar".to_vec())),
+        tags.iter().next().unwrap().unwrap()
+    );
+}
+
+fn test_keys(source_key: Option<u32>) -> Keys<'static> {
+    const USER_HASH: [u8; 16] = hex!("a0a1a2a3a4a5a6a7a8a9aaabacadaeaf");
+    Keys {
+        kad_id: KadId::from_wire(hex!("000102030405060708090a0b0c0d0e0f")),
+        user_hash: &USER_HASH,
+        source_ip: "192.168.1.2".parse().unwrap(),
+        source_key,
+    }
+}
+
+#[test]
+fn decrypt_kad_id() {
+    let v = hex!("5c123423e7db85107c8758eb77fa6dc06fb7");
+    let mut p = Packet::from_slice(&v).unwrap();
+    let d = p.decrypt(&test_keys(None)).unwrap().unwrap();
+    assert_eq!(d.key, KeyKind::KadId);
+    assert_eq!(d.verify_keys, Some((0x11223344, 0x55667788)));
+    assert_eq!(p.udp_proto(), Some(UdpProto::KademliaHeader));
+    match p.kind().unwrap() {
+        Kind::Kad(k) => assert_eq!(k.opcode(), Some(KadOpCode::BootstrapReq)),
+        k => panic!("unexpected kind: {:?}", k),
+    }
+}
+
+#[test]
+fn decrypt_user_hash() {
+    let v = hex!("1112343650b24cfc1446aece");
+    let mut p = Packet::from_slice(&v).unwrap();
+    let d = p.decrypt(&test_keys(None)).unwrap().unwrap();
+    assert_eq!(d.key, KeyKind::UserHash);
+    assert_eq!(d.verify_keys, None);
+    assert_eq!(p.udp_proto(), Some(UdpProto::KademliaHeader));
+
+    // emule doesn't avoid the ed2k protocol byte as a user hash marker
+    let v = hex!("e312343650b24cfc1446aece");
+    let mut p = Packet::from_slice(&v).unwrap();
+    let d = p.decrypt(&test_keys(None)).unwrap().unwrap();
+    assert_eq!(d.key, KeyKind::UserHash);
+    assert_eq!(p.udp_proto(), Some(UdpProto::KademliaHeader));
+
+    // but a plain ed2k packet is left alone
+    let v = hex!("e3 96 01020304");
+    let mut p = Packet::from_slice(&v).unwrap();
+    assert_eq!(p.decrypt(&test_keys(None)).unwrap(), None);
+    assert_eq!(p.udp_proto(), Some(UdpProto::Edonkey));
+}
+
+#[test]
+fn decrypt_verify_key() {
+    let v = hex!("16123424e4214b94dab38981fe23eba87a59");
+    let mut p = Packet::from_slice(&v).unwrap();
+    assert!(matches!(
+        p.decrypt(&test_keys(None)),
+        Err(Error::ObfuscatedNoKeyMatched)
+    ));
+
+    let d = p.decrypt(&test_keys(Some(0xdeadbeef))).unwrap().unwrap();
+    assert_eq!(d.key, KeyKind::VerifyKey);
+    assert_eq!(d.verify_keys, Some((0xdeadbeef, 0x01020304)));
+    assert_eq!(p.udp_proto(), Some(UdpProto::KademliaHeader));
+}
+
+#[test]
+fn decrypt_plain() {
+    let v = [UdpProto::KademliaHeader as u8, KadOpCode::BootstrapReq as u8];
+    let mut p = Packet::from_slice(&v).unwrap();
+    assert_eq!(p.decrypt(&test_keys(None)).unwrap(), None);
+}
+
+#[test]
+fn obfuscate_round_trip() {
+    let keys = test_keys(Some(0xdeadbeef));
+    let mut rng = rand::rng();
+
+    let send_kad_id = SendKeys {
+        target_kad_id: Some(keys.kad_id),
+        receiver_verify_key: 0,
+        sender_verify_key: 0x01020304,
+    };
+    // a zero kad id is treated as unknown
+    let send_verify_key = SendKeys {
+        target_kad_id: Some(KadId::default()),
+        receiver_verify_key: 0xdeadbeef,
+        sender_verify_key: 0x01020304,
+    };
+
+    for (send, key, max_padding) in [
+        (send_kad_id, KeyKind::KadId, 0),
+        (send_kad_id, KeyKind::KadId, 16),
+        (send_verify_key, KeyKind::VerifyKey, 0),
+        (send_verify_key, KeyKind::VerifyKey, 255),
+    ] {
+        let mut b = Vec::new();
+        OperationBuf::BootstrapReq
+            .write_obfuscated_to(&mut b, &send, max_padding, &mut rng)
+            .unwrap();
+        assert!(b.len() <= 2 + 8 + 8 + max_padding as usize);
+
+        let mut p = Packet::from_slice(&b).unwrap();
+        assert_eq!(p.udp_proto(), None);
+        let d = p.decrypt(&keys).unwrap().unwrap();
+        assert_eq!(d.key, key);
+        assert_eq!(
+            d.verify_keys,
+            Some((send.receiver_verify_key, send.sender_verify_key))
+        );
+        match p.kind().unwrap() {
+            Kind::Kad(k) => assert_eq!(k.opcode(), Some(KadOpCode::BootstrapReq)),
+            k => panic!("unexpected kind: {:?}", k),
+        }
+    }
+}
+
+#[test]
+fn verify_keys() {
+    let private_key = 0x12345678;
+    let ip = "192.168.1.2".parse().unwrap();
+    assert_eq!(udp_verify_key(private_key, ip), 0x58aac540);
+    assert_eq!(
+        udp_verify_key(private_key, "192.168.1.3".parse().unwrap()),
+        0x46e4c8a5
+    );
+    assert_eq!(udp_verify_key(0, "0.0.0.0".parse().unwrap()), 0x0d05f875);
+
+    // a node obfuscating with the key we handed it
+    let send = SendKeys {
+        target_kad_id: None,
+        receiver_verify_key: udp_verify_key(private_key, ip),
+        sender_verify_key: 0x01020304,
+    };
+    let mut b = Vec::new();
+    OperationBuf::BootstrapReq
+        .write_obfuscated_to(&mut b, &send, 0, &mut rand::rng())
+        .unwrap();
+    let mut p = Packet::from_slice(&b).unwrap();
+    let d = p
+        .decrypt(&test_keys(Some(udp_verify_key(private_key, ip))))
+        .unwrap()
+        .unwrap();
+    assert_eq!(d.key, KeyKind::VerifyKey);
+    assert!(d.is_receiver_key_valid(private_key, ip));
+    assert!(!d.is_receiver_key_valid(private_key + 1, ip));
+
+    let public_ip = "1.2.3.4".parse().unwrap();
+    let key = d.sender_key(public_ip).unwrap();
+    assert_eq!(
+        key,
+        KadUdpKey {
+            key: 0x01020304,
+            ip: public_ip
+        }
+    );
+    assert_eq!(key.key_for(public_ip), 0x01020304);
+    assert_eq!(key.key_for("1.2.3.5".parse().unwrap()), 0);
+    assert_eq!(KadUdpKey::from_raw(key.to_raw()), key);
+    assert_eq!(key.to_raw(), (0x01020304, 0x04030201));
+}
+
+/// A kad id, as emule displays it
+fn id(s: &str) -> KadId {
+    s.parse().unwrap()
+}
+
+fn write(op: OperationBuf) -> Vec<u8> {
+    let mut b = Vec::new();
+    op.write_to(&mut b).unwrap();
+    b
+}
+
+#[test]
+fn write_pong() {
+    assert_eq!(
+        write(OperationBuf::Pong { recv_port: 4672 }),
+        hex!("e4 61 4012")
+    );
+}
+
+#[test]
+fn write_hello() {
+    let details = || Details {
+        src_kad_id: id("03020100070605040b0a09080f0e0d0c"),
+        src_port: 4662,
+        kad_version: 9,
+        src_port_internal: None,
+        udp_firewalled: None,
+        tcp_firewalled: None,
+        req_ack: None,
+    };
+
+    assert_eq!(
+        write(OperationBuf::HelloRes(details())),
+        hex!("e4 19 000102030405060708090a0b0c0d0e0f 3612 09 00")
+    );
+
+    assert_eq!(
+        write(OperationBuf::HelloReq(Details {
+            src_port_internal: Some(4672),
+            tcp_firewalled: Some(true),
+            req_ack: Some(true),
+            ..details()
+        })),
+        hex!(
+            "e4 11 000102030405060708090a0b0c0d0e0f 3612 09 02"
+            "08 0100 fc 4012"
+            "09 0100 f2 06"
+        )
+    );
+
+    // like emule, the port is always a Uint16 tag
+    assert_eq!(
+        write(OperationBuf::HelloReq(Details {
+            src_port_internal: Some(80),
+            ..details()
+        })),
+        hex!(
+            "e4 11 000102030405060708090a0b0c0d0e0f 3612 09 01"
+            "08 0100 fc 5000"
+        )
+    );
+}
+
+#[test]
+fn write_publish_source_req() {
+    assert_eq!(
+        write(OperationBuf::PublishSourceReq {
+            target_id: KadId::from(1),
+            contact_id: KadId::from(2),
+            tags: vec![TagBuf {
+                name: vec![0xff],
+                value: TagValueBuf::uint(1),
+            }],
+        }),
+        hex!(
+            "e4 44 00000000000000000000000001000000 00000000000000000000000002000000"
+            "01 09 0100 ff 01"
+        )
+    );
+}
+
+#[test]
+fn write_find_buddy_req() {
+    assert_eq!(
+        write(OperationBuf::FindBuddyReqV1 {
+            buddy_id: KadId::from(1),
+            src_client_hash: [2; 16],
+            src_client_port: 4662,
+            connect_options: Some(0x03),
+        }),
+        hex!("e4 51 00000000000000000000000001000000 02020202020202020202020202020202 3612 03")
+    );
+}
+
+fn contacts(n: u32) -> Vec<ContactBuf> {
+    (0..n)
+        .map(|i| ContactBuf {
+            client_id: KadId::from(i as u128),
+            ip_addr: [10, 0, 0, i as u8].into(),
+            udp_port: 4672,
+            tcp_port: 4662,
+            version: 8,
+        })
+        .collect()
+}
+
+fn random_contacts(n: u32) -> Vec<ContactBuf> {
+    // xorshift, so the content doesn't compress
+    let mut x = 0x1234_5678u32;
+    let mut next = move || {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        x
+    };
+    (0..n)
+        .map(|_| ContactBuf {
+            client_id: KadId::from(
+                (next() as u128) << 96
+                    | (next() as u128) << 64
+                    | (next() as u128) << 32
+                    | next() as u128,
+            ),
+            ip_addr: next().into(),
+            udp_port: next() as u16,
+            tcp_port: next() as u16,
+            version: next() as u8,
+        })
+        .collect()
+}
+
+#[test]
+fn write_packed() {
+    // too small to bother
+    let mut b = Vec::new();
+    OperationBuf::BootstrapReq.write_packed_to(&mut b).unwrap();
+    assert_eq!(b, hex!("e4 01"));
+
+    // compressible
+    let op = OperationBuf::BootstrapResp {
+        client_id: KadId::from(5),
+        client_port: 4672,
+        client_version: 9,
+        contacts: contacts(20),
+    };
+    let mut plain = Vec::new();
+    op.write_to(&mut plain).unwrap();
+    let mut b = Vec::new();
+    op.write_packed_to(&mut b).unwrap();
+    assert!(b.len() < plain.len());
+
+    let p = Packet::from_slice(&b).unwrap();
+    assert!(p.is_packed());
+    match p.kind().unwrap() {
+        Kind::Kad(k) => match k.operation() {
+            Some(Operation::BootstrapResp(r)) => {
+                assert_eq!(r.client_id(), KadId::from(5));
+                assert_eq!(r.client_port(), 4672);
+                assert_eq!(r.client_version(), 9);
+                let c: Vec<_> = r.contacts().unwrap().map(|c| c.client_id()).collect();
+                assert_eq!(c, (0..20).map(KadId::from).collect::<Vec<_>>());
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+        k => panic!("unexpected kind: {:?}", k),
+    }
+
+    // incompressible
+    let op = OperationBuf::Res {
+        target: KadId::from(1),
+        contacts: random_contacts(10),
+    };
+    let mut plain = Vec::new();
+    op.write_to(&mut plain).unwrap();
+    assert!(plain.len() - 2 > KAD_PACK_THRESHOLD);
+    let mut b = Vec::new();
+    op.write_packed_to(&mut b).unwrap();
+    assert_eq!(b, plain);
+}
+
+#[test]
+fn decode_limits() {
+    let mut b = Vec::new();
+    OperationBuf::BootstrapResp {
+        client_id: KadId::from(5),
+        client_port: 4672,
+        client_version: 9,
+        contacts: contacts(20),
+    }
+    .write_packed_to(&mut b)
+    .unwrap();
+    let limits = DecodeLimits {
+        max_decompressed_len: 100,
+        ..Default::default()
+    };
+    let p = Packet::from_slice(&b).unwrap().with_limits(limits);
+    let e = p.kind().unwrap_err();
+    assert!(matches!(e, Error::DecompressedTooLarge { max: 100, .. }));
+    assert!(e.is_limit_exceeded());
+    assert_eq!(e.variant_name(), "DecompressedTooLarge");
+
+    let unlimited = DecodeLimits {
+        max_decompressed_len: usize::MAX,
+        ..Default::default()
+    };
+    let p = Packet::from_slice(&b).unwrap().with_limits(unlimited);
+    assert!(matches!(p.kind(), Ok(Kind::Kad(_))));
+    assert!(matches!(DecodeContext::new().kind(&p), Ok(Kind::Kad(_))));
+
+    let b = write(OperationBuf::PublishSourceReq {
+        target_id: KadId::from(1),
+        contact_id: KadId::from(2),
+        tags: vec![
+            TagBuf::with_id(TagId::FileName, TagValueBuf::String(vec![b'a'; 10])),
+            TagBuf::with_id(TagId::FileSize, TagValueBuf::uint(1)),
+            TagBuf::with_id(TagId::Sources, TagValueBuf::uint(2)),
+        ],
+    });
+    let try_operation = |limits| {
+        let p = Packet::from_slice(&b).unwrap().with_limits(limits);
+        let Kind::Kad(k) = p.kind().unwrap() else {
+            panic!("expected a kad packet");
+        };
+        k.try_operation().map(|op| op.is_some())
+    };
+    assert!(matches!(try_operation(DecodeLimits::default()), Ok(true)));
+    assert!(matches!(
+        try_operation(DecodeLimits {
+            max_tags: 2,
+            ..Default::default()
+        }),
+        Err(Error::TooManyTags {
+            offset: 32,
+            count: 3,
+            max: 2
+        })
+    ));
+    assert!(matches!(
+        try_operation(DecodeLimits {
+            max_string_len: 9,
+            ..Default::default()
+        }),
+        Err(Error::StringTooLong {
+            offset: 39,
+            len: 10,
+            max: 9
+        })
+    ));
+}
+
+#[test]
+fn decode_context() {
+    let packed = |n| {
+        let mut b = Vec::new();
+        OperationBuf::BootstrapResp {
+            client_id: KadId::from(n as u128),
+            client_port: 4672,
+            client_version: 9,
+            contacts: contacts(n),
+        }
+        .write_packed_to(&mut b)
+        .unwrap();
+        b
+    };
+    let client_id = |kind: Kind<'_>| match kind {
+        Kind::Kad(k) => match k.operation() {
+            Some(Operation::BootstrapResp(r)) => r.client_id(),
+            o => panic!("unexpected operation: {:?}", o),
+        },
+        k => panic!("unexpected kind: {:?}", k),
+    };
+
+    let mut ctx = DecodeContext::new();
+    for b in [packed(20), packed(10), packed(30)] {
+        let p = Packet::from_slice(&b).unwrap();
+        assert!(p.is_packed());
+        assert_eq!(
+            client_id(ctx.kind(&p).unwrap()),
+            client_id(p.kind().unwrap())
+        );
+    }
+
+    // not packed, passed through
+    let b = write(OperationBuf::Ping);
+    let p = Packet::from_slice(&b).unwrap();
+    assert!(matches!(ctx.kind(&p), Ok(Kind::Kad(_))));
+
+    // a truncated stream is an error, and doesn't upset the next packet
+    let b = packed(20);
+    let p = Packet::from_slice(&b[..(b.len() - 10)]).unwrap();
+    assert!(matches!(
+        ctx.kind(&p),
+        Err(Error::KadPackedDecompress { .. })
+    ));
+    let p = Packet::from_slice(&b).unwrap();
+    assert_eq!(client_id(ctx.kind(&p).unwrap()), KadId::from(20));
+}
+
+#[test]
+fn parse_hello() {
+    let b = write(OperationBuf::HelloReq(Details {
+        src_kad_id: id("03020100070605040b0a09080f0e0d0c"),
+        src_port: 4662,
+        kad_version: 9,
+        src_port_internal: Some(4672),
+        udp_firewalled: Some(true),
+        tcp_firewalled: None,
+        req_ack: Some(true),
+    }));
+
+    let p = Packet::from_slice(&b).unwrap();
+    let Kind::Kad(k) = p.kind().unwrap() else {
+        panic!("expected a kad packet");
+    };
+    match k.operation() {
+        Some(Operation::HelloReq(h)) => {
+            assert_eq!(h.client_id(), id("03020100070605040b0a09080f0e0d0c"));
+            assert_eq!(h.tcp_port(), 4662);
+            assert_eq!(h.version(), 9);
+            assert_eq!(h.tags().count(), 2);
+            assert_eq!(h.source_udp_port(), Some(4672));
+            assert_eq!(
+                h.misc_options(),
+                Some(MiscOptions {
+                    udp_firewalled: true,
+                    tcp_firewalled: false,
+                    req_ack: true,
+                })
+            );
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    }
+
+    // no tags
+    let v = hex!("e4 19 000102030405060708090a0b0c0d0e0f 3612 08 00");
+    let p = Packet::from_slice(&v).unwrap();
+    let Kind::Kad(k) = p.kind().unwrap() else {
+        panic!("expected a kad packet");
+    };
+    match k.operation() {
+        Some(Operation::HelloRes(h)) => {
+            assert_eq!(h.version(), 8);
+            assert_eq!(h.source_udp_port(), None);
+            assert_eq!(h.misc_options(), None);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    }
+
+    // truncated tag list
+    assert!(Hello::from_slice(&hex!("000102030405060708090a0b0c0d0e0f 3612 08 01")).is_err());
+}
+
+#[test]
+fn parse_hello_res_ack() {
+    let v = hex!("e4 22 000102030405060708090a0b0c0d0e0f 00");
+    let p = Packet::from_slice(&v).unwrap();
+    let Kind::Kad(k) = p.kind().unwrap() else {
+        panic!("expected a kad packet");
+    };
+    match k.operation() {
+        Some(Operation::HelloResAck(h)) => {
+            assert_eq!(h.client_id(), id("03020100070605040b0a09080f0e0d0c"));
+            assert_eq!(h.tags().count(), 0);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    }
+}
+
+fn operation_of(v: &[u8], f: impl FnOnce(Option<Operation<'_>>)) {
+    let p = Packet::from_slice(v).unwrap();
+    let Kind::Kad(k) = p.kind().unwrap() else {
+        panic!("expected a kad packet");
+    };
+    f(k.operation())
+}
+
+#[test]
+fn parse_search_key_req() {
+    let v = hex!(
+        "e4 33 000102030405060708090a0b0c0d0e0f 0580"
+        "00 00"
+        "01 0700 666f6f20626172"
+        "00 02"
+        "02 0300 6d7033 0100 03"
+        "03 e8030000 03 0100 02"
+    );
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchKeyReq(r)) => {
+            assert_eq!(r.target(), id("03020100070605040b0a09080f0e0d0c"));
+            assert_eq!(r.start_position(), 5);
+            assert!(r.is_restrictive());
+            assert_eq!(
+                r.expr(),
+                Some(SearchExpr::And(
+                    Box::new(SearchExpr::String(b"foo bar")),
+                    Box::new(SearchExpr::AndNot(
+                        Box::new(SearchExpr::MetaTag {
+                            name: b"\x03",
+                            value: b"mp3"
+                        }),
+                        Box::new(SearchExpr::Numeric {
+                            name: b"\x02",
+                            op: NumericOp::Ge,
+                            value: 1000
+                        }),
+                    )),
+                ))
+            );
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    // no expression
+    let v = hex!("e4 33 000102030405060708090a0b0c0d0e0f 0000");
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchKeyReq(r)) => {
+            assert!(!r.is_restrictive());
+            assert_eq!(r.expr(), None);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    // 64 bit numeric
+    assert_eq!(
+        SearchExpr::from_slice(&hex!("08 0000000001000000 05 0100 02 ff")).unwrap(),
+        (
+            SearchExpr::Numeric {
+                name: b"\x02",
+                op: NumericOp::Ne,
+                value: 1 << 32
+            },
+            &[0xff_u8][..]
+        )
+    );
+
+    // truncated & invalid expressions
+    for v in [
+        &hex!("00 00 01 0100 61")[..],
+        &hex!("00 03 01 0100 61 01 0100 61")[..],
+        &hex!("01 0500 61")[..],
+        &hex!("03 e8030000 06 0100 02")[..],
+        &hex!("04")[..],
+    ] {
+        assert!(SearchExpr::from_slice(v).is_err(), "{:x?}", v);
+    }
+    assert!(matches!(
+        SearchExpr::from_slice(&hex!("00 00 01 0100 61 05")),
+        Err(Error::SearchExprInvalidOp {
+            offset: 6,
+            value: 5
+        })
+    ));
+    assert!(matches!(
+        SearchExpr::from_slice(&hex!("03 e8030000 06 0100 02")),
+        Err(Error::SearchExprInvalidNumericOp {
+            offset: 5,
+            value: 6
+        })
+    ));
+
+    // `ands` nested ands put the deepest leaf at level `ands + 1`
+    let nested = |ands: usize| {
+        let mut v = vec![0x00; 2 * ands];
+        v.extend_from_slice(&hex!("01 0100 61").repeat(ands + 1));
+        v
+    };
+    assert!(SearchExpr::from_slice(&nested(SEARCH_EXPR_MAX_DEPTH - 1)).is_ok());
+    assert!(matches!(
+        SearchExpr::from_slice(&nested(SEARCH_EXPR_MAX_DEPTH)),
+        Err(Error::SearchExprTooDeep { offset, .. }) if offset == 2 * SEARCH_EXPR_MAX_DEPTH
+    ));
+}
+
+#[test]
+fn parse_search_source_req() {
+    let v = hex!("e4 34 000102030405060708090a0b0c0d0e0f 0300 0010000001000000");
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchSourceReq(r)) => {
+            assert_eq!(r.target(), id("03020100070605040b0a09080f0e0d0c"));
+            assert_eq!(r.start_position(), 3);
+            assert_eq!(r.file_size(), 0x1_0000_1000);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+}
+
+#[test]
+fn parse_search_notes_req() {
+    let v = hex!("e4 35 000102030405060708090a0b0c0d0e0f 0010000000000000");
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchNotesReq(r)) => {
+            assert_eq!(r.target(), id("03020100070605040b0a09080f0e0d0c"));
+            assert_eq!(r.file_size(), 0x1000);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    assert!(SearchNotesReq::from_slice(&hex!("000102030405060708090a0b0c0d0e0f 00")).is_err());
+}
+
+#[test]
+fn parse_search_res() {
+    let mut v = hex!(
+        "e4 3b 000102030405060708090a0b0c0d0e0f 101112131415161718191a1b1c1d1e1f 0200"
+        // keyword result
+        "202122232425262728292a2b2c2d2e2f 06"
+        "02 0100 01 0700 666f6f2e6d7033"
+        "03 0100 02 00100000"
+        "03 0100 3a 01000000"
+        "09 0100 15 07"
+        "0a 0100 37 2b 02"
+        "01 aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+        "05 bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"
+        "09 0200 d0d1 05"
+        // source result
+        "303132333435363738393a3b3c3d3e3f 06"
+        "09 0100 ff 03"
+        "03 0100 fe 04030201"
+        "08 0100 fd 3612"
+        "03 0100 fb 08070605"
+        "08 0100 fa 4012"
+        "02 0100 f8 2000"
+    )
+    .to_vec();
+    v.extend_from_slice(b"000102030405060708090a0b0c0d0e0f");
+
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchRes(r)) => {
+            assert_eq!(r.source_id(), id("03020100070605040b0a09080f0e0d0c"));
+            assert_eq!(r.target_id(), id("13121110171615141b1a19181f1e1d1c"));
+            let (results, rem) = r.results().unwrap();
+            assert!(rem.is_empty());
+            let results: Vec<_> = results.collect();
+            assert_eq!(results.len(), 2);
+
+            let k = &results[0];
+            assert_eq!(k.id(), id("23222120272625242b2a29282f2e2d2c"));
+            assert_eq!(k.file_name(), Some(&b"foo.mp3"[..]));
+            assert_eq!(k.file_size(), Some(0x1_0000_1000));
+            assert_eq!(k.file_type(), None);
+            assert_eq!(k.sources(), Some(7));
+            assert_eq!(k.aich_hash(), Some(&[0xbb; 20][..]));
+            assert_eq!(k.source_ip(), None);
+            let unknown: Vec<_> = k.unknown_tags().collect();
+            assert_eq!(unknown.len(), 1);
+            assert_eq!(unknown[0].name(), b"\xd0\xd1");
+            assert_eq!(unknown[0].value(), TagValue::Uint8(5));
+
+            let s = &results[1];
+            assert_eq!(s.file_name(), None);
+            assert_eq!(s.source_type(), Some(3));
+            assert_eq!(s.source_ip(), Some([1, 2, 3, 4].into()));
+            assert_eq!(s.source_port(), Some(4662));
+            assert_eq!(s.source_udp_port(), None);
+            assert_eq!(s.buddy_ip(), Some([5, 6, 7, 8].into()));
+            assert_eq!(s.buddy_port(), Some(4672));
+            assert_eq!(
+                s.buddy_hash(),
+                Some(hex!("000102030405060708090a0b0c0d0e0f"))
+            );
+            assert_eq!(s.unknown_tags().count(), 0);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    // result count larger than the results present
+    let v = hex!("000102030405060708090a0b0c0d0e0f 101112131415161718191a1b1c1d1e1f 0100");
+    assert!(SearchRes::from_slice(&v).is_err());
+}
+
+#[test]
+fn publish_key_req_round_trip() {
+    let b = write(OperationBuf::PublishKeyReq {
+        target_id: KadId::from(1),
+        entries: vec![
+            PublishEntryBuf {
+                file_id: KadId::from(2),
+                tags: vec![TagBuf {
+                    name: TagId::FileSize.name().to_vec(),
+                    value: TagValueBuf::uint(0x1000),
+                }],
+            },
+            PublishEntryBuf {
+                file_id: KadId::from(3),
+                tags: vec![],
+            },
+        ],
+    });
+    assert_eq!(
+        b,
+        hex!(
+            "e4 43 00000000000000000000000001000000 0200"
+            "00000000000000000000000002000000 01 08 0100 02 0010"
+            "00000000000000000000000003000000 00"
+        )
+    );
+
+    operation_of(&b, |op| match op {
+        Some(Operation::PublishKeyReq(r)) => {
+            assert_eq!(r.target_id(), KadId::from(1));
+            assert_eq!(r.entry_ct(), 2);
+            let e: Vec<_> = r.entries().collect();
+            assert_eq!(e.len(), 2);
+            assert_eq!(e[0].file_id(), KadId::from(2));
+            assert_eq!(
+                e[0].tags().find(TagId::FileSize.name()).unwrap().value(),
+                TagValue::Uint16(0x1000)
+            );
+            assert_eq!(e[1].file_id(), KadId::from(3));
+            assert_eq!(e[1].tags().count(), 0);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    // entry count larger than the entries present
+    assert!(PublishKeyReq::from_slice(&b[2..(b.len() - 17)]).is_err());
+}
+
+#[test]
+fn publish_req_round_trip() {
+    let tags = || {
+        vec![TagBuf {
+            name: TagId::SourceType.name().to_vec(),
+            value: TagValueBuf::uint(1),
+        }]
+    };
+
+    let b = write(OperationBuf::PublishNotesReq {
+        target_id: KadId::from(1),
+        contact_id: KadId::from(2),
+        tags: tags(),
+    });
+    assert_eq!(
+        b,
+        hex!(
+            "e4 45 00000000000000000000000001000000 00000000000000000000000002000000"
+            "01 09 0100 ff 01"
+        )
+    );
+
+    for (b, is_source) in [
+        (b, false),
+        (
+            write(OperationBuf::PublishSourceReq {
+                target_id: KadId::from(1),
+                contact_id: KadId::from(2),
+                tags: tags(),
+            }),
+            true,
+        ),
+    ] {
+        operation_of(&b, |op| {
+            let r = match op {
+                Some(Operation::PublishSourceReq(r)) if is_source => r,
+                Some(Operation::PublishNotesReq(r)) if !is_source => r,
+                o => panic!("unexpected operation: {:?}", o),
+            };
+            assert_eq!(r.target_id(), KadId::from(1));
+            assert_eq!(r.contact_id(), KadId::from(2));
+            let t: Vec<_> = r.tags().iter().map(Result::unwrap).collect();
+            assert_eq!(t, tags());
+        });
+    }
+}
+
+#[test]
+fn publish_res_round_trip() {
+    let b = write(OperationBuf::PublishRes {
+        target_id: KadId::from(1),
+        load: 42,
+    });
+    assert_eq!(b, hex!("e4 4b 00000000000000000000000001000000 2a"));
+    operation_of(&b, |op| match op {
+        Some(Operation::PublishRes(r)) => {
+            assert_eq!(r.target_id(), KadId::from(1));
+            assert_eq!(r.load(), Some(42));
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let b = write(OperationBuf::PublishResAck);
+    assert_eq!(b, hex!("e4 4c"));
+    operation_of(&b, |op| {
+        assert!(matches!(op, Some(Operation::PublishResAck)))
+    });
+}
+
+#[test]
+fn firewall_round_trip() {
+    let b = write(OperationBuf::FirewalledReqV1 { tcp_port: 4662 });
+    assert_eq!(b, hex!("e4 50 3612"));
+    operation_of(&b, |op| match op {
+        Some(Operation::FirewalledReqV1(r)) => assert_eq!(r.tcp_port(), 4662),
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let b = write(OperationBuf::Firewalled2ReqV1 {
+        tcp_port: 4662,
+        user_hash: [2; 16],
+        connect_options: 0x07,
+    });
+    assert_eq!(b, hex!("e4 53 3612 02020202020202020202020202020202 07"));
+    operation_of(&b, |op| match op {
+        Some(Operation::Firewalled2ReqV1(r)) => {
+            assert_eq!(r.tcp_port(), 4662);
+            assert_eq!(r.user_hash(), [2; 16]);
+            assert_eq!(r.connect_options(), 0x07);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let b = write(OperationBuf::FirewalledResV1 {
+        ip_addr: [1, 2, 3, 4].into(),
+    });
+    assert_eq!(b, hex!("e4 58 04030201"));
+    operation_of(&b, |op| match op {
+        Some(Operation::FirewalledResV1(r)) => {
+            assert_eq!(r.ip_addr(), std::net::Ipv4Addr::new(1, 2, 3, 4))
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let b = write(OperationBuf::FirewalledAckResV1);
+    assert_eq!(b, hex!("e4 59"));
+    operation_of(&b, |op| {
+        assert!(matches!(op, Some(Operation::FirewalledAckResV1)))
+    });
+
+    let b = write(OperationBuf::FirewallUdp {
+        error_code: 0,
+        incoming_port: 4672,
+    });
+    assert_eq!(b, hex!("e4 62 00 4012"));
+    operation_of(&b, |op| match op {
+        Some(Operation::FirewallUdp(r)) => {
+            assert_eq!(r.error_code(), 0);
+            assert_eq!(r.incoming_port(), 4672);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    assert!(FirewalledRes::from_slice(&hex!("040302")).is_err());
+}
+
+#[test]
+fn buddy_round_trip() {
+    let b = write(OperationBuf::FindBuddyReqV1 {
+        buddy_id: !KadId::from(5),
+        src_client_hash: [2; 16],
+        src_client_port: 4662,
+        connect_options: None,
+    });
+    operation_of(&b, |op| match op {
+        Some(Operation::FindBuddyReqV1(r)) => {
+            assert_eq!(r.requester_id(), KadId::from(5));
+            assert_eq!(r.client_hash(), [2; 16]);
+            assert_eq!(r.client_port(), 4662);
+            assert_eq!(r.connect_options(), None);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let b = write(OperationBuf::FindBuddyResV1 {
+        buddy_id: !KadId::from(5),
+        src_client_hash: [3; 16],
+        src_client_port: 4663,
+        connect_options: Some(0x01),
+    });
+    assert_eq!(
+        b,
+        hex!("e4 5a fffffffffffffffffffffffffaffffff 03030303030303030303030303030303 3712 01")
+    );
+    operation_of(&b, |op| match op {
+        Some(Operation::FindBuddyResV1(r)) => {
+            assert_eq!(r.buddy_id(), !KadId::from(5));
+            assert_eq!(r.client_hash(), [3; 16]);
+            assert_eq!(r.client_port(), 4663);
+            assert_eq!(r.connect_options(), Some(0x01));
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let b = write(OperationBuf::CallbackReqV1 {
+        buddy_id: KadId::from(1),
+        file_id: KadId::from(2),
+        tcp_port: 4662,
+    });
+    assert_eq!(
+        b,
+        hex!("e4 52 00000000000000000000000001000000 00000000000000000000000002000000 3612")
+    );
+    operation_of(&b, |op| match op {
+        Some(Operation::CallbackReqV1(r)) => {
+            assert_eq!(r.buddy_id(), KadId::from(1));
+            assert_eq!(r.file_id(), KadId::from(2));
+            assert_eq!(r.tcp_port(), 4662);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    assert!(FindBuddy::from_slice(&b[2..(b.len() - 1)]).is_err());
+}
+
+#[test]
+fn ping_pong_round_trip() {
+    let b = write(OperationBuf::Ping);
+    assert_eq!(b, hex!("e4 60"));
+    operation_of(&b, |op| assert!(matches!(op, Some(Operation::Ping))));
+
+    let b = write(OperationBuf::Pong { recv_port: 4672 });
+    operation_of(&b, |op| match op {
+        Some(Operation::Pong(p)) => assert_eq!(p.recv_port(), 4672),
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    assert!(Pong::from_slice(&hex!("40")).is_err());
+}
+
+#[test]
+fn legacy_bootstrap() {
+    let contact = || ContactBuf {
+        client_id: KadId::from(1),
+        ip_addr: [1, 2, 3, 4].into(),
+        udp_port: 4672,
+        tcp_port: 4662,
+        version: 0,
+    };
+    let b = write(OperationBuf::BootstrapReqV0(contact()));
+    assert_eq!(
+        b,
+        hex!("e4 00 00000000000000000000000001000000 04030201 4012 3612 00")
+    );
+
+    let p = Packet::from_slice(&b).unwrap();
+    let Kind::Kad(k) = p.kind().unwrap() else {
+        panic!("expected a kad packet");
+    };
+    assert_eq!(k.version(), Some(KadVersion::V0));
+    assert!(k.opcode().unwrap().is_legacy());
+    match k.operation() {
+        Some(Operation::BootstrapReqV0(c)) => {
+            assert_eq!(c.client_id(), KadId::from(1));
+            assert_eq!(c.ip_addr(), std::net::Ipv4Addr::new(1, 2, 3, 4));
+            assert_eq!(c.udp_port(), 4672);
+            assert_eq!(c.tcp_port(), 4662);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    }
+
+    let mut v = hex!("e4 08 0200").to_vec();
+    contact().write_to(&mut v).unwrap();
+    ContactBuf {
+        client_id: KadId::from(2),
+        ..contact()
+    }
+    .write_to(&mut v)
+    .unwrap();
+    operation_of(&v, |op| match op {
+        Some(Operation::BootstrapResV0(r)) => {
+            let c: Vec<_> = r.contacts().unwrap().map(|c| c.client_id()).collect();
+            assert_eq!(c, vec![KadId::from(1), KadId::from(2)]);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+    assert!(BootstrapResV0::from_slice(&v[2..(v.len() - 1)]).is_err());
+}
+
+#[test]
+fn legacy_versions() {
+    assert_eq!(KadOpCode::HelloReqV0.version(), KadVersion::V0);
+    assert_eq!(KadOpCode::SearchReqV1.version(), KadVersion::V1);
+    assert_eq!(KadOpCode::FindBuddyReqV1.version(), KadVersion::V1);
+    assert_eq!(KadOpCode::HelloReq.version(), KadVersion::V2);
+    assert!(KadOpCode::SearchReqV1.is_legacy());
+    assert!(!KadOpCode::FindBuddyReqV1.is_legacy());
+    assert!(!KadOpCode::HelloReq.is_legacy());
+}
+
+#[test]
+fn legacy_res_and_search() {
+    let mut v = hex!("e4 28 00000000000000000000000001000000 02").to_vec();
+    for c in contacts(2) {
+        c.write_to(&mut v).unwrap();
+    }
+    operation_of(&v, |op| match op {
+        Some(Operation::ResV0(r)) => {
+            assert_eq!(r.target(), KadId::from(1));
+            let c: Vec<_> = r.contacts().map(|c| c.client_id()).collect();
+            assert_eq!(c, vec![KadId::from(0), KadId::from(1)]);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    // source search
+    let v = hex!("e4 30 00000000000000000000000001000000 01");
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchReqV1(r)) => {
+            assert_eq!(r.target(), KadId::from(1));
+            assert!(r.is_source_search());
+            assert_eq!(r.expr(), None);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    // keyword search
+    let v = hex!("e4 30 00000000000000000000000001000000 01 01 0300 666f6f");
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchReqV1(r)) => {
+            assert!(!r.is_source_search());
+            assert_eq!(r.expr(), Some(SearchExpr::String(b"foo")));
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let v = hex!(
+        "e4 38 00000000000000000000000001000000 0100"
+        "00000000000000000000000002000000 01 09 0100 15 03"
+    );
+    operation_of(&v, |op| match op {
+        Some(Operation::SearchResV1(r)) => {
+            assert_eq!(r.target_id(), KadId::from(1));
+            let (results, _) = r.results().unwrap();
+            let s: Vec<_> = results.map(|r| (r.id(), r.sources())).collect();
+            assert_eq!(s, vec![(KadId::from(2), Some(3))]);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    let v = hex!("e4 48 00000000000000000000000001000000");
+    operation_of(&v, |op| match op {
+        Some(Operation::PublishResV1(r)) => {
+            assert_eq!(r.target_id(), KadId::from(1));
+            assert_eq!(r.load(), None);
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    });
+}
+
+#[test]
+fn res_truncated_and_trailing() {
+    let b = write(OperationBuf::Res {
+        target: KadId::from(1),
+        contacts: contacts(2),
+    });
+
+    // the second contact is cut short
+    assert!(matches!(
+        Res::from_slice(&b[2..(b.len() - 3)]),
+        Err(Error::ResContactTooShort {
+            offset: 42,
+            have: 22,
+            need: 25
+        })
+    ));
+
+    // bytes past the advertised contacts are ignored
+    let mut v = b.clone();
+    v.extend([1, 2, 3]);
+    operation_of(&v, |op| match op {
+        Some(Operation::Res(r)) => assert_eq!(r.contacts().count(), 2),
+        o => panic!("unexpected operation: {:?}", o),
+    });
+
+    assert!(matches!(
+        BootstrapRespContact::from_slice(&b[19..40]),
+        Err(Error::BootstrapRespContactTooShort { have: 21, .. })
+    ));
+
+    // offsets count from the start of the operation payload
+    let b = write(OperationBuf::BootstrapResp {
+        client_id: KadId::from(5),
+        client_port: 4672,
+        client_version: 9,
+        contacts: contacts(2),
+    });
+    let r = BootstrapResp::from_slice(&b[2..(b.len() - 3)]).unwrap();
+    assert!(matches!(
+        r.contacts(),
+        Err(Error::BootstrapRespContactTooShort {
+            offset: 46,
+            have: 22,
+            need: 25
+        })
+    ));
+}
+
+fn emule_operation_of(op: EmuleOperationBuf, f: impl FnOnce(Option<EmuleOperation<'_>>)) {
+    let mut v = Vec::new();
+    op.write_to(&mut v).unwrap();
+    let p = Packet::from_slice(&v).unwrap();
+    assert_eq!(p.udp_proto(), Some(UdpProto::Emule));
+    let Kind::Emule(e) = p.kind().unwrap() else {
+        panic!("expected an emule packet");
+    };
+    f(e.operation())
+}
+
+#[test]
+fn parse_reask_file_ping() {
+    // udp version 4, 10 parts
+    let v = hex!("c5 90 0f0e0d0c0b0a09080706050403020100 0a00 0502 0700");
+    let p = Packet::from_slice(&v).unwrap();
+    let Kind::Emule(e) = p.kind().unwrap() else {
+        panic!("expected an emule packet");
+    };
+    match e.operation() {
+        Some(EmuleOperation::ReAskFilePing(r)) => {
+            assert_eq!(r.file_hash(), hex!("0f0e0d0c0b0a09080706050403020100"));
+            let x = r.extended(4).unwrap();
+            let parts = x.part_status.unwrap();
+            assert_eq!(parts.part_count(), 10);
+            let have: Vec<_> = (0..12).filter(|&i| parts.has_part(i)).collect();
+            assert_eq!(have, vec![0, 2, 9]);
+            assert_eq!(x.complete_sources, Some(7));
+
+            // udp version 3 has only the complete sources
+            let x = r.extended(3).unwrap();
+            assert!(x.part_status.is_none());
+            assert_eq!(x.complete_sources, Some(10));
+        }
+        o => panic!("unexpected operation: {:?}", o),
+    }
+
+    // truncated bitfield
+    let v = hex!("c5 90 0f0e0d0c0b0a09080706050403020100 1100 ff");
+    let p = Packet::from_slice(&v).unwrap();
+    let Kind::Emule(e) = p.kind().unwrap() else {
+        panic!("expected an emule packet");
+    };
+    match e.operation() {
+        Some(EmuleOperation::ReAskFilePing(r)) => assert!(r.extended(4).is_err()),
+        o => panic!("unexpected operation: {:?}", o),
+    }
+}
+
+#[test]
+fn emule_round_trip() {
+    let parts = vec![
+        true, false, true, true, false, false, false, false, false, true,
+    ];
+    emule_operation_of(
+        EmuleOperationBuf::ReAskFilePing {
+            file_hash: [5; 16],
+            part_status: Some(parts.clone()),
+            complete_sources: Some(3),
+        },
+        |op| match op {
+            Some(EmuleOperation::ReAskFilePing(r)) => {
+                assert_eq!(r.file_hash(), [5; 16]);
+                let x = r.extended(4).unwrap();
+                let p = x.part_status.unwrap();
+                let have: Vec<_> = (0..10).map(|i| p.has_part(i)).collect();
+                assert_eq!(have, parts);
+                assert_eq!(x.complete_sources, Some(3));
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    emule_operation_of(
+        EmuleOperationBuf::ReAskAck {
+            part_status: Some(vec![]),
+            queue_rank: 42,
+        },
+        |op| match op {
+            Some(EmuleOperation::ReAskAck(r)) => {
+                assert_eq!(r.queue_rank(), 42);
+                assert!(r.part_status().unwrap().unwrap().is_complete());
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    emule_operation_of(
+        EmuleOperationBuf::ReAskAck {
+            part_status: None,
+            queue_rank: 7,
+        },
+        |op| match op {
+            Some(EmuleOperation::ReAskAck(r)) => {
+                assert_eq!(r.queue_rank(), 7);
+                assert!(r.part_status().unwrap().is_none());
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    emule_operation_of(EmuleOperationBuf::FileNotFound, |op| {
+        assert!(matches!(op, Some(EmuleOperation::FileNotFound)))
+    });
+    emule_operation_of(EmuleOperationBuf::QueueFull, |op| {
+        assert!(matches!(op, Some(EmuleOperation::QueueFull)))
+    });
+
+    emule_operation_of(
+        EmuleOperationBuf::ReAskCallBackUdp {
+            buddy_id: KadId::from(1),
+            file_hash: [2; 16],
+            part_status: None,
+            complete_sources: Some(9),
+        },
+        |op| match op {
+            Some(EmuleOperation::ReAskCallBackUdp(r)) => {
+                assert_eq!(r.buddy_id(), KadId::from(1));
+                assert_eq!(r.reask().file_hash(), [2; 16]);
+                assert_eq!(r.reask().extended(3).unwrap().complete_sources, Some(9));
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    emule_operation_of(
+        EmuleOperationBuf::DirectCallbackReq {
+            tcp_port: 4662,
+            user_hash: [0xab; 16],
+            connect_options: 0x0d,
+        },
+        |op| match op {
+            Some(EmuleOperation::DirectCallbackReq(r)) => {
+                assert_eq!(r.tcp_port(), 4662);
+                assert_eq!(r.user_hash(), [0xab; 16]);
+                assert_eq!(r.connect_options(), 0x0d);
+            }
+            o => panic!("unexpected operation: {:?}", o),
+        },
+    );
+
+    emule_operation_of(EmuleOperationBuf::PortTest { value: 0x12 }, |op| {
+        assert!(matches!(op, Some(EmuleOperation::PortTest { value: 0x12 })))
+    });
+}
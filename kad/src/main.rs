@@ -4,9 +4,9 @@ use async_std::stream;
 use async_std::sync::Mutex;
 use async_std::task;
 use clap::{Arg, Command};
-use core::fmt;
 use emule_proto as remule;
 use fmt_extra::Hs;
+use remule::KadId;
 use std::collections::hash_map;
 use std::collections::HashMap;
 use std::ffi::OsString;
@@ -15,23 +15,6 @@ use std::io::Read;
 use std::sync::Arc;
 use std::time::Duration;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-struct KadId {
-    inner: u128,
-}
-
-impl fmt::Display for KadId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.inner)
-    }
-}
-
-impl From<u128> for KadId {
-    fn from(v: u128) -> Self {
-        KadId { inner: v }
-    }
-}
-
 #[derive(Debug)]
 struct Peer {
     // XXX: maybe just use an array of bytes here?
@@ -43,7 +26,7 @@ struct Peer {
 impl From<remule::nodes::Contact> for Peer {
     fn from(c: remule::nodes::Contact) -> Self {
         Peer {
-            _id: Some(From::from(c.id)),
+            _id: Some(c.id),
             last_contact: None,
             last_addr: net::SocketAddr::from((c.ip, c.udp_port)),
         }
@@ -68,12 +51,14 @@ struct KadMut {
     // TODO: track peers in buckets by distance from our id
     //buckets: HashMap<u8, Vec<Peer>>,
     //
+    extern_port: remule::extern_port::ExternPortLookup,
 }
 
 impl KadMut {
     fn new() -> Self {
         Self {
             peers: HashMap::default(),
+            extern_port: remule::extern_port::ExternPortLookup::new(),
         }
     }
 }
@@ -83,6 +68,7 @@ impl KadMut {
 struct Tasks {
     _rx_join: task::JoinHandle<()>,
     _bootstrap_join: task::JoinHandle<()>,
+    _extern_port_join: task::JoinHandle<()>,
 }
 
 #[derive(Debug)]
@@ -151,7 +137,56 @@ impl Kad {
             })
         };
 
-        futures::join!(rx_join, bootstrap_join);
+        let extern_port_join = {
+            let kad = self.clone();
+            task::spawn(async move {
+                kad.extern_port_lookup(stream::interval(
+                    remule::extern_port::EXTERNAL_PORT_LOOKUP_INTERVAL,
+                ))
+                .await
+                .unwrap();
+            })
+        };
+
+        futures::join!(rx_join, bootstrap_join, extern_port_join);
+    }
+
+    /// Ping a random peer until enough `Pong`s arrive to determine our external udp port
+    async fn extern_port_lookup(
+        &self,
+        mut timeout: stream::Interval,
+    ) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        loop {
+            let target = {
+                let mut kad_mut = self.shared.kad_mut.lock().unwrap();
+                if !kad_mut.extern_port.is_searching() {
+                    println!(
+                        "extern port lookup done: {:?}",
+                        kad_mut.extern_port.extern_port()
+                    );
+                    return Ok(());
+                }
+
+                if kad_mut.peers.is_empty() {
+                    None
+                } else {
+                    let idx = rand::random_range(0..kad_mut.peers.len());
+                    let target = kad_mut.peers.values().nth(idx).map(|p| p.last_addr);
+                    if let Some(target) = target {
+                        kad_mut.extern_port.add_ping(target.ip());
+                    }
+                    target
+                }
+            };
+
+            if let Some(target) = target {
+                let mut out_buf = Vec::new();
+                remule::udp_proto::OperationBuf::Ping.write_to(&mut out_buf)?;
+                self.shared.socket.send_to(&out_buf[..], target).await?;
+            }
+
+            timeout.next().await;
+        }
     }
 
     async fn bootstrap(
@@ -196,7 +231,7 @@ impl Kad {
     ) -> Result<(), Box<dyn std::error::Error + 'static>> {
         let mut kad_mut = self.shared.kad_mut.lock().unwrap();
 
-        let peer_id = KadId::from(bootstrap_resp.client_id());
+        let peer_id = bootstrap_resp.client_id();
 
         let reported_port = bootstrap_resp.client_port();
         if reported_port != rx_addr.port() {
@@ -230,7 +265,7 @@ impl Kad {
 
         // track packet reported peers
         for bs_node in bootstrap_resp.contacts()? {
-            let bs_node_id = KadId::from(bs_node.client_id());
+            let bs_node_id = bs_node.client_id();
 
             match kad_mut.peers.entry(bs_node_id) {
                 hash_map::Entry::Occupied(mut occupied) => {
@@ -260,7 +295,27 @@ impl Kad {
         Ok(())
     }
 
-    fn handle_packet(
+    async fn handle_ping(
+        &self,
+        rx_addr: net::SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let mut out_buf = Vec::new();
+        remule::udp_proto::OperationBuf::Pong {
+            recv_port: rx_addr.port(),
+        }
+        .write_to(&mut out_buf)?;
+        self.shared.socket.send_to(&out_buf[..], rx_addr).await?;
+        Ok(())
+    }
+
+    fn handle_pong(&self, rx_addr: net::SocketAddr, pong: remule::udp_proto::Pong<'_>) {
+        let mut kad_mut = self.shared.kad_mut.lock().unwrap();
+        if let Some(port) = kad_mut.extern_port.add_pong(rx_addr.ip(), pong.recv_port()) {
+            println!("{}: extern port is {}", rx_addr, port);
+        }
+    }
+
+    async fn handle_packet(
         &self,
         ts: std::time::Instant,
         rx_addr: net::SocketAddr,
@@ -274,11 +329,24 @@ impl Kad {
                 Some(remule::udp_proto::Operation::BootstrapResp(bootstrap_resp)) => {
                     self.handle_bootstrap_resp(ts, rx_addr, bootstrap_resp)
                 }
+                Some(remule::udp_proto::Operation::Ping) => self.handle_ping(rx_addr).await,
+                Some(remule::udp_proto::Operation::Pong(pong)) => {
+                    self.handle_pong(rx_addr, pong);
+                    Ok(())
+                }
                 kad_operation => {
                     println!("unhandled kad op: {:?}", kad_operation);
                     Ok(())
                 }
             },
+            remule::udp_proto::Kind::Emule(emule_packet) => {
+                println!("unhandled emule op: {:?}", emule_packet.operation());
+                Ok(())
+            }
+            remule::udp_proto::Kind::Server(server_packet) => {
+                println!("unhandled server op: {:?}", server_packet.operation());
+                Ok(())
+            }
         }
     }
 
@@ -292,7 +360,7 @@ impl Kad {
             let ts = std::time::Instant::now();
             let rx_data = &rx_buf[..recv];
 
-            if let Err(e) = self.handle_packet(ts, rx_addr, rx_data) {
+            if let Err(e) = self.handle_packet(ts, rx_addr, rx_data).await {
                 println!("{}: error handling packet: {}", rx_addr, e);
             }
         }
@@ -308,6 +376,7 @@ impl Kad {
     //  - find a buddy every 20 minutes
     //  - determine our external port from a contact ever 15 seconds
     //    - (by sending a Null packet to a random contact)
+    //    - see `extern_port_lookup()`
     //  - some "big timer" that runs every 10 seconds & every 1 hour per "zone"
     //  - small timer every 1 minute per "zone"
     //  - search jumpstart every X seconds
@@ -357,7 +426,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
             let mut b = Vec::default();
             f_nodes.read_to_end(&mut b)?;
             bs_nodes.extend(
-                remule::nodes::parse(&mut b)?
+                remule::nodes::parse(&b)?
                     .contacts
                     .into_iter()
                     .map(From::from),
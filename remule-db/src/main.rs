@@ -1,14 +1,43 @@
 use clap::{Arg, Command};
 use emule_proto as remule;
+use remule::udp_proto::{DecodeContext, Dissection, Keys, Kind, Packet};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::ffi::OsString;
 use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let matches = Command::new("remule-db")
         .subcommand(Command::new("known2").arg(Arg::new("known2-dat").required(true).index(1)))
         .subcommand(Command::new("clients").arg(Arg::new("clients-met").required(true).index(1)))
         .subcommand(Command::new("nodes").arg(Arg::new("nodes-dat").required(true).index(1)))
+        .subcommand(
+            Command::new("dissect")
+                .about("decode the emule udp traffic in a pcap or pcapng capture")
+                .arg(
+                    Arg::new("pcap")
+                        .required(true)
+                        .index(1)
+                        .value_parser(clap::value_parser!(OsString)),
+                )
+                .arg(
+                    Arg::new("kad-id")
+                        .long("kad-id")
+                        .help("kad id of the capturing node, as emule displays it, to deobfuscate packets it received"),
+                )
+                .arg(
+                    Arg::new("user-hash")
+                        .long("user-hash")
+                        .help("user hash of the capturing node, in hex, to deobfuscate packets it received"),
+                )
+                .arg(
+                    Arg::new("verify-key")
+                        .long("verify-key")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("udp verify key the capturing node handed out"),
+                ),
+        )
         .get_matches();
 
     match matches.subcommand() {
@@ -18,7 +47,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     Ok(mut h) => {
                         let mut b = Vec::default();
                         h.read_to_end(&mut b)?;
-                        println!("{:?}", remule::known2::parse(&mut b));
+                        println!("{:?}", remule::known2::parse(&b));
                     }
                     Err(e) => {
                         eprintln!("error: could not open {:?}: {:?}", f, e);
@@ -32,7 +61,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     Ok(mut h) => {
                         let mut b = Vec::default();
                         h.read_to_end(&mut b)?;
-                        println!("{:?}", remule::clientcredit::parse(&mut b));
+                        println!("{:?}", remule::clientcredit::parse(&b));
                     }
                     Err(e) => {
                         eprintln!("error: could not open {:?}: {:?}", f, e);
@@ -46,7 +75,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     Ok(mut h) => {
                         let mut b = Vec::default();
                         h.read_to_end(&mut b)?;
-                        let nodes = remule::nodes::parse(&mut b)?;
+                        let nodes = remule::nodes::parse(&b)?;
 
                         println!("{}", serde_json::to_string(&nodes)?);
                     }
@@ -56,6 +85,39 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
             }
         }
+        Some(("dissect", submatches)) => {
+            let kad_id = submatches
+                .get_one::<String>("kad-id")
+                .map(|v| v.parse::<remule::KadId>())
+                .transpose()?;
+            let user_hash = submatches
+                .get_one::<String>("user-hash")
+                .map(|v| parse_hash(v))
+                .transpose()?;
+            let verify_key = submatches.get_one::<u32>("verify-key").copied();
+            let keys = if kad_id.is_some() || user_hash.is_some() || verify_key.is_some() {
+                Some((
+                    kad_id.unwrap_or_default(),
+                    user_hash.unwrap_or_default(),
+                    verify_key,
+                ))
+            } else {
+                None
+            };
+
+            for f in submatches.get_many::<OsString>("pcap").unwrap() {
+                match std::fs::File::open(f) {
+                    Ok(mut h) => {
+                        let mut b = Vec::default();
+                        h.read_to_end(&mut b)?;
+                        dissect(&b, keys.as_ref())?;
+                    }
+                    Err(e) => {
+                        eprintln!("error: could not open {:?}: {:?}", f, e);
+                    }
+                }
+            }
+        }
         Some((subname, _)) => {
             Err(format!("unknown subcommand {:?}", subname))?;
         }
@@ -66,3 +128,96 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+fn parse_hash(v: &str) -> Result<[u8; 16], Box<dyn Error>> {
+    if v.len() != 32 || !v.is_ascii() {
+        Err(format!("expected 32 hex digits, got {:?}", v))?;
+    }
+
+    let mut h = [0u8; 16];
+    for (i, b) in h.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&v[(i * 2)..(i * 2 + 2)], 16)?;
+    }
+    Ok(h)
+}
+
+/// Print each decoded packet in `capture` as a line of json, followed by a count of errors
+///
+/// Only packets sent to the capturing node can be deobfuscated, as the ones it sent were
+/// obfuscated with the remote's keys.
+fn dissect(
+    capture: &[u8],
+    keys: Option<&(remule::KadId, [u8; 16], Option<u32>)>,
+) -> Result<(), Box<dyn Error>> {
+    let mut ctx = DecodeContext::new();
+    let mut errors = BTreeMap::<&'static str, u64>::new();
+    let mut count = |variant: &'static str| {
+        *errors.entry(variant).or_default() += 1;
+    };
+
+    let mut packets = 0u64;
+    for d in remule::pcap::datagrams(capture)? {
+        // the rest of the capture can't be read, but what was read is still summarized
+        let d = match d {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                count(e.variant_name());
+                break;
+            }
+        };
+        packets += 1;
+        let mut packet = match Packet::from_slice(d.payload) {
+            Ok(v) => v,
+            Err(e) => {
+                count(e.variant_name());
+                continue;
+            }
+        };
+
+        let decrypted = match keys {
+            Some((kad_id, user_hash, source_key)) => packet.decrypt(&Keys {
+                kad_id: *kad_id,
+                user_hash,
+                source_ip: match d.src.ip() {
+                    IpAddr::V4(ip) => ip,
+                    IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+                },
+                source_key: *source_key,
+            }),
+            None => Ok(None),
+        };
+
+        // only the first error for each packet is counted
+        let kind = ctx.kind(&packet);
+        let op = match &kind {
+            Ok(Kind::Kad(k)) => k.try_operation().err(),
+            Ok(Kind::Emule(e)) => e.try_operation().err(),
+            Ok(Kind::Server(s)) => s.try_operation().err(),
+            Err(_) => None,
+        };
+        if let Some(e) = decrypted
+            .as_ref()
+            .err()
+            .or(kind.as_ref().err())
+            .or(op.as_ref())
+        {
+            count(e.variant_name());
+        }
+
+        println!(
+            "{}.{:06} {} -> {} {}",
+            d.timestamp.as_secs(),
+            d.timestamp.subsec_micros(),
+            d.src,
+            d.dst,
+            serde_json::to_string(&Dissection::new(&packet, kind))?
+        );
+    }
+
+    eprintln!("{} udp packets", packets);
+    for (variant, n) in errors {
+        eprintln!("{:>8} {}", n, variant);
+    }
+    Ok(())
+}